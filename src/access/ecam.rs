@@ -0,0 +1,177 @@
+use crate::{access::Mmio, ConfigRegionAccess, PciAddress};
+use core::ops::RangeInclusive;
+
+/// Accesses the configuration space through the PCIe Enhanced Configuration Access Mechanism (ECAM). Each
+/// function has a 4KiB region of configuration space, mapped at:
+/// ```ignore
+///     base + (bus << 20 | device << 15 | function << 12 | offset)
+/// ```
+///
+/// One `EcamAccess` covers a single segment, and only the buses within the range it was created with. This
+/// matches the entries of the ACPI `MCFG` table: `base` is the address reported for the segment (the address at
+/// which bus `0` would be mapped), even if the bus range does not start at `0`.
+pub struct EcamAccess<M: Mmio> {
+    base: usize,
+    segment: u16,
+    bus_start: u8,
+    bus_end: u8,
+    mmio: M,
+}
+
+impl<M: Mmio> EcamAccess<M> {
+    /// Create a new `EcamAccess`.
+    ///
+    /// # Safety
+    /// The configuration space of every bus in `buses` must be accessible through `mmio` at the addresses given
+    /// above.
+    pub unsafe fn new(base: usize, segment: u16, buses: RangeInclusive<u8>, mmio: M) -> EcamAccess<M> {
+        EcamAccess { base, segment, bus_start: *buses.start(), bus_end: *buses.end(), mmio }
+    }
+
+    pub fn segment(&self) -> u16 {
+        self.segment
+    }
+
+    pub fn bus_range(&self) -> RangeInclusive<u8> {
+        self.bus_start..=self.bus_end
+    }
+
+    /// Get the address of a register in the configuration space of a function, or `None` if the function is not
+    /// covered by this `EcamAccess` or `offset` isn't the 4-byte aligned offset of a register.
+    fn register_address(&self, address: PciAddress, offset: u16) -> Option<usize> {
+        if address.segment() != self.segment
+            || address.bus() < self.bus_start
+            || address.bus() > self.bus_end
+            || offset >= 0x1000
+            || offset & 0x3 != 0
+        {
+            return None;
+        }
+
+        Some(
+            self.base
+                + ((address.bus() as usize) << 20
+                    | (address.device() as usize) << 15
+                    | (address.function() as usize) << 12
+                    | offset as usize),
+        )
+    }
+}
+
+impl<M: Mmio> ConfigRegionAccess for EcamAccess<M> {
+    fn function_exists(&self, address: PciAddress) -> bool {
+        /*
+         * Functions that don't exist return all ones, so a Vendor ID of `0xffff` means that there is nothing
+         * there.
+         */
+        self.register_address(address, 0x00).is_some() && unsafe { self.read(address, 0x00) } & 0xffff != 0xffff
+    }
+
+    /// Read a register. Reads from functions outside of this `EcamAccess`'s segment and bus range, and reads from
+    /// unaligned offsets, return all ones, like reads from a function that doesn't exist.
+    unsafe fn read(&self, address: PciAddress, offset: u16) -> u32 {
+        match self.register_address(address, offset) {
+            Some(register) => unsafe { self.mmio.read32(register) },
+            None => 0xffffffff,
+        }
+    }
+
+    /// Write a register. Writes to functions outside of this `EcamAccess`'s segment and bus range, and writes to
+    /// unaligned offsets, are ignored.
+    unsafe fn write(&self, address: PciAddress, offset: u16, value: u32) {
+        if let Some(register) = self.register_address(address, offset) {
+            unsafe { self.mmio.write32(register, value) }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use core::cell::RefCell;
+    use std::{vec, vec::Vec};
+
+    const BASE: usize = 0x8000_0000;
+
+    /// An `Mmio` backed by a buffer covering the configuration space of buses `1` and `2`.
+    struct BufferMmio {
+        buffer: RefCell<Vec<u32>>,
+    }
+
+    impl BufferMmio {
+        fn new() -> BufferMmio {
+            BufferMmio { buffer: RefCell::new(vec![0xffffffff; (2 << 20) / 4]) }
+        }
+
+        fn index(address: usize) -> usize {
+            (address - BASE - (1 << 20)) / 4
+        }
+    }
+
+    impl Mmio for &BufferMmio {
+        unsafe fn read32(&self, address: usize) -> u32 {
+            assert_eq!(address & 0x3, 0);
+            self.buffer.borrow()[BufferMmio::index(address)]
+        }
+
+        unsafe fn write32(&self, address: usize, value: u32) {
+            assert_eq!(address & 0x3, 0);
+            self.buffer.borrow_mut()[BufferMmio::index(address)] = value;
+        }
+    }
+
+    #[test]
+    fn address_layout() {
+        let mmio = BufferMmio::new();
+        let ecam = unsafe { EcamAccess::new(BASE, 0, 1..=2, &mmio) };
+        let address = PciAddress::new(0, 2, 3, 4);
+
+        unsafe { ecam.write(address, 0x10, 0xdeadbeef) };
+        let index = ((2 << 20 | 3 << 15 | 4 << 12 | 0x10) - (1 << 20)) / 4;
+        assert_eq!(mmio.buffer.borrow()[index], 0xdeadbeef);
+        assert_eq!(unsafe { ecam.read(address, 0x10) }, 0xdeadbeef);
+
+        unsafe { ecam.write(address, 0xffc, 0x1234) };
+        assert_eq!(mmio.buffer.borrow()[index - 0x10 / 4 + 0xffc / 4], 0x1234);
+    }
+
+    #[test]
+    fn function_exists() {
+        let mmio = BufferMmio::new();
+        let ecam = unsafe { EcamAccess::new(BASE, 0, 1..=2, &mmio) };
+        let address = PciAddress::new(0, 1, 0, 0);
+
+        assert!(!ecam.function_exists(address));
+        unsafe { ecam.write(address, 0x00, 0x1234_8086) };
+        assert!(ecam.function_exists(address));
+    }
+
+    #[test]
+    fn rejects_outside_bus_range_and_segment() {
+        let mmio = BufferMmio::new();
+        let ecam = unsafe { EcamAccess::new(BASE, 1, 1..=2, &mmio) };
+
+        for address in [PciAddress::new(1, 0, 0, 0), PciAddress::new(1, 3, 0, 0), PciAddress::new(0, 1, 0, 0)] {
+            assert!(!ecam.function_exists(address));
+            unsafe { ecam.write(address, 0x00, 0) };
+            assert_eq!(unsafe { ecam.read(address, 0x00) }, 0xffffffff);
+        }
+        assert!(mmio.buffer.borrow().iter().all(|&value| value == 0xffffffff));
+    }
+
+    #[test]
+    fn rejects_unaligned_and_out_of_range_offsets() {
+        let mmio = BufferMmio::new();
+        let ecam = unsafe { EcamAccess::new(BASE, 0, 1..=2, &mmio) };
+        let address = PciAddress::new(0, 1, 0, 0);
+
+        unsafe {
+            ecam.write(address, 0x02, 0);
+            ecam.write(address, 0x1000, 0);
+        }
+        assert!(mmio.buffer.borrow().iter().all(|&value| value == 0xffffffff));
+        assert_eq!(unsafe { ecam.read(address, 0x02) }, 0xffffffff);
+    }
+}
@@ -0,0 +1,190 @@
+use crate::{access::PortIo, ConfigRegionAccess, PciAddress};
+use bit_field::BitField;
+use core::{
+    hint,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+const CONFIG_ADDRESS: u16 = 0xcf8;
+const CONFIG_DATA: u16 = 0xcfc;
+
+/// Accesses the configuration space through PCI Configuration Mechanism #1, which uses the `0xcf8` address port
+/// and the `0xcfc` data port. This is available on x86 systems that don't support ECAM, but can only reach the
+/// first 256 bytes of each function's configuration space, and only on segment `0`.
+///
+/// The address is written to `0xcf8`:
+/// ```ignore
+/// 32   31        24        16          11         8                 0
+///  +---+---------+---------+-----------+----------+-----------------+
+///  | E | Reserved|   bus   |  device   | function |     offset      |
+///  +---+---------+---------+-----------+----------+-----------------+
+/// ```
+/// and then the register is read or written through `0xcfc`. As the two ports are shared, the sequence is done
+/// under a lock.
+pub struct LegacyPortAccess<P: PortIo> {
+    port_io: P,
+    lock: AtomicBool,
+}
+
+impl<P: PortIo> LegacyPortAccess<P> {
+    /// Create a new `LegacyPortAccess`.
+    ///
+    /// # Safety
+    /// Nothing else may use ports `0xcf8` and `0xcfc` while this `LegacyPortAccess` exists.
+    pub unsafe fn new(port_io: P) -> LegacyPortAccess<P> {
+        LegacyPortAccess { port_io, lock: AtomicBool::new(false) }
+    }
+
+    /// Get the value to write to `0xcf8` to access a register, or `None` if the register can't be reached
+    /// through this mechanism or `offset` isn't the 4-byte aligned offset of a register.
+    fn config_address(address: PciAddress, offset: u16) -> Option<u32> {
+        if address.segment() != 0 || offset > 0xff || offset & 0x3 != 0 {
+            return None;
+        }
+
+        let mut result = 0;
+        result.set_bits(2..8, (offset >> 2) as u32);
+        result.set_bits(8..11, address.function() as u32);
+        result.set_bits(11..16, address.device() as u32);
+        result.set_bits(16..24, address.bus() as u32);
+        result.set_bit(31, true);
+        Some(result)
+    }
+
+    fn with_lock<R>(&self, f: impl FnOnce() -> R) -> R {
+        while self.lock.compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed).is_err() {
+            hint::spin_loop();
+        }
+        let result = f();
+        self.lock.store(false, Ordering::Release);
+        result
+    }
+}
+
+impl<P: PortIo> ConfigRegionAccess for LegacyPortAccess<P> {
+    fn function_exists(&self, address: PciAddress) -> bool {
+        Self::config_address(address, 0x00).is_some() && unsafe { self.read(address, 0x00) } & 0xffff != 0xffff
+    }
+
+    /// Read a register. Reads from a non-zero segment, or from an offset above `0xff`, cannot be done through
+    /// this mechanism and so return all ones, as do reads from unaligned offsets.
+    unsafe fn read(&self, address: PciAddress, offset: u16) -> u32 {
+        match Self::config_address(address, offset) {
+            Some(config_address) => self.with_lock(|| unsafe {
+                self.port_io.write32(CONFIG_ADDRESS, config_address);
+                self.port_io.read32(CONFIG_DATA)
+            }),
+            None => 0xffffffff,
+        }
+    }
+
+    /// Write a register. Writes to a non-zero segment, or to an offset above `0xff`, cannot be done through this
+    /// mechanism and so are ignored, as are writes to unaligned offsets.
+    unsafe fn write(&self, address: PciAddress, offset: u16, value: u32) {
+        if let Some(config_address) = Self::config_address(address, offset) {
+            self.with_lock(|| unsafe {
+                self.port_io.write32(CONFIG_ADDRESS, config_address);
+                self.port_io.write32(CONFIG_DATA, value);
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use core::cell::RefCell;
+    use std::{vec, vec::Vec};
+
+    #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+    enum Access {
+        Read(u16),
+        Write(u16, u32),
+    }
+
+    /// A `PortIo` that records every access, and reads back `data` from `0xcfc`.
+    struct RecordingPortIo {
+        accesses: RefCell<Vec<Access>>,
+        data: u32,
+    }
+
+    impl RecordingPortIo {
+        fn new(data: u32) -> RecordingPortIo {
+            RecordingPortIo { accesses: RefCell::new(Vec::new()), data }
+        }
+    }
+
+    impl PortIo for &RecordingPortIo {
+        unsafe fn read32(&self, port: u16) -> u32 {
+            self.accesses.borrow_mut().push(Access::Read(port));
+            self.data
+        }
+
+        unsafe fn write32(&self, port: u16, value: u32) {
+            self.accesses.borrow_mut().push(Access::Write(port, value));
+        }
+    }
+
+    #[test]
+    fn read_sequence() {
+        let ports = RecordingPortIo::new(0x1234_8086);
+        let access = unsafe { LegacyPortAccess::new(&ports) };
+
+        assert_eq!(unsafe { access.read(PciAddress::new(0, 0x12, 0x1f, 0x7), 0xfc) }, 0x1234_8086);
+        assert_eq!(
+            *ports.accesses.borrow(),
+            vec![Access::Write(CONFIG_ADDRESS, 0x8012_fffc), Access::Read(CONFIG_DATA)]
+        );
+    }
+
+    #[test]
+    fn write_sequence() {
+        let ports = RecordingPortIo::new(0);
+        let access = unsafe { LegacyPortAccess::new(&ports) };
+
+        unsafe { access.write(PciAddress::new(0, 0x01, 0x02, 0x3), 0x10, 0xdeadbeef) };
+        assert_eq!(
+            *ports.accesses.borrow(),
+            vec![Access::Write(CONFIG_ADDRESS, 0x8001_1310), Access::Write(CONFIG_DATA, 0xdeadbeef)]
+        );
+    }
+
+    #[test]
+    fn function_exists() {
+        let present = RecordingPortIo::new(0x1234_8086);
+        let absent = RecordingPortIo::new(0xffffffff);
+
+        assert!(unsafe { LegacyPortAccess::new(&present) }.function_exists(PciAddress::new(0, 0, 0, 0)));
+        assert!(!unsafe { LegacyPortAccess::new(&absent) }.function_exists(PciAddress::new(0, 0, 0, 0)));
+    }
+
+    #[test]
+    fn rejects_unreachable_registers() {
+        let ports = RecordingPortIo::new(0);
+        let access = unsafe { LegacyPortAccess::new(&ports) };
+
+        unsafe {
+            assert_eq!(access.read(PciAddress::new(0, 0, 0, 0), 0x100), 0xffffffff);
+            assert_eq!(access.read(PciAddress::new(1, 0, 0, 0), 0x00), 0xffffffff);
+            access.write(PciAddress::new(0, 0, 0, 0), 0x100, 0);
+            access.write(PciAddress::new(1, 0, 0, 0), 0x00, 0);
+        }
+        assert!(!access.function_exists(PciAddress::new(1, 0, 0, 0)));
+        assert!(ports.accesses.borrow().is_empty());
+    }
+
+    #[test]
+    fn rejects_unaligned_offsets() {
+        let ports = RecordingPortIo::new(0);
+        let access = unsafe { LegacyPortAccess::new(&ports) };
+
+        unsafe {
+            assert_eq!(access.read(PciAddress::new(0, 0, 0, 0), 0x02), 0xffffffff);
+            assert_eq!(access.read(PciAddress::new(0, 0, 0, 0), 0xfd), 0xffffffff);
+            access.write(PciAddress::new(0, 0, 0, 0), 0x0e, 0);
+        }
+        assert!(ports.accesses.borrow().is_empty());
+    }
+}
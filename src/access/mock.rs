@@ -0,0 +1,394 @@
+use crate::{capability::Delay, ConfigRegionAccess, DeviceId, HeaderType, PciAddress, VendorId};
+use alloc::{boxed::Box, collections::BTreeMap, vec::Vec};
+use bit_field::BitField;
+use core::cell::RefCell;
+
+const CONFIG_SPACE_DWORDS: usize = 1024;
+
+/// An emulated configuration space, for testing code that uses the crate without real hardware.
+///
+/// Each function has a 4KiB configuration space. Every register has a write mask (which bits can be changed by
+/// writes - all other bits are read-only) and an RW1C mask (bits that are cleared by writing a `1` to them, like
+/// the error bits of the Status register). BARs are emulated by making their address bits writable only down to
+/// the size of the BAR, so writing all ones to a BAR reads back its size mask, as on real hardware.
+///
+/// Reads from functions that don't exist return all ones, and writes to them are ignored.
+#[derive(Default)]
+pub struct MockConfigSpace {
+    functions: RefCell<BTreeMap<PciAddress, Box<MockFunction>>>,
+}
+
+impl MockConfigSpace {
+    pub fn new() -> MockConfigSpace {
+        MockConfigSpace::default()
+    }
+
+    /// Add a function at `address`, replacing any function that is already there.
+    pub fn add_function(&mut self, address: PciAddress, function: MockFunction) -> &mut MockFunction {
+        let functions = self.functions.get_mut();
+        functions.insert(address, Box::new(function));
+        functions.get_mut(&address).unwrap()
+    }
+
+    pub fn remove_function(&mut self, address: PciAddress) -> Option<MockFunction> {
+        self.functions.get_mut().remove(&address).map(|function| *function)
+    }
+
+    pub fn function(&mut self, address: PciAddress) -> Option<&mut MockFunction> {
+        self.functions.get_mut().get_mut(&address).map(|function| &mut **function)
+    }
+}
+
+impl ConfigRegionAccess for MockConfigSpace {
+    fn function_exists(&self, address: PciAddress) -> bool {
+        self.functions.borrow().contains_key(&address)
+    }
+
+    unsafe fn read(&self, address: PciAddress, offset: u16) -> u32 {
+        match self.functions.borrow().get(&address) {
+            Some(function) if offset < 0x1000 => function.get(offset),
+            _ => 0xffffffff,
+        }
+    }
+
+    unsafe fn write(&self, address: PciAddress, offset: u16, value: u32) {
+        if let Some(function) = self.functions.borrow_mut().get_mut(&address) {
+            if offset < 0x1000 {
+                function.write(offset, value);
+            }
+        }
+    }
+}
+
+/// The configuration space of a single function in a [`MockConfigSpace`].
+#[derive(Clone)]
+pub struct MockFunction {
+    registers: [u32; CONFIG_SPACE_DWORDS],
+    write_masks: [u32; CONFIG_SPACE_DWORDS],
+    rw1c_masks: [u32; CONFIG_SPACE_DWORDS],
+}
+
+impl MockFunction {
+    /// Create a function with the given IDs and header type. The header's writable and RW1C bits are set up as
+    /// they would be on a typical device - the Command register, the error bits of the Status register, the
+    /// Interrupt Line and, on bridges, the bus numbers, forwarding windows and Bridge Control register. BARs are
+    /// not implemented until they are added with [`MockFunction::set_memory_bar`] or
+    /// [`MockFunction::set_io_bar`].
+    pub fn new(vendor_id: VendorId, device_id: DeviceId, header_type: HeaderType) -> MockFunction {
+        let mut function = MockFunction {
+            registers: [0; CONFIG_SPACE_DWORDS],
+            write_masks: [0; CONFIG_SPACE_DWORDS],
+            rw1c_masks: [0; CONFIG_SPACE_DWORDS],
+        };
+
+        function.set(0x00, (device_id as u32) << 16 | vendor_id as u32);
+        function.set_write_mask(0x04, 0x0000_07ff);
+        function.set_rw1c_mask(0x04, 0xf900_0000);
+        function.set_write_mask(0x0c, 0x0000_ffff);
+
+        let header_type = match header_type {
+            HeaderType::Endpoint => 0x00,
+            HeaderType::PciPciBridge => 0x01,
+            HeaderType::CardBusBridge => 0x02,
+            HeaderType::Unknown(t) => t,
+        };
+        function.registers[0x0c / 4].set_bits(16..23, header_type as u32);
+
+        match header_type {
+            0x00 => {
+                function.set_write_mask(0x3c, 0x0000_00ff);
+            }
+            0x01 => {
+                // Bus numbers and Secondary Latency Timer
+                function.set_write_mask(0x18, 0xffff_ffff);
+                // I/O base and limit, with 32-bit decoding, and the Secondary Status register
+                function.set(0x1c, 0x0000_0101);
+                function.set_write_mask(0x1c, 0x0000_f0f0);
+                function.set_rw1c_mask(0x1c, 0xf900_0000);
+                function.set_write_mask(0x30, 0xffff_ffff);
+                // Memory base and limit
+                function.set_write_mask(0x20, 0xfff0_fff0);
+                // Prefetchable memory base and limit, with 64-bit decoding
+                function.set(0x24, 0x0001_0001);
+                function.set_write_mask(0x24, 0xfff0_fff0);
+                function.set_write_mask(0x28, 0xffff_ffff);
+                function.set_write_mask(0x2c, 0xffff_ffff);
+                // Interrupt Line and Bridge Control
+                function.set_write_mask(0x3c, 0x0fff_00ff);
+            }
+            0x02 => {
+                function.set_rw1c_mask(0x14, 0xf900_0000);
+                function.set_write_mask(0x18, 0xffff_ffff);
+                function.set_write_mask(0x1c, 0xffff_f000);
+                function.set_write_mask(0x20, 0xffff_f000);
+                function.set_write_mask(0x24, 0xffff_f000);
+                function.set_write_mask(0x28, 0xffff_f000);
+                function.set_write_mask(0x2c, 0xffff_fffc);
+                function.set_write_mask(0x30, 0xffff_fffc);
+                function.set_write_mask(0x34, 0xffff_fffc);
+                function.set_write_mask(0x38, 0xffff_fffc);
+                function.set_write_mask(0x3c, 0x07ff_00ff);
+            }
+            _ => (),
+        }
+
+        function
+    }
+
+    /// Create an endpoint with arbitrary IDs, for when it doesn't matter which device it is.
+    pub fn endpoint() -> MockFunction {
+        MockFunction::new(0x8086, 0x1234, HeaderType::Endpoint)
+    }
+
+    /// Create a PCI-PCI bridge with arbitrary IDs, and with its bus numbers already set up.
+    pub fn bridge(primary: u8, secondary: u8, subordinate: u8) -> MockFunction {
+        let mut bridge = MockFunction::new(0x8086, 0x5678, HeaderType::PciPciBridge);
+        bridge.set(0x18, (subordinate as u32) << 16 | (secondary as u32) << 8 | primary as u32);
+        bridge
+    }
+
+    /// Get the raw value of the register at `offset`.
+    pub fn get(&self, offset: u16) -> u32 {
+        self.registers[Self::index(offset)]
+    }
+
+    /// Set the raw value of the register at `offset`, ignoring its write and RW1C masks.
+    pub fn set(&mut self, offset: u16, value: u32) {
+        self.registers[Self::index(offset)] = value;
+    }
+
+    /// Set which bits of the register at `offset` can be changed by writes through [`ConfigRegionAccess`]. All
+    /// other bits are read-only.
+    pub fn set_write_mask(&mut self, offset: u16, mask: u32) {
+        self.write_masks[Self::index(offset)] = mask;
+    }
+
+    /// Set which bits of the register at `offset` are cleared by writing a `1` to them through
+    /// [`ConfigRegionAccess`].
+    pub fn set_rw1c_mask(&mut self, offset: u16, mask: u32) {
+        self.rw1c_masks[Self::index(offset)] = mask;
+    }
+
+    /// Write to the register at `offset`, as if through [`ConfigRegionAccess`].
+    pub fn write(&mut self, offset: u16, value: u32) {
+        let index = Self::index(offset);
+        let write_mask = self.write_masks[index];
+        let rw1c_mask = self.rw1c_masks[index];
+
+        let mut register = self.registers[index];
+        register = (register & !write_mask) | (value & write_mask);
+        register &= !(value & rw1c_mask);
+        self.registers[index] = register;
+    }
+
+    pub fn set_multiple_functions(&mut self, multiple_functions: bool) {
+        self.register_mut(0x0c).set_bit(23, multiple_functions);
+    }
+
+    pub fn set_class(&mut self, revision: u8, base_class: u8, sub_class: u8, interface: u8) {
+        self.set(0x08, u32::from_le_bytes([revision, interface, sub_class, base_class]));
+    }
+
+    /// Implement a memory BAR at `slot`, of `size` bytes (which must be a power of two, and at least 16). A 64-bit
+    /// BAR also uses the slot after `slot`.
+    pub fn set_memory_bar(&mut self, slot: u8, address: u64, size: u64, prefetchable: bool, is_64bit: bool) {
+        let offset = 0x10 + slot as u16 * 4;
+        let mask = !(size - 1);
+
+        let mut low = (address & mask) as u32;
+        low.set_bits(1..3, if is_64bit { 0b10 } else { 0b00 });
+        low.set_bit(3, prefetchable);
+        self.set(offset, low);
+        self.set_write_mask(offset, mask as u32 & !0xf);
+
+        if is_64bit {
+            self.set(offset + 4, (address >> 32) as u32);
+            self.set_write_mask(offset + 4, (mask >> 32) as u32);
+        }
+    }
+
+    /// Implement an I/O BAR at `slot`, of `size` bytes (which must be a power of two, and at least 4).
+    pub fn set_io_bar(&mut self, slot: u8, port: u32, size: u32) {
+        let offset = 0x10 + slot as u16 * 4;
+        let mask = !(size - 1);
+
+        self.set(offset, (port & mask) | 0b1);
+        self.set_write_mask(offset, mask & !0x3);
+    }
+
+    /// Implement an Expansion ROM BAR of `size` bytes (which must be a power of two, and at least 2KiB). The ROM
+    /// starts off disabled.
+    pub fn set_expansion_rom(&mut self, address: u32, size: u32) {
+        let offset = if self.get(0x0c).get_bits(16..23) == 0x01 { 0x38 } else { 0x30 };
+        let mask = !(size - 1);
+
+        self.set(offset, address & mask);
+        self.set_write_mask(offset, mask | 0b1);
+    }
+
+    /// Add a capability with the given ID at `offset`, to the front of the capability list, and set the
+    /// Capabilities List bit in the Status register. The rest of the capability's registers can then be set up
+    /// with [`MockFunction::set`].
+    pub fn add_capability(&mut self, offset: u16, id: u8) {
+        let pointer_offset = if self.get(0x0c).get_bits(16..23) == 0x02 { 0x14 } else { 0x34 };
+        let next = self.get(pointer_offset).get_bits(0..8);
+
+        let mut header = self.get(offset);
+        header.set_bits(0..8, id as u32);
+        header.set_bits(8..16, next);
+        self.set(offset, header);
+
+        let mut pointer = self.get(pointer_offset);
+        pointer.set_bits(0..8, offset as u32);
+        self.set(pointer_offset, pointer);
+
+        self.register_mut(0x04).set_bit(20, true);
+    }
+
+    /// Add an extended capability with the given ID and version at `offset`, to the end of the extended
+    /// capability list. The first extended capability must be at `0x100`.
+    pub fn add_extended_capability(&mut self, offset: u16, id: u16, version: u8) {
+        let mut header = 0;
+        header.set_bits(0..16, id as u32);
+        header.set_bits(16..20, version as u32);
+
+        if offset != 0x100 {
+            let mut last = 0x100;
+            while self.get(last).get_bits(20..32) != 0 {
+                last = self.get(last).get_bits(20..32) as u16;
+            }
+            self.register_mut(last).set_bits(20..32, offset as u32);
+        }
+
+        self.set(offset, header);
+    }
+
+    fn register_mut(&mut self, offset: u16) -> &mut u32 {
+        &mut self.registers[Self::index(offset)]
+    }
+
+    fn index(offset: u16) -> usize {
+        (offset as usize & 0xfff) / 4
+    }
+}
+
+/// A [`Delay`] that records the delays it is asked for instead of waiting, so that code which waits for the
+/// hardware can be tested without slowing the tests down.
+#[derive(Default)]
+pub struct MockDelay {
+    delays: RefCell<Vec<u32>>,
+}
+
+impl MockDelay {
+    pub fn new() -> MockDelay {
+        MockDelay::default()
+    }
+
+    /// Get every delay that has been asked for so far, in microseconds.
+    pub fn delays(&self) -> Vec<u32> {
+        self.delays.borrow().clone()
+    }
+}
+
+impl Delay for MockDelay {
+    fn delay_us(&self, microseconds: u32) {
+        self.delays.borrow_mut().push(microseconds);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDRESS: PciAddress = PciAddress(0x0000_0100);
+
+    fn config_space(function: MockFunction) -> MockConfigSpace {
+        let mut config = MockConfigSpace::new();
+        config.add_function(ADDRESS, function);
+        config
+    }
+
+    #[test]
+    fn missing_functions() {
+        let config = MockConfigSpace::new();
+        assert!(!config.function_exists(ADDRESS));
+        unsafe {
+            config.write(ADDRESS, 0x04, 0x7);
+            assert_eq!(config.read(ADDRESS, 0x00), 0xffffffff);
+        }
+    }
+
+    #[test]
+    fn write_masks() {
+        let config = config_space(MockFunction::new(0x8086, 0x1234, HeaderType::Endpoint));
+        assert!(config.function_exists(ADDRESS));
+        unsafe {
+            config.write(ADDRESS, 0x00, 0);
+            assert_eq!(config.read(ADDRESS, 0x00), 0x1234_8086);
+            config.write(ADDRESS, 0x04, 0x0000_ffff);
+            assert_eq!(config.read(ADDRESS, 0x04), 0x0000_07ff);
+            config.write(ADDRESS, 0x3c, 0xffff_ff0b);
+            assert_eq!(config.read(ADDRESS, 0x3c), 0x0000_000b);
+        }
+    }
+
+    #[test]
+    fn rw1c() {
+        let mut function = MockFunction::new(0x8086, 0x1234, HeaderType::Endpoint);
+        function.set(0x04, 0xf900_0006);
+        let config = config_space(function);
+        unsafe {
+            // Writing zeros to the Status register leaves it alone
+            config.write(ADDRESS, 0x04, 0x0000_0007);
+            assert_eq!(config.read(ADDRESS, 0x04), 0xf900_0007);
+            // Writing ones clears only those bits
+            config.write(ADDRESS, 0x04, 0x8100_0007);
+            assert_eq!(config.read(ADDRESS, 0x04), 0x7800_0007);
+        }
+    }
+
+    #[test]
+    fn bar_sizing() {
+        let mut function = MockFunction::new(0x8086, 0x1234, HeaderType::Endpoint);
+        function.set_memory_bar(0, 0xfebf_0000, 0x1000, false, false);
+        function.set_memory_bar(1, 0x1_0000_0000, 0x4000_0000, true, true);
+        function.set_io_bar(3, 0xe000, 0x20);
+        let config = config_space(function);
+        unsafe {
+            assert_eq!(config.read(ADDRESS, 0x10), 0xfebf_0000);
+            config.write(ADDRESS, 0x10, 0xffffffff);
+            assert_eq!(config.read(ADDRESS, 0x10), 0xffff_f000);
+
+            assert_eq!(config.read(ADDRESS, 0x14), 0x0000_000c);
+            assert_eq!(config.read(ADDRESS, 0x18), 0x0000_0001);
+            config.write(ADDRESS, 0x14, 0xffffffff);
+            config.write(ADDRESS, 0x18, 0xffffffff);
+            assert_eq!(config.read(ADDRESS, 0x14), 0xc000_000c);
+            assert_eq!(config.read(ADDRESS, 0x18), 0xffffffff);
+
+            assert_eq!(config.read(ADDRESS, 0x1c), 0x0000_e001);
+            config.write(ADDRESS, 0x1c, 0xffffffff);
+            assert_eq!(config.read(ADDRESS, 0x1c), 0xffff_ffe1);
+
+            // Unimplemented BARs read back as zero
+            config.write(ADDRESS, 0x20, 0xffffffff);
+            assert_eq!(config.read(ADDRESS, 0x20), 0);
+        }
+    }
+
+    #[test]
+    fn capability_lists() {
+        let mut function = MockFunction::new(0x8086, 0x1234, HeaderType::Endpoint);
+        function.add_capability(0x40, 0x01);
+        function.add_capability(0x50, 0x05);
+        function.add_extended_capability(0x100, 0x0001, 2);
+        function.add_extended_capability(0x140, 0x000d, 1);
+
+        assert!(function.get(0x04).get_bit(20));
+        assert_eq!(function.get(0x34) & 0xff, 0x50);
+        assert_eq!(function.get(0x50) & 0xffff, 0x4005);
+        assert_eq!(function.get(0x40) & 0xffff, 0x0001);
+        assert_eq!(function.get(0x100), 0x1402_0001);
+        assert_eq!(function.get(0x140), 0x0001_000d);
+    }
+}
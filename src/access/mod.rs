@@ -0,0 +1,85 @@
+//! Ready-made implementations of [`ConfigRegionAccess`](crate::ConfigRegionAccess) for the common
+//! configuration mechanisms.
+
+mod ecam;
+mod legacy;
+#[cfg(feature = "alloc")]
+mod mock;
+#[cfg(feature = "std")]
+mod sysfs;
+
+pub use ecam::EcamAccess;
+pub use legacy::LegacyPortAccess;
+#[cfg(feature = "alloc")]
+pub use mock::{MockConfigSpace, MockDelay, MockFunction};
+#[cfg(feature = "std")]
+pub use sysfs::SysfsAccess;
+
+/// Access to memory-mapped registers. Backends that access the configuration space through memory go through
+/// this trait, so that they can be pointed at an in-memory buffer instead of real hardware.
+pub trait Mmio {
+    /// Read the 32-bit register at `address`.
+    ///
+    /// # Safety
+    /// `address` must be 4-byte aligned and valid for a 32-bit read.
+    unsafe fn read32(&self, address: usize) -> u32;
+
+    /// Write `value` to the 32-bit register at `address`.
+    ///
+    /// # Safety
+    /// `address` must be 4-byte aligned and valid for a 32-bit write.
+    unsafe fn write32(&self, address: usize, value: u32);
+}
+
+/// Accesses memory-mapped registers directly, using volatile reads and writes. This is the implementation to use
+/// when the registers are mapped into the current address space.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct VolatileMmio;
+
+impl Mmio for VolatileMmio {
+    unsafe fn read32(&self, address: usize) -> u32 {
+        unsafe { core::ptr::read_volatile(address as *const u32) }
+    }
+
+    unsafe fn write32(&self, address: usize, value: u32) {
+        unsafe { core::ptr::write_volatile(address as *mut u32, value) }
+    }
+}
+
+/// Access to I/O ports. Backends that access the configuration space through I/O ports go through this trait, so
+/// that they can be tested against a fake set of ports.
+pub trait PortIo {
+    /// Read a 32-bit value from `port`.
+    ///
+    /// # Safety
+    /// Reading from an I/O port can have side effects on the device behind it.
+    unsafe fn read32(&self, port: u16) -> u32;
+
+    /// Write a 32-bit `value` to `port`.
+    ///
+    /// # Safety
+    /// Writing to an I/O port can have side effects on the device behind it.
+    unsafe fn write32(&self, port: u16, value: u32);
+}
+
+/// Accesses I/O ports directly, using the `in` and `out` instructions.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[derive(Clone, Copy, Default, Debug)]
+pub struct X86PortIo;
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+impl PortIo for X86PortIo {
+    unsafe fn read32(&self, port: u16) -> u32 {
+        let value: u32;
+        unsafe {
+            core::arch::asm!("in eax, dx", out("eax") value, in("dx") port, options(nomem, nostack, preserves_flags));
+        }
+        value
+    }
+
+    unsafe fn write32(&self, port: u16, value: u32) {
+        unsafe {
+            core::arch::asm!("out dx, eax", in("dx") port, in("eax") value, options(nomem, nostack, preserves_flags));
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use crate::{ConfigRegionAccess, PciAddress};
+use std::{
+    format,
+    fs::{File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+/// Accesses the configuration space through the `config` files that Linux exposes in sysfs, at
+/// `/sys/bus/pci/devices/<segment>:<bus>:<device>.<function>/config`. This allows the rest of the crate to be used
+/// from userspace.
+///
+/// Linux only lets privileged processes read beyond the first 64 bytes of the configuration space, and only
+/// lets them write to it at all.
+#[derive(Clone, Debug)]
+pub struct SysfsAccess {
+    root: PathBuf,
+}
+
+impl SysfsAccess {
+    /// Create a `SysfsAccess` that uses the devices in `/sys/bus/pci/devices`.
+    pub fn new() -> SysfsAccess {
+        SysfsAccess::with_root("/sys/bus/pci/devices")
+    }
+
+    /// Create a `SysfsAccess` that uses the devices in another directory, laid out in the same way as
+    /// `/sys/bus/pci/devices`.
+    pub fn with_root(root: impl Into<PathBuf>) -> SysfsAccess {
+        SysfsAccess { root: root.into() }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn function_path(&self, address: PciAddress) -> PathBuf {
+        self.root.join(format!(
+            "{:04x}:{:02x}:{:02x}.{:x}",
+            address.segment(),
+            address.bus(),
+            address.device(),
+            address.function()
+        ))
+    }
+
+    fn read_config(&self, address: PciAddress, offset: u16) -> std::io::Result<u32> {
+        let mut file = File::open(self.function_path(address).join("config"))?;
+        let mut bytes = [0; 4];
+        file.seek(SeekFrom::Start(offset as u64))?;
+        file.read_exact(&mut bytes)?;
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    fn write_config(&self, address: PciAddress, offset: u16, value: u32) -> std::io::Result<()> {
+        let mut file = OpenOptions::new().write(true).open(self.function_path(address).join("config"))?;
+        file.seek(SeekFrom::Start(offset as u64))?;
+        file.write_all(&value.to_le_bytes())
+    }
+}
+
+impl Default for SysfsAccess {
+    fn default() -> SysfsAccess {
+        SysfsAccess::new()
+    }
+}
+
+impl ConfigRegionAccess for SysfsAccess {
+    fn function_exists(&self, address: PciAddress) -> bool {
+        self.function_path(address).is_dir()
+    }
+
+    /// Read a register. If the register can't be read (e.g. because the function doesn't exist, or the process
+    /// doesn't have permission to read it), this returns all ones.
+    unsafe fn read(&self, address: PciAddress, offset: u16) -> u32 {
+        self.read_config(address, offset).unwrap_or(0xffffffff)
+    }
+
+    /// Write a register. If the register can't be written, the write is ignored.
+    unsafe fn write(&self, address: PciAddress, offset: u16, value: u32) {
+        let _ = self.write_config(address, offset, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, process, vec};
+
+    /// A fake sysfs tree in a temporary directory, which is removed when it is dropped.
+    struct FakeSysfs {
+        root: PathBuf,
+    }
+
+    impl FakeSysfs {
+        fn new(name: &str) -> FakeSysfs {
+            let root = std::env::temp_dir().join(format!("pci_types-sysfs-{}-{}", name, process::id()));
+            let _ = fs::remove_dir_all(&root);
+            fs::create_dir_all(&root).unwrap();
+            FakeSysfs { root }
+        }
+
+        fn add_function(&self, name: &str, config: &[u8]) {
+            let path = self.root.join(name);
+            fs::create_dir(&path).unwrap();
+            fs::write(path.join("config"), config).unwrap();
+        }
+    }
+
+    impl Drop for FakeSysfs {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+
+    #[test]
+    fn function_exists() {
+        let sysfs = FakeSysfs::new("exists");
+        sysfs.add_function("0001:02:1f.3", &[0; 64]);
+        let access = SysfsAccess::with_root(&sysfs.root);
+
+        assert!(access.function_exists(PciAddress::new(1, 2, 0x1f, 3)));
+        assert!(!access.function_exists(PciAddress::new(0, 2, 0x1f, 3)));
+        assert!(!access.function_exists(PciAddress::new(1, 2, 0x1f, 2)));
+    }
+
+    #[test]
+    fn read_and_write() {
+        let sysfs = FakeSysfs::new("read-write");
+        let mut config = vec![0; 256];
+        config[0..4].copy_from_slice(&[0x86, 0x80, 0x34, 0x12]);
+        sysfs.add_function("0000:00:02.0", &config);
+        let access = SysfsAccess::with_root(&sysfs.root);
+        let address = PciAddress::new(0, 0, 2, 0);
+
+        unsafe {
+            assert_eq!(access.read(address, 0x00), 0x1234_8086);
+            access.write(address, 0x10, 0xfebf_0000);
+            assert_eq!(access.read(address, 0x10), 0xfebf_0000);
+        }
+        assert_eq!(
+            fs::read(sysfs.root.join("0000:00:02.0/config")).unwrap()[0x10..0x14],
+            [0x00, 0x00, 0xbf, 0xfe]
+        );
+    }
+
+    #[test]
+    fn unreadable_registers() {
+        let sysfs = FakeSysfs::new("unreadable");
+        sysfs.add_function("0000:00:00.0", &[0; 64]);
+        let access = SysfsAccess::with_root(&sysfs.root);
+
+        unsafe {
+            assert_eq!(access.read(PciAddress::new(0, 0, 0, 0), 0x40), 0xffffffff);
+            assert_eq!(access.read(PciAddress::new(0, 0, 1, 0), 0x00), 0xffffffff);
+            access.write(PciAddress::new(0, 0, 1, 0), 0x00, 0);
+        }
+        assert!(!sysfs.root.join("0000:00:01.0").exists());
+    }
+}
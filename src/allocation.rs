@@ -0,0 +1,451 @@
+use crate::{
+    Bar,
+    BarError,
+    BarWriteError,
+    BridgeWindowError,
+    ConfigRegionAccess,
+    PciAddress,
+    PciEnumerator,
+    PciFunction,
+    PciPciBridgeHeader,
+};
+use alloc::vec::Vec;
+use core::{cmp::Reverse, convert::TryFrom, ops::RangeInclusive};
+
+/// Bridges forward I/O in blocks of 4KiB.
+const IO_WINDOW_GRANULARITY: u64 = 0x1000;
+/// Bridges forward memory in blocks of 1MiB.
+const MEMORY_WINDOW_GRANULARITY: u64 = 0x100000;
+
+/// The kinds of resources that BARs and bridge windows are allocated from.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ResourceKind {
+    Io = 0,
+    Memory = 1,
+    PrefetchableMemory = 2,
+}
+
+impl ResourceKind {
+    const ALL: [ResourceKind; 3] = [ResourceKind::Io, ResourceKind::Memory, ResourceKind::PrefetchableMemory];
+
+    fn window_granularity(&self) -> u64 {
+        match self {
+            ResourceKind::Io => IO_WINDOW_GRANULARITY,
+            ResourceKind::Memory | ResourceKind::PrefetchableMemory => MEMORY_WINDOW_GRANULARITY,
+        }
+    }
+}
+
+/// The ranges of addresses that the host bridge forwards to the root bus, which resources are allocated from.
+#[derive(Clone, Debug, Default)]
+pub struct Apertures {
+    /// I/O ports
+    pub io: Option<RangeInclusive<u32>>,
+    /// Memory addresses below 4GiB
+    pub memory: Option<RangeInclusive<u32>>,
+    /// Prefetchable memory addresses, which may be above 4GiB. If this is not provided, prefetchable BARs are
+    /// allocated from `memory` instead.
+    pub prefetchable_memory: Option<RangeInclusive<u64>>,
+}
+
+/// What a range of addresses has been assigned to.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AssignedResource {
+    /// A BAR of an endpoint or PCI-PCI bridge. For 64-bit BARs, this is the first slot of the pair.
+    Bar(u8),
+    /// One of the forwarding windows of a PCI-PCI bridge.
+    BridgeWindow,
+}
+
+/// A range of addresses assigned by [`assign_resources`].
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Assignment {
+    pub address: PciAddress,
+    pub resource: AssignedResource,
+    pub kind: ResourceKind,
+    pub range: RangeInclusive<u64>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AllocationError {
+    /// The resources of this kind needed on `bus` (including everything behind its bridges) don't fit in the
+    /// aperture or bridge window they have to be allocated from.
+    OutOfSpace { kind: ResourceKind, bus: u8 },
+    /// A bridge couldn't be programmed with the window allocated to it - e.g. because it only decodes 16-bit I/O
+    /// ports, or 32-bit prefetchable memory addresses.
+    BridgeWindow { address: PciAddress, error: BridgeWindowError },
+    /// A BAR couldn't be programmed with the address allocated to it.
+    BarWrite { address: PciAddress, slot: u8, error: BarWriteError },
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Target {
+    Bar(u8),
+    /// The window of the bridge at the given index in `BusResources::bridges`
+    BridgeWindow(usize),
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Request {
+    address: PciAddress,
+    target: Target,
+    kind: ResourceKind,
+    size: u64,
+    align: u64,
+}
+
+struct BridgeResources {
+    address: PciAddress,
+    secondary: BusResources,
+}
+
+struct BusResources {
+    bus: u8,
+    requests: Vec<Request>,
+    bridges: Vec<BridgeResources>,
+}
+
+/// Allocate addresses for every BAR on `root_bus` of `segment`, and on the buses behind its PCI-PCI bridges, and
+/// program the BARs and the bridges' forwarding windows with them.
+///
+/// This works like resource assignment in Linux. First, the sizes of all of the BARs are found, and the hierarchy
+/// is walked bottom-up to work out how large each bridge window needs to be to fit everything behind it (rounded
+/// up to the window's granularity of 4KiB for I/O and 1MiB for memory). Then the hierarchy is walked top-down,
+/// placing the largest-aligned resources on each bus first, so that as little space as possible is lost to
+/// alignment.
+///
+/// Non-prefetchable memory BARs (including 64-bit ones) are allocated from the 32-bit memory aperture, as
+/// bridges only forward non-prefetchable memory below 4GiB. Prefetchable 64-bit BARs are allocated from the
+/// prefetchable aperture, and prefetchable 32-bit BARs from the 32-bit memory aperture.
+///
+/// The bridges' bus numbers must already be set up (e.g. with [`assign_bus_numbers`](crate::assign_bus_numbers)).
+/// Decoding should be disabled on all of the functions while this runs, and can be enabled once it has
+/// succeeded, although BARs are sized without disturbing functions that are already decoding. Returns every
+/// range that was assigned.
+pub fn assign_resources(
+    segment: u16,
+    root_bus: u8,
+    apertures: &Apertures,
+    access: &impl ConfigRegionAccess,
+) -> Result<Vec<Assignment>, AllocationError> {
+    let resources = gather_bus(segment, root_bus, apertures.prefetchable_memory.is_some(), access)?;
+
+    let mut assignments = Vec::new();
+    let windows = [
+        apertures.io.as_ref().map(|io| (*io.start() as u64, *io.end() as u64)),
+        apertures.memory.as_ref().map(|memory| (*memory.start() as u64, *memory.end() as u64)),
+        apertures.prefetchable_memory.as_ref().map(|memory| (*memory.start(), *memory.end())),
+    ];
+    assign_bus(&resources, windows, &mut assignments, access)?;
+    Ok(assignments)
+}
+
+/// Find the resources needed by every function on `bus`, and (recursively) by the buses behind its bridges.
+fn gather_bus(
+    segment: u16,
+    bus: u8,
+    use_prefetchable: bool,
+    access: &impl ConfigRegionAccess,
+) -> Result<BusResources, AllocationError> {
+    let mut resources = BusResources { bus, requests: Vec::new(), bridges: Vec::new() };
+
+    for function in PciEnumerator::single_bus(segment, bus, access) {
+        match PciFunction::from_header(function.header(), access) {
+            PciFunction::Endpoint(endpoint) => {
+                gather_bars(function.address, endpoint.bars(access), use_prefetchable, &mut resources.requests);
+            }
+            PciFunction::PciPciBridge(bridge) => {
+                /*
+                 * The bridge's own BARs are decoded on its primary bus, so are allocated alongside the other
+                 * functions on this bus rather than from its windows.
+                 */
+                gather_bars(function.address, bridge.bars(access), use_prefetchable, &mut resources.requests);
+
+                /*
+                 * Bus numbers are assigned depth-first, so the secondary bus is always above this one. Checking
+                 * this also stops a misconfigured bridge from making us recurse forever.
+                 */
+                let secondary = bridge.secondary_bus_number(access);
+                if secondary <= bus {
+                    continue;
+                }
+
+                let secondary = gather_bus(segment, secondary, use_prefetchable, access)?;
+                let index = resources.bridges.len();
+                for kind in ResourceKind::ALL.iter() {
+                    let (size, align) = window_requirement(&secondary.requests, *kind)
+                        .ok_or(AllocationError::OutOfSpace { kind: *kind, bus: secondary.bus })?;
+                    if size != 0 {
+                        resources.requests.push(Request {
+                            address: function.address,
+                            target: Target::BridgeWindow(index),
+                            kind: *kind,
+                            size,
+                            align,
+                        });
+                    }
+                }
+                resources.bridges.push(BridgeResources { address: function.address, secondary });
+            }
+            PciFunction::CardBusBridge(_) | PciFunction::Unknown(_) => (),
+        }
+    }
+
+    Ok(resources)
+}
+
+/// Add a request for each of the BARs of the function at `address` that needs space.
+fn gather_bars(
+    address: PciAddress,
+    bars: impl Iterator<Item = (u8, Result<Bar, BarError>)>,
+    use_prefetchable: bool,
+    requests: &mut Vec<Request>,
+) {
+    for (slot, bar) in bars {
+        /*
+         * A BAR that can't be decoded is left alone, rather than stopping the whole allocation.
+         */
+        let (kind, size) = match bar {
+            Ok(Bar::Memory32 { size, .. }) => (ResourceKind::Memory, size as u64),
+            Ok(Bar::Memory64 { size, prefetchable, .. }) => {
+                if prefetchable && use_prefetchable {
+                    (ResourceKind::PrefetchableMemory, size)
+                } else {
+                    (ResourceKind::Memory, size)
+                }
+            }
+            Ok(Bar::Io { size, .. }) => (ResourceKind::Io, size as u64),
+            Err(_) => continue,
+        };
+
+        if size != 0 {
+            requests.push(Request { address, target: Target::Bar(slot), kind, size, align: size });
+        }
+    }
+}
+
+/// Work out the size and alignment of the bridge window needed to fit the requests of `kind` on a bus, or `None`
+/// if they don't fit in a 64-bit address space.
+fn window_requirement(requests: &[Request], kind: ResourceKind) -> Option<(u64, u64)> {
+    let granularity = kind.window_granularity();
+    let mut requests: Vec<&Request> = requests.iter().filter(|request| request.kind == kind).collect();
+    if requests.is_empty() {
+        return Some((0, granularity));
+    }
+    requests.sort_by_key(|request| Reverse(request.align));
+
+    /*
+     * The window will be aligned to the largest alignment of any request, so laying the requests out from zero
+     * gives the same padding as laying them out from the window's real base.
+     */
+    let mut end: u64 = 0;
+    for request in requests.iter() {
+        end = align_up(end, request.align)?.checked_add(request.size)?;
+    }
+
+    Some((align_up(end, granularity)?, u64::max(requests[0].align, granularity)))
+}
+
+/// Allocate the resources of a bus from `windows` (indexed by `ResourceKind`), then do the same for the buses
+/// behind its bridges.
+fn assign_bus(
+    resources: &BusResources,
+    windows: [Option<(u64, u64)>; 3],
+    assignments: &mut Vec<Assignment>,
+    access: &impl ConfigRegionAccess,
+) -> Result<(), AllocationError> {
+    let mut bridge_windows = alloc::vec![[None; 3]; resources.bridges.len()];
+
+    for kind in ResourceKind::ALL.iter() {
+        let mut requests: Vec<&Request> =
+            resources.requests.iter().filter(|request| request.kind == *kind).collect();
+        if requests.is_empty() {
+            continue;
+        }
+        requests.sort_by_key(|request| Reverse(request.align));
+
+        let out_of_space = AllocationError::OutOfSpace { kind: *kind, bus: resources.bus };
+        let (start, end) = windows[*kind as usize].ok_or(out_of_space)?;
+        /*
+         * `next` is `None` once a request has been placed at the very top of the address space.
+         */
+        let mut next = Some(start);
+
+        for request in requests {
+            let base = next.and_then(|next| align_up(next, request.align)).ok_or(out_of_space)?;
+            let limit = base.checked_add(request.size - 1).filter(|&limit| limit <= end).ok_or(out_of_space)?;
+            next = limit.checked_add(1);
+
+            match request.target {
+                Target::Bar(slot) => {
+                    let value = usize::try_from(base).map_err(|_| AllocationError::BarWrite {
+                        address: request.address,
+                        slot,
+                        error: BarWriteError::InvalidValue,
+                    })?;
+                    unsafe { write_bar(request.address, slot, value, access) }
+                        .map_err(|error| AllocationError::BarWrite { address: request.address, slot, error })?;
+                    assignments.push(Assignment {
+                        address: request.address,
+                        resource: AssignedResource::Bar(slot),
+                        kind: *kind,
+                        range: base..=limit,
+                    });
+                }
+                Target::BridgeWindow(index) => {
+                    bridge_windows[index][*kind as usize] = Some((base, limit));
+                    assignments.push(Assignment {
+                        address: request.address,
+                        resource: AssignedResource::BridgeWindow,
+                        kind: *kind,
+                        range: base..=limit,
+                    });
+                }
+            }
+        }
+    }
+
+    for (bridge, windows) in resources.bridges.iter().zip(bridge_windows) {
+        program_bridge(bridge.address, &windows, access)?;
+        assign_bus(&bridge.secondary, windows, assignments, access)?;
+    }
+
+    Ok(())
+}
+
+fn program_bridge(
+    address: PciAddress,
+    windows: &[Option<(u64, u64)>; 3],
+    access: &impl ConfigRegionAccess,
+) -> Result<(), AllocationError> {
+    let bridge = PciPciBridgeHeader(address);
+    let to_error = |error| AllocationError::BridgeWindow { address, error };
+
+    /*
+     * The I/O and memory windows are allocated from apertures that are below 4GiB, so always fit in 32 bits.
+     */
+    let [io, memory, prefetchable] = *windows;
+    bridge.set_io_window(io.map(|(base, limit)| base as u32..=limit as u32), access).map_err(to_error)?;
+    bridge.set_memory_window(memory.map(|(base, limit)| base as u32..=limit as u32), access).map_err(to_error)?;
+    bridge
+        .set_prefetchable_memory_window(prefetchable.map(|(base, limit)| base..=limit), access)
+        .map_err(to_error)?;
+    Ok(())
+}
+
+/// Write a BAR of either an endpoint or a PCI-PCI bridge.
+unsafe fn write_bar(
+    address: PciAddress,
+    slot: u8,
+    value: usize,
+    access: &impl ConfigRegionAccess,
+) -> Result<(), BarWriteError> {
+    match PciFunction::new(address, access) {
+        PciFunction::Endpoint(mut endpoint) => unsafe { endpoint.write_bar(slot, access, value) },
+        PciFunction::PciPciBridge(mut bridge) => unsafe { bridge.write_bar(slot, access, value) },
+        PciFunction::CardBusBridge(_) | PciFunction::Unknown(_) => Err(BarWriteError::NoSuchBar),
+    }
+}
+
+/// Round `value` up to a multiple of `align`, or `None` if that doesn't fit in a `u64`.
+fn align_up(value: u64, align: u64) -> Option<u64> {
+    Some(value.checked_add(align - 1)? & !(align - 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        access::{MockConfigSpace, MockFunction},
+        EndpointHeader,
+    };
+
+    #[test]
+    fn bridge_hierarchy() {
+        let mut config = MockConfigSpace::new();
+        let mut function = MockFunction::endpoint();
+        function.set_memory_bar(0, 0, 0x1000, false, false);
+        function.set_io_bar(1, 0, 0x100);
+        function.set_memory_bar(2, 0, 0x100000, true, true);
+        config.add_function(PciAddress::new(0, 0, 1, 0), function);
+        let mut function = MockFunction::bridge(0, 1, 1);
+        function.set_memory_bar(0, 0, 0x4000, false, false);
+        config.add_function(PciAddress::new(0, 0, 2, 0), function);
+        let mut function = MockFunction::endpoint();
+        function.set_memory_bar(0, 0, 0x200000, false, false);
+        config.add_function(PciAddress::new(0, 1, 0, 0), function);
+
+        let apertures = Apertures {
+            io: Some(0x1000..=0xffff),
+            memory: Some(0x8000_0000..=0x8fff_ffff),
+            prefetchable_memory: Some(0x1_0000_0000..=0x1_ffff_ffff),
+        };
+        let assignments = assign_resources(0, 0, &apertures, &config).unwrap();
+        assert_eq!(assignments.len(), 6);
+
+        let endpoint = EndpointHeader(PciAddress::new(0, 0, 1, 0));
+        assert!(matches!(
+            endpoint.bar(0, &config),
+            Ok(Some(Bar::Memory32 { address: 0x8020_4000, size: 0x1000, prefetchable: false }))
+        ));
+        assert!(matches!(endpoint.bar(1, &config), Ok(Some(Bar::Io { port: 0x1000, size: 0x100 }))));
+        assert!(matches!(
+            endpoint.bar(2, &config),
+            Ok(Some(Bar::Memory64 { address: 0x1_0000_0000, size: 0x100000, prefetchable: true }))
+        ));
+
+        /*
+         * The bridge's own BAR is allocated on its primary bus, outside of its memory window.
+         */
+        let bridge = PciPciBridgeHeader(PciAddress::new(0, 0, 2, 0));
+        assert!(matches!(
+            bridge.bar(0, &config),
+            Ok(Some(Bar::Memory32 { address: 0x8020_0000, size: 0x4000, prefetchable: false }))
+        ));
+        assert_eq!(bridge.memory_window(&config), Some(0x8000_0000..=0x801f_ffff));
+        assert_eq!(bridge.io_window(&config), None);
+        assert_eq!(bridge.prefetchable_memory_window(&config), None);
+
+        let endpoint = EndpointHeader(PciAddress::new(0, 1, 0, 0));
+        assert!(matches!(
+            endpoint.bar(0, &config),
+            Ok(Some(Bar::Memory32 { address: 0x8000_0000, size: 0x200000, prefetchable: false }))
+        ));
+    }
+
+    #[test]
+    fn top_of_address_space() {
+        let apertures =
+            Apertures { io: None, memory: None, prefetchable_memory: Some(0xffff_ffff_fff0_0000..=u64::MAX) };
+
+        let mut config = MockConfigSpace::new();
+        let function = config.add_function(PciAddress::new(0, 0, 1, 0), MockFunction::endpoint());
+        function.set_memory_bar(0, 0, 0x80000, true, true);
+        function.set_memory_bar(2, 0, 0x80000, true, true);
+        let assignments = assign_resources(0, 0, &apertures, &config).unwrap();
+        assert_eq!(assignments[1].range, 0xffff_ffff_fff8_0000..=u64::MAX);
+
+        /*
+         * Once the aperture is used up to its very end, any further request is out of space.
+         */
+        config.function(PciAddress::new(0, 0, 1, 0)).unwrap().set_memory_bar(4, 0, 0x80000, true, true);
+        assert_eq!(
+            assign_resources(0, 0, &apertures, &config),
+            Err(AllocationError::OutOfSpace { kind: ResourceKind::PrefetchableMemory, bus: 0 })
+        );
+    }
+
+    #[test]
+    fn out_of_space() {
+        let mut config = MockConfigSpace::new();
+        config.add_function(PciAddress::new(0, 0, 1, 0), MockFunction::bridge(0, 1, 1));
+        let function = config.add_function(PciAddress::new(0, 1, 0, 0), MockFunction::endpoint());
+        function.set_memory_bar(0, 0, 0x200000, false, false);
+
+        let apertures = Apertures { io: None, memory: Some(0x8000_0000..=0x800f_ffff), prefetchable_memory: None };
+        assert_eq!(
+            assign_resources(0, 0, &apertures, &config),
+            Err(AllocationError::OutOfSpace { kind: ResourceKind::Memory, bus: 0 })
+        );
+    }
+}
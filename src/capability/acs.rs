@@ -0,0 +1,122 @@
+use crate::{capability::PciCapabilityAddress, ConfigRegionAccess};
+use bit_field::BitField;
+
+bitflags::bitflags! {
+    /// Access Control Services, as reported in the ACS Capability register and enabled in the ACS Control
+    /// register.
+    #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+    pub struct AcsFlags: u16 {
+        /// Check that the Requester ID of upstream requests belongs to the bus range behind the port.
+        const SOURCE_VALIDATION = 1 << 0;
+        /// Block upstream requests that carry translated addresses.
+        const TRANSLATION_BLOCKING = 1 << 1;
+        /// Redirect peer-to-peer requests upstream, rather than routing them directly.
+        const P2P_REQUEST_REDIRECT = 1 << 2;
+        /// Redirect peer-to-peer completions upstream, rather than routing them directly.
+        const P2P_COMPLETION_REDIRECT = 1 << 3;
+        /// Forward upstream requests that target the port's own link back upstream.
+        const UPSTREAM_FORWARDING = 1 << 4;
+        /// Block peer-to-peer requests to the ports selected by the Egress Control Vector.
+        const P2P_EGRESS_CONTROL = 1 << 5;
+        /// Route peer-to-peer requests with translated addresses directly, even if they would otherwise be
+        /// redirected.
+        const DIRECT_TRANSLATED_P2P = 1 << 6;
+    }
+}
+
+/// The Access Control Services capability, which controls how a port (or a function of a multi-function device)
+/// routes peer-to-peer transactions. It has the form:
+/// ```ignore
+///     32                              16               8              0
+///      +-------------------------------+-------------------------------+
+///      |       PCI Express Extended Capability Header                  | 0x00
+///      +-------------------------------+---------------+---------------+
+///      |          ACS Control          |  Egress Ctrl  |      ACS      | 0x04
+///      |                               |  Vector Size  |  Capability   |
+///      +-------------------------------+---------------+---------------+
+///      |              Egress Control Vector (1-8 dwords)               | 0x08
+///      +---------------------------------------------------------------+
+/// ```
+#[derive(Debug, Clone)]
+pub struct AcsCapability {
+    address: PciCapabilityAddress,
+}
+
+impl AcsCapability {
+    pub(crate) fn new(address: PciCapabilityAddress) -> AcsCapability {
+        AcsCapability { address }
+    }
+
+    /// The location of the capability's header.
+    pub fn address(&self) -> &PciCapabilityAddress {
+        &self.address
+    }
+
+    fn read(&self, offset: u16, access: &impl ConfigRegionAccess) -> u32 {
+        unsafe { access.read(self.address.address, self.address.offset + offset) }
+    }
+
+    fn write(&self, offset: u16, value: u32, access: &impl ConfigRegionAccess) {
+        unsafe { access.write(self.address.address, self.address.offset + offset, value) }
+    }
+
+    /// Get the services the function implements. Services that aren't implemented either don't apply to the
+    /// function, or are always enabled.
+    pub fn capabilities(&self, access: &impl ConfigRegionAccess) -> AcsFlags {
+        AcsFlags::from_bits_truncate(self.read(0x04, access).get_bits(0..8) as u16)
+    }
+
+    /// The number of bits in the Egress Control Vector, or `0` if
+    /// [`P2P_EGRESS_CONTROL`](AcsFlags::P2P_EGRESS_CONTROL) isn't implemented.
+    pub fn egress_control_vector_size(&self, access: &impl ConfigRegionAccess) -> u16 {
+        let data = self.read(0x04, access);
+        if !data.get_bit(5) {
+            return 0;
+        }
+
+        match data.get_bits(8..16) {
+            0 => 256,
+            size => size as u16,
+        }
+    }
+
+    pub fn control(&self, access: &impl ConfigRegionAccess) -> AcsFlags {
+        AcsFlags::from_bits_truncate(self.read(0x04, access).get_bits(16..32) as u16)
+    }
+
+    pub fn update_control<F>(&self, access: &impl ConfigRegionAccess, f: F)
+    where
+        F: Fn(AcsFlags) -> AcsFlags,
+    {
+        let mut data = self.read(0x04, access);
+        let control = f(AcsFlags::from_bits_truncate(data.get_bits(16..32) as u16));
+        data.set_bits(16..32, control.bits() as u32);
+        self.write(0x04, data, access);
+    }
+
+    /// Are peer-to-peer requests to the port or function selected by `bit` blocked? For a Downstream Port, `bit`
+    /// is the Port Number of another Downstream Port of the switch. For a function, it is another function of the
+    /// device. Returns `None` if `bit` is outside the Egress Control Vector.
+    pub fn is_egress_blocked(&self, bit: u16, access: &impl ConfigRegionAccess) -> Option<bool> {
+        if bit >= self.egress_control_vector_size(access) {
+            return None;
+        }
+        Some(self.read(0x08 + (bit / 32) * 4, access).get_bit((bit % 32) as usize))
+    }
+
+    /// Block or allow peer-to-peer requests to the port or function selected by `bit` (see
+    /// [`AcsCapability::is_egress_blocked`]). This only has an effect while
+    /// [`P2P_EGRESS_CONTROL`](AcsFlags::P2P_EGRESS_CONTROL) is enabled. Returns `false` (and does nothing) if `bit`
+    /// is outside the Egress Control Vector.
+    pub fn set_egress_blocked(&self, bit: u16, blocked: bool, access: &impl ConfigRegionAccess) -> bool {
+        if bit >= self.egress_control_vector_size(access) {
+            return false;
+        }
+
+        let offset = 0x08 + (bit / 32) * 4;
+        let mut data = self.read(offset, access);
+        data.set_bit((bit % 32) as usize, blocked);
+        self.write(offset, data, access);
+        true
+    }
+}
@@ -0,0 +1,513 @@
+use crate::{capability::PciCapabilityAddress, ConfigRegionAccess, PciAddress};
+use bit_field::BitField;
+
+bitflags::bitflags! {
+    /// Uncorrectable errors, as reported in the Uncorrectable Error Status, Mask and Severity registers.
+    #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+    pub struct UncorrectableErrors: u32 {
+        const DATA_LINK_PROTOCOL_ERROR = 1 << 4;
+        const SURPRISE_DOWN_ERROR = 1 << 5;
+        const POISONED_TLP_RECEIVED = 1 << 12;
+        const FLOW_CONTROL_PROTOCOL_ERROR = 1 << 13;
+        const COMPLETION_TIMEOUT = 1 << 14;
+        const COMPLETER_ABORT = 1 << 15;
+        const UNEXPECTED_COMPLETION = 1 << 16;
+        const RECEIVER_OVERFLOW = 1 << 17;
+        const MALFORMED_TLP = 1 << 18;
+        const ECRC_ERROR = 1 << 19;
+        const UNSUPPORTED_REQUEST = 1 << 20;
+        const ACS_VIOLATION = 1 << 21;
+        const UNCORRECTABLE_INTERNAL_ERROR = 1 << 22;
+        const MC_BLOCKED_TLP = 1 << 23;
+        const ATOMIC_OP_EGRESS_BLOCKED = 1 << 24;
+        const TLP_PREFIX_BLOCKED = 1 << 25;
+        const POISONED_TLP_EGRESS_BLOCKED = 1 << 26;
+    }
+}
+
+bitflags::bitflags! {
+    /// Correctable errors, as reported in the Correctable Error Status and Mask registers.
+    #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+    pub struct CorrectableErrors: u32 {
+        const RECEIVER_ERROR = 1 << 0;
+        const BAD_TLP = 1 << 6;
+        const BAD_DLLP = 1 << 7;
+        const REPLAY_NUM_ROLLOVER = 1 << 8;
+        const REPLAY_TIMER_TIMEOUT = 1 << 12;
+        const ADVISORY_NON_FATAL_ERROR = 1 << 13;
+        const CORRECTED_INTERNAL_ERROR = 1 << 14;
+        const HEADER_LOG_OVERFLOW = 1 << 15;
+    }
+}
+
+bitflags::bitflags! {
+    /// Controls which errors reported to a Root Port (or Root Complex Event Collector) generate interrupts.
+    #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+    pub struct RootErrorCommand: u32 {
+        const CORRECTABLE_ERROR_REPORTING = 1 << 0;
+        const NON_FATAL_ERROR_REPORTING = 1 << 1;
+        const FATAL_ERROR_REPORTING = 1 << 2;
+    }
+}
+
+/// The Advanced Error Reporting capability, which reports errors in much more detail than the PCI Express
+/// capability's Device Status register. It has the form:
+/// ```ignore
+///     32                                                              0
+///      +---------------------------------------------------------------+
+///      |               PCI Express Extended Capability Header          | 0x00
+///      +---------------------------------------------------------------+
+///      |                 Uncorrectable Error Status                    | 0x04
+///      +---------------------------------------------------------------+
+///      |                  Uncorrectable Error Mask                     | 0x08
+///      +---------------------------------------------------------------+
+///      |                Uncorrectable Error Severity                   | 0x0c
+///      +---------------------------------------------------------------+
+///      |                  Correctable Error Status                     | 0x10
+///      +---------------------------------------------------------------+
+///      |                   Correctable Error Mask                      | 0x14
+///      +---------------------------------------------------------------+
+///      |        Advanced Error Capabilities and Control                | 0x18
+///      +---------------------------------------------------------------+
+///      |                     Header Log (4 dwords)                     | 0x1c
+///      +---------------------------------------------------------------+
+///      |             Root Error Command (Root Ports only)              | 0x2c
+///      +---------------------------------------------------------------+
+///      |             Root Error Status (Root Ports only)               | 0x30
+///      +-------------------------------+-------------------------------+
+///      | ERR_FATAL/NONFATAL Source ID  |      ERR_COR Source ID        | 0x34
+///      +-------------------------------+-------------------------------+
+///      |                  TLP Prefix Log (4 dwords)                    | 0x38
+///      +---------------------------------------------------------------+
+/// ```
+#[derive(Debug, Clone)]
+pub struct AerCapability {
+    address: PciCapabilityAddress,
+}
+
+impl AerCapability {
+    pub(crate) fn new(address: PciCapabilityAddress) -> AerCapability {
+        AerCapability { address }
+    }
+
+    /// The location of the capability's header.
+    pub fn address(&self) -> &PciCapabilityAddress {
+        &self.address
+    }
+
+    fn read(&self, offset: u16, access: &impl ConfigRegionAccess) -> u32 {
+        unsafe { access.read(self.address.address, self.address.offset + offset) }
+    }
+
+    fn write(&self, offset: u16, value: u32, access: &impl ConfigRegionAccess) {
+        unsafe { access.write(self.address.address, self.address.offset + offset, value) }
+    }
+
+    pub fn uncorrectable_status(&self, access: &impl ConfigRegionAccess) -> UncorrectableErrors {
+        UncorrectableErrors::from_bits_retain(self.read(0x04, access))
+    }
+
+    /// Clear the uncorrectable errors that are set in `errors`. Passing the value returned by
+    /// [`AerCapability::uncorrectable_status`] clears the errors that were seen, without losing any that have been
+    /// detected since.
+    pub fn clear_uncorrectable_status(&self, errors: UncorrectableErrors, access: &impl ConfigRegionAccess) {
+        self.write(0x04, errors.bits(), access);
+    }
+
+    /// Get the uncorrectable errors that are masked, and so are neither logged in the Header Log nor reported to
+    /// the Root Complex.
+    pub fn uncorrectable_mask(&self, access: &impl ConfigRegionAccess) -> UncorrectableErrors {
+        UncorrectableErrors::from_bits_retain(self.read(0x08, access))
+    }
+
+    pub fn set_uncorrectable_mask(&self, mask: UncorrectableErrors, access: &impl ConfigRegionAccess) {
+        self.write(0x08, mask.bits(), access);
+    }
+
+    /// Get the uncorrectable errors that are reported as fatal (`ERR_FATAL`). The others are reported as non-fatal
+    /// (`ERR_NONFATAL`).
+    pub fn uncorrectable_severity(&self, access: &impl ConfigRegionAccess) -> UncorrectableErrors {
+        UncorrectableErrors::from_bits_retain(self.read(0x0c, access))
+    }
+
+    pub fn set_uncorrectable_severity(&self, fatal: UncorrectableErrors, access: &impl ConfigRegionAccess) {
+        self.write(0x0c, fatal.bits(), access);
+    }
+
+    pub fn correctable_status(&self, access: &impl ConfigRegionAccess) -> CorrectableErrors {
+        CorrectableErrors::from_bits_retain(self.read(0x10, access))
+    }
+
+    /// Clear the correctable errors that are set in `errors`. This behaves like
+    /// [`AerCapability::clear_uncorrectable_status`].
+    pub fn clear_correctable_status(&self, errors: CorrectableErrors, access: &impl ConfigRegionAccess) {
+        self.write(0x10, errors.bits(), access);
+    }
+
+    /// Get the correctable errors that are masked, and so aren't reported to the Root Complex.
+    pub fn correctable_mask(&self, access: &impl ConfigRegionAccess) -> CorrectableErrors {
+        CorrectableErrors::from_bits_retain(self.read(0x14, access))
+    }
+
+    pub fn set_correctable_mask(&self, mask: CorrectableErrors, access: &impl ConfigRegionAccess) {
+        self.write(0x14, mask.bits(), access);
+    }
+
+    pub fn control(&self, access: &impl ConfigRegionAccess) -> AerControl {
+        AerControl(self.read(0x18, access))
+    }
+
+    pub fn update_control<F>(&self, access: &impl ConfigRegionAccess, f: F)
+    where
+        F: Fn(AerControl) -> AerControl,
+    {
+        let control = f(self.control(access));
+        self.write(0x18, control.0, access);
+    }
+
+    /// Get the header of the TLP that caused the error pointed to by
+    /// [`AerControl::first_error_pointer`]. This is only valid for errors that log a header.
+    pub fn header_log(&self, access: &impl ConfigRegionAccess) -> TlpHeader {
+        TlpHeader([
+            self.read(0x1c, access),
+            self.read(0x20, access),
+            self.read(0x24, access),
+            self.read(0x28, access),
+        ])
+    }
+
+    /// Get the TLP Prefix Log, which holds the End-End TLP Prefixes of the TLP in the Header Log. Returns `None`
+    /// if the function doesn't implement the log (see [`AerControl::tlp_prefix_log_present`]).
+    pub fn tlp_prefix_log(&self, access: &impl ConfigRegionAccess) -> Option<[u32; 4]> {
+        if !self.control(access).tlp_prefix_log_present() {
+            return None;
+        }
+        Some([self.read(0x38, access), self.read(0x3c, access), self.read(0x40, access), self.read(0x44, access)])
+    }
+
+    /// Get which errors reported by other functions generate interrupts. Only Root Ports and Root Complex Event
+    /// Collectors implement this register.
+    pub fn root_error_command(&self, access: &impl ConfigRegionAccess) -> RootErrorCommand {
+        RootErrorCommand::from_bits_truncate(self.read(0x2c, access))
+    }
+
+    pub fn set_root_error_command(&self, command: RootErrorCommand, access: &impl ConfigRegionAccess) {
+        self.write(0x2c, command.bits(), access);
+    }
+
+    /// Get which error messages have been received. Only Root Ports and Root Complex Event Collectors implement
+    /// this register.
+    pub fn root_error_status(&self, access: &impl ConfigRegionAccess) -> RootErrorStatus {
+        RootErrorStatus(self.read(0x30, access))
+    }
+
+    /// Clear the messages that are set in `status`. Passing the value returned by
+    /// [`AerCapability::root_error_status`] clears the messages that were seen, without losing any that have
+    /// been received since.
+    pub fn clear_root_error_status(&self, status: RootErrorStatus, access: &impl ConfigRegionAccess) {
+        self.write(0x30, status.0 & RootErrorStatus::RW1C_MASK, access);
+    }
+
+    /// Get the function that sent the first `ERR_COR` message recorded in the Root Error Status register.
+    pub fn correctable_error_source(&self, access: &impl ConfigRegionAccess) -> PciAddress {
+        let id = self.read(0x34, access).get_bits(0..16) as u16;
+        PciAddress::from_routing_id(self.address.address.segment(), id)
+    }
+
+    /// Get the function that sent the first `ERR_FATAL` or `ERR_NONFATAL` message recorded in the Root Error
+    /// Status register.
+    pub fn uncorrectable_error_source(&self, access: &impl ConfigRegionAccess) -> PciAddress {
+        let id = self.read(0x34, access).get_bits(16..32) as u16;
+        PciAddress::from_routing_id(self.address.address.segment(), id)
+    }
+}
+
+/// The Advanced Error Capabilities and Control register.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct AerControl(u32);
+
+impl AerControl {
+    /// The bit in the Uncorrectable Error Status register of the error that was detected first. The Header Log
+    /// holds the header of the TLP that caused this error.
+    pub fn first_error_pointer(&self) -> u8 {
+        self.0.get_bits(0..5) as u8
+    }
+
+    pub fn ecrc_generation_capable(&self) -> bool {
+        self.0.get_bit(5)
+    }
+
+    pub fn ecrc_generation(&self) -> bool {
+        self.0.get_bit(6)
+    }
+
+    pub fn set_ecrc_generation(&mut self, enabled: bool) {
+        self.0.set_bit(6, enabled);
+    }
+
+    pub fn ecrc_check_capable(&self) -> bool {
+        self.0.get_bit(7)
+    }
+
+    pub fn ecrc_check(&self) -> bool {
+        self.0.get_bit(8)
+    }
+
+    pub fn set_ecrc_check(&mut self, enabled: bool) {
+        self.0.set_bit(8, enabled);
+    }
+
+    pub fn multiple_header_recording_capable(&self) -> bool {
+        self.0.get_bit(9)
+    }
+
+    pub fn multiple_header_recording(&self) -> bool {
+        self.0.get_bit(10)
+    }
+
+    pub fn set_multiple_header_recording(&mut self, enabled: bool) {
+        self.0.set_bit(10, enabled);
+    }
+
+    pub fn tlp_prefix_log_present(&self) -> bool {
+        self.0.get_bit(11)
+    }
+
+    pub fn completion_timeout_prefix_header_log_capable(&self) -> bool {
+        self.0.get_bit(12)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct RootErrorStatus(u32);
+
+impl RootErrorStatus {
+    const RW1C_MASK: u32 = 0x7f;
+
+    /// Has an `ERR_COR` message been received?
+    pub fn correctable_error_received(&self) -> bool {
+        self.0.get_bit(0)
+    }
+
+    /// Has an `ERR_COR` message been received while the previous one was still recorded?
+    pub fn multiple_correctable_errors_received(&self) -> bool {
+        self.0.get_bit(1)
+    }
+
+    /// Has an `ERR_FATAL` or `ERR_NONFATAL` message been received?
+    pub fn uncorrectable_error_received(&self) -> bool {
+        self.0.get_bit(2)
+    }
+
+    /// Has an `ERR_FATAL` or `ERR_NONFATAL` message been received while the previous one was still recorded?
+    pub fn multiple_uncorrectable_errors_received(&self) -> bool {
+        self.0.get_bit(3)
+    }
+
+    /// Was the first uncorrectable error message received `ERR_FATAL`?
+    pub fn first_uncorrectable_fatal(&self) -> bool {
+        self.0.get_bit(4)
+    }
+
+    pub fn non_fatal_error_messages_received(&self) -> bool {
+        self.0.get_bit(5)
+    }
+
+    pub fn fatal_error_messages_received(&self) -> bool {
+        self.0.get_bit(6)
+    }
+
+    /// The MSI or MSI-X vector used for interrupts generated by this register.
+    pub fn interrupt_message_number(&self) -> u8 {
+        self.0.get_bits(27..32) as u8
+    }
+}
+
+/// The header of a TLP, as recorded in the Header Log. The first byte of the header is in the top byte of the
+/// first dword.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct TlpHeader(pub [u32; 4]);
+
+impl TlpHeader {
+    /// The Fmt field, which gives the size of the header and whether it has a data payload.
+    pub fn format(&self) -> u8 {
+        self.0[0].get_bits(29..32) as u8
+    }
+
+    /// The Type field, which (together with the Fmt field) gives the type of the TLP.
+    pub fn tlp_type(&self) -> u8 {
+        self.0[0].get_bits(24..29) as u8
+    }
+
+    /// Is the header four dwords long, rather than three?
+    pub fn is_4dw(&self) -> bool {
+        self.format().get_bit(0)
+    }
+
+    /// Does the TLP carry a data payload?
+    pub fn has_data(&self) -> bool {
+        self.format().get_bit(1)
+    }
+
+    /// The length of the data payload, in dwords.
+    pub fn length(&self) -> u16 {
+        match self.0[0].get_bits(0..10) as u16 {
+            0 => 1024,
+            length => length,
+        }
+    }
+
+    /// The Requester ID of a request TLP (see [`PciAddress::from_routing_id`]).
+    pub fn requester_id(&self) -> u16 {
+        self.0[1].get_bits(16..32) as u16
+    }
+
+    pub fn tag(&self) -> u8 {
+        self.0[1].get_bits(8..16) as u8
+    }
+
+    /// The address targeted by a memory request TLP, or `None` if this isn't a memory request.
+    pub fn memory_address(&self) -> Option<u64> {
+        if self.tlp_type() & 0b11110 != 0 {
+            return None;
+        }
+
+        if self.is_4dw() {
+            Some((self.0[2] as u64) << 32 | (self.0[3] & !0x3) as u64)
+        } else {
+            Some((self.0[2] & !0x3) as u64)
+        }
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod tests {
+    use super::*;
+    use crate::{
+        access::{MockConfigSpace, MockFunction},
+        capability::PciExtendedCapability,
+        EndpointHeader,
+    };
+
+    const ADDRESS: PciAddress = PciAddress(0x0002_0100);
+
+    fn mock_aer(f: impl FnOnce(&mut MockFunction)) -> MockConfigSpace {
+        let mut function = MockFunction::endpoint();
+        function.add_extended_capability(0x100, 0x0001, 2);
+        function.set_rw1c_mask(0x104, 0x07ff_f030);
+        function.set_rw1c_mask(0x110, 0x0000_f1c1);
+        function.set_rw1c_mask(0x130, 0x0000_007f);
+        f(&mut function);
+        let mut config = MockConfigSpace::new();
+        config.add_function(ADDRESS, function);
+        config
+    }
+
+    fn aer(config: &MockConfigSpace) -> AerCapability {
+        EndpointHeader(ADDRESS)
+            .extended_capabilities(config)
+            .find_map(|capability| match capability {
+                PciExtendedCapability::AdvancedErrorReporting(aer) => Some(aer),
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn clear_status() {
+        let config = mock_aer(|function| {
+            function.set(0x104, 0x0010_4000);
+            function.set(0x110, 0x0000_2041);
+        });
+        let aer = aer(&config);
+
+        assert_eq!(
+            aer.uncorrectable_status(&config),
+            UncorrectableErrors::COMPLETION_TIMEOUT | UncorrectableErrors::UNSUPPORTED_REQUEST
+        );
+        aer.clear_uncorrectable_status(UncorrectableErrors::COMPLETION_TIMEOUT, &config);
+        assert_eq!(aer.uncorrectable_status(&config), UncorrectableErrors::UNSUPPORTED_REQUEST);
+
+        let seen = aer.correctable_status(&config);
+        assert_eq!(
+            seen,
+            CorrectableErrors::RECEIVER_ERROR
+                | CorrectableErrors::BAD_TLP
+                | CorrectableErrors::ADVISORY_NON_FATAL_ERROR
+        );
+        aer.clear_correctable_status(seen - CorrectableErrors::BAD_TLP, &config);
+        assert_eq!(aer.correctable_status(&config), CorrectableErrors::BAD_TLP);
+    }
+
+    #[test]
+    fn clear_root_error_status() {
+        // ERR_COR and ERR_FATAL received, with the interrupt message number in the top bits
+        let mut config = mock_aer(|function| function.set(0x130, 0x1800_0055));
+        let aer = aer(&config);
+
+        let status = aer.root_error_status(&config);
+        assert!(status.correctable_error_received());
+        assert!(status.uncorrectable_error_received());
+        assert!(status.first_uncorrectable_fatal());
+        assert!(status.fatal_error_messages_received());
+        assert_eq!(status.interrupt_message_number(), 3);
+
+        /*
+         * A message received after the status was read isn't lost.
+         */
+        config.function(ADDRESS).unwrap().set(0x130, 0x1800_0057);
+        aer.clear_root_error_status(status, &config);
+        let status = aer.root_error_status(&config);
+        assert!(status.multiple_correctable_errors_received());
+        assert!(!status.correctable_error_received());
+        assert_eq!(unsafe { config.read(ADDRESS, 0x130) }, 0x1800_0002);
+    }
+
+    #[test]
+    fn tlp_prefix_log() {
+        let mut config = mock_aer(|function| {
+            function.set(0x138, 0x9000_0001);
+            function.set(0x144, 0x9000_0004);
+        });
+        assert_eq!(aer(&config).tlp_prefix_log(&config), None);
+
+        config.function(ADDRESS).unwrap().set(0x118, 1 << 11);
+        assert!(aer(&config).control(&config).tlp_prefix_log_present());
+        assert_eq!(aer(&config).tlp_prefix_log(&config), Some([0x9000_0001, 0, 0, 0x9000_0004]));
+    }
+
+    #[test]
+    fn error_sources() {
+        let config = mock_aer(|function| function.set(0x134, 0x0312_0208));
+        let aer = aer(&config);
+
+        assert_eq!(aer.correctable_error_source(&config), PciAddress::new(2, 0x02, 0x01, 0x0));
+        assert_eq!(aer.uncorrectable_error_source(&config), PciAddress::new(2, 0x03, 0x02, 0x2));
+    }
+
+    #[test]
+    fn tlp_header() {
+        // 3DW Memory Read of 1 dword
+        let header = TlpHeader([0x0000_0001, 0x0100_2a0f, 0xfee0_0003, 0]);
+        assert!(!header.is_4dw());
+        assert!(!header.has_data());
+        assert_eq!(header.length(), 1);
+        assert_eq!(header.requester_id(), 0x0100);
+        assert_eq!(header.tag(), 0x2a);
+        assert_eq!(header.memory_address(), Some(0xfee0_0000));
+
+        // 4DW Memory Write of 1024 dwords
+        let header = TlpHeader([0x6000_0000, 0x0208_0000, 0x0000_0001, 0x2345_6781]);
+        assert_eq!((header.format(), header.tlp_type()), (0b011, 0b00000));
+        assert!(header.is_4dw());
+        assert!(header.has_data());
+        assert_eq!(header.length(), 1024);
+        assert_eq!(header.memory_address(), Some(0x1_2345_6780));
+
+        // Memory Read Request-Locked
+        assert_eq!(TlpHeader([0x0100_0001, 0, 0x8000_0000, 0]).memory_address(), Some(0x8000_0000));
+        // Configuration Read Type 0 and Completion with Data
+        assert_eq!(TlpHeader([0x0400_0001, 0, 0x0100_0010, 0]).memory_address(), None);
+        assert_eq!(TlpHeader([0x4a00_0001, 0, 0, 0]).memory_address(), None);
+    }
+}
@@ -0,0 +1,553 @@
+use crate::{
+    capability::{Delay, PciCapabilityAddress, TlpHeader},
+    ConfigRegionAccess,
+    PciAddress,
+};
+use bit_field::BitField;
+
+/// How many times the DPC RP Busy bit is polled before releasing a port from containment is given up on.
+const RP_BUSY_POLL_LIMIT: u32 = 100;
+/// How long to wait between polls of the DPC RP Busy bit, in microseconds. Together with `RP_BUSY_POLL_LIMIT`,
+/// this allows the Root Port 1s to finish its internal activity.
+const RP_BUSY_POLL_INTERVAL_US: u32 = 10_000;
+
+/// Which errors cause a port to contain its link.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DpcTrigger {
+    Disabled = 0b00,
+    /// Contain the link when an `ERR_FATAL` is detected.
+    Fatal = 0b01,
+    /// Contain the link when an `ERR_NONFATAL` or an `ERR_FATAL` is detected.
+    NonFatalOrFatal = 0b10,
+}
+
+/// Why a port contained its link.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DpcTriggerReason {
+    /// The port itself detected an unmasked uncorrectable error.
+    UnmaskedUncorrectableError,
+    /// The port received an `ERR_NONFATAL` message.
+    NonFatalMessage,
+    /// The port received an `ERR_FATAL` message.
+    FatalMessage,
+    /// A Root Port detected an RP PIO error (see [`DpcCapability::rp_pio_status`]).
+    RpPioError,
+    /// Software set the DPC Software Trigger bit.
+    SoftwareTrigger,
+    Unknown,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DpcError {
+    /// The Root Port didn't finish its internal activity in time, so it can't be released from containment yet.
+    RootPortBusy,
+}
+
+bitflags::bitflags! {
+    /// Errors in requests issued by a Root Port on behalf of the CPU (Programmed I/O), as reported in the RP PIO
+    /// Status, Mask, Severity, SysError and Exception registers.
+    #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+    pub struct RpPioErrors: u32 {
+        const CONFIGURATION_UNSUPPORTED_REQUEST = 1 << 0;
+        const CONFIGURATION_COMPLETER_ABORT = 1 << 1;
+        const CONFIGURATION_COMPLETION_TIMEOUT = 1 << 2;
+        const IO_UNSUPPORTED_REQUEST = 1 << 8;
+        const IO_COMPLETER_ABORT = 1 << 9;
+        const IO_COMPLETION_TIMEOUT = 1 << 10;
+        const MEMORY_UNSUPPORTED_REQUEST = 1 << 16;
+        const MEMORY_COMPLETER_ABORT = 1 << 17;
+        const MEMORY_COMPLETION_TIMEOUT = 1 << 18;
+    }
+}
+
+/// The Downstream Port Containment capability, which lets a Root Port or Switch Downstream Port take its link
+/// down when an uncorrectable error is detected below it, stopping potentially-corrupted data from propagating.
+/// It has the form:
+/// ```ignore
+///     32                              16                              0
+///      +-------------------------------+-------------------------------+
+///      |       PCI Express Extended Capability Header                  | 0x00
+///      +-------------------------------+-------------------------------+
+///      |          DPC Control          |        DPC Capability         | 0x04
+///      +-------------------------------+-------------------------------+
+///      |      DPC Error Source ID      |          DPC Status           | 0x08
+///      +-------------------------------+-------------------------------+
+///      |                         RP PIO Status                         | 0x0c
+///      |                          RP PIO Mask                          | 0x10
+///      |                        RP PIO Severity                        | 0x14
+///      |                        RP PIO SysError                        | 0x18
+///      |                        RP PIO Exception                       | 0x1c
+///      |                   RP PIO Header Log (4 dwords)                | 0x20
+///      |                      RP PIO ImpSpec Log                       | 0x30
+///      |               RP PIO TLP Prefix Log (0-4 dwords)              | 0x34
+///      +---------------------------------------------------------------+
+/// ```
+/// The RP PIO registers are only implemented by Root Ports that support the RP Extensions for DPC. On other
+/// ports, reading them returns `None` and writing them does nothing.
+#[derive(Debug, Clone)]
+pub struct DpcCapability {
+    address: PciCapabilityAddress,
+}
+
+impl DpcCapability {
+    pub(crate) fn new(address: PciCapabilityAddress) -> DpcCapability {
+        DpcCapability { address }
+    }
+
+    /// The location of the capability's header.
+    pub fn address(&self) -> &PciCapabilityAddress {
+        &self.address
+    }
+
+    fn read(&self, offset: u16, access: &impl ConfigRegionAccess) -> u32 {
+        unsafe { access.read(self.address.address, self.address.offset + offset) }
+    }
+
+    fn write(&self, offset: u16, value: u32, access: &impl ConfigRegionAccess) {
+        unsafe { access.write(self.address.address, self.address.offset + offset, value) }
+    }
+
+    fn read_rp_pio(&self, offset: u16, access: &impl ConfigRegionAccess) -> Option<RpPioErrors> {
+        if !self.capabilities(access).root_port_extensions() {
+            return None;
+        }
+        Some(RpPioErrors::from_bits_retain(self.read(offset, access)))
+    }
+
+    fn write_rp_pio(&self, offset: u16, errors: RpPioErrors, access: &impl ConfigRegionAccess) {
+        if self.capabilities(access).root_port_extensions() {
+            self.write(offset, errors.bits(), access);
+        }
+    }
+
+    pub fn capabilities(&self, access: &impl ConfigRegionAccess) -> DpcCapabilities {
+        DpcCapabilities(self.read(0x04, access).get_bits(0..16) as u16)
+    }
+
+    pub fn control(&self, access: &impl ConfigRegionAccess) -> DpcControl {
+        DpcControl(self.read(0x04, access).get_bits(16..32) as u16)
+    }
+
+    pub fn update_control<F>(&self, access: &impl ConfigRegionAccess, f: F)
+    where
+        F: Fn(DpcControl) -> DpcControl,
+    {
+        let mut data = self.read(0x04, access);
+        let control = f(DpcControl(data.get_bits(16..32) as u16));
+        data.set_bits(16..32, control.0 as u32);
+        self.write(0x04, data, access);
+    }
+
+    /// Enable containment of the link when errors selected by `trigger` are detected. The other DPC controls are
+    /// left alone.
+    pub fn enable(&self, trigger: DpcTrigger, access: &impl ConfigRegionAccess) {
+        self.update_control(access, |mut control| {
+            control.set_trigger(trigger);
+            control
+        });
+    }
+
+    pub fn disable(&self, access: &impl ConfigRegionAccess) {
+        self.enable(DpcTrigger::Disabled, access);
+    }
+
+    /// Contain the link, as if an error had been detected. This is only possible if the port supports
+    /// [software triggering](DpcCapabilities::software_triggering_supported) and DPC is enabled.
+    pub fn trigger(&self, access: &impl ConfigRegionAccess) {
+        self.update_control(access, |mut control| {
+            control.0.set_bit(6, true);
+            control
+        });
+    }
+
+    pub fn status(&self, access: &impl ConfigRegionAccess) -> DpcStatus {
+        DpcStatus(self.read(0x08, access).get_bits(0..16) as u16)
+    }
+
+    /// Is the port's link currently contained?
+    pub fn is_contained(&self, access: &impl ConfigRegionAccess) -> bool {
+        self.status(access).trigger_status()
+    }
+
+    /// Clear the DPC Interrupt Status bit, without releasing the port from containment.
+    pub fn clear_interrupt_status(&self, access: &impl ConfigRegionAccess) {
+        self.write(0x08, 1 << 3, access);
+    }
+
+    /// Release the port from containment, after the errors that caused it have been handled (e.g. AER status
+    /// has been cleared, and drivers of the functions below the port have been told about the failure). The port
+    /// then tries to bring its link back up.
+    ///
+    /// A Root Port must finish its internal activity before it can be released, so this waits (for up to 1s) for
+    /// the DPC RP Busy bit to clear.
+    pub fn release(&self, delay: &impl Delay, access: &impl ConfigRegionAccess) -> Result<(), DpcError> {
+        if self.capabilities(access).root_port_extensions() {
+            let mut polls = 0;
+            while self.status(access).root_port_busy() {
+                if polls == RP_BUSY_POLL_LIMIT {
+                    return Err(DpcError::RootPortBusy);
+                }
+                delay.delay_us(RP_BUSY_POLL_INTERVAL_US);
+                polls += 1;
+            }
+        }
+
+        /*
+         * DPC Trigger Status and DPC Interrupt Status are RW1C. Clearing the former releases the port.
+         */
+        self.write(0x08, 1 << 0 | 1 << 3, access);
+        Ok(())
+    }
+
+    /// Get the function that sent the `ERR_FATAL` or `ERR_NONFATAL` message that caused containment. This is only
+    /// valid if the [trigger reason](DpcStatus::trigger_reason) is one of these messages.
+    pub fn error_source(&self, access: &impl ConfigRegionAccess) -> PciAddress {
+        let id = self.read(0x08, access).get_bits(16..32) as u16;
+        PciAddress::from_routing_id(self.address.address.segment(), id)
+    }
+
+    /// Get the RP PIO errors that have been detected, or `None` if the port doesn't implement the RP PIO
+    /// registers.
+    pub fn rp_pio_status(&self, access: &impl ConfigRegionAccess) -> Option<RpPioErrors> {
+        self.read_rp_pio(0x0c, access)
+    }
+
+    /// Clear the RP PIO errors that are set in `errors`. Passing the value returned by
+    /// [`DpcCapability::rp_pio_status`] clears the errors that were seen, without losing any that have been
+    /// detected since. Does nothing if the port doesn't implement the RP PIO registers.
+    pub fn clear_rp_pio_status(&self, errors: RpPioErrors, access: &impl ConfigRegionAccess) {
+        self.write_rp_pio(0x0c, errors, access);
+    }
+
+    /// Get the RP PIO errors that are masked, and so neither trigger containment nor are logged.
+    pub fn rp_pio_mask(&self, access: &impl ConfigRegionAccess) -> Option<RpPioErrors> {
+        self.read_rp_pio(0x10, access)
+    }
+
+    pub fn set_rp_pio_mask(&self, mask: RpPioErrors, access: &impl ConfigRegionAccess) {
+        self.write_rp_pio(0x10, mask, access);
+    }
+
+    /// Get the RP PIO errors that are treated as uncorrectable errors (and so can trigger containment). The others
+    /// are treated as advisory.
+    pub fn rp_pio_severity(&self, access: &impl ConfigRegionAccess) -> Option<RpPioErrors> {
+        self.read_rp_pio(0x14, access)
+    }
+
+    pub fn set_rp_pio_severity(&self, severity: RpPioErrors, access: &impl ConfigRegionAccess) {
+        self.write_rp_pio(0x14, severity, access);
+    }
+
+    /// Get the RP PIO errors that cause a System Error.
+    pub fn rp_pio_system_error(&self, access: &impl ConfigRegionAccess) -> Option<RpPioErrors> {
+        self.read_rp_pio(0x18, access)
+    }
+
+    pub fn set_rp_pio_system_error(&self, errors: RpPioErrors, access: &impl ConfigRegionAccess) {
+        self.write_rp_pio(0x18, errors, access);
+    }
+
+    /// Get the RP PIO errors that are signalled to the CPU as an exception (in a platform-specific way).
+    pub fn rp_pio_exception(&self, access: &impl ConfigRegionAccess) -> Option<RpPioErrors> {
+        self.read_rp_pio(0x1c, access)
+    }
+
+    pub fn set_rp_pio_exception(&self, errors: RpPioErrors, access: &impl ConfigRegionAccess) {
+        self.write_rp_pio(0x1c, errors, access);
+    }
+
+    /// Get the header of the request that caused the RP PIO error pointed to by
+    /// [`DpcStatus::rp_pio_first_error_pointer`], or `None` if the port doesn't implement the RP PIO registers.
+    pub fn rp_pio_header_log(&self, access: &impl ConfigRegionAccess) -> Option<TlpHeader> {
+        if !self.capabilities(access).root_port_extensions() {
+            return None;
+        }
+        Some(TlpHeader([
+            self.read(0x20, access),
+            self.read(0x24, access),
+            self.read(0x28, access),
+            self.read(0x2c, access),
+        ]))
+    }
+
+    /// Get the RP PIO ImpSpec Log, or `None` if the Root Port doesn't implement it.
+    pub fn rp_pio_impspec_log(&self, access: &impl ConfigRegionAccess) -> Option<u32> {
+        if self.capabilities(access).rp_pio_log_size() < 5 {
+            return None;
+        }
+        Some(self.read(0x30, access))
+    }
+
+    /// Get the `index`th dword of the RP PIO TLP Prefix Log, or `None` if the Root Port doesn't implement that
+    /// many.
+    pub fn rp_pio_tlp_prefix_log(&self, index: u8, access: &impl ConfigRegionAccess) -> Option<u32> {
+        if index >= 4 || self.capabilities(access).rp_pio_log_size() < 6 + index {
+            return None;
+        }
+        Some(self.read(0x34 + index as u16 * 4, access))
+    }
+}
+
+/// The DPC Capability register.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct DpcCapabilities(u16);
+
+impl DpcCapabilities {
+    /// The MSI or MSI-X vector used for DPC interrupts.
+    pub fn interrupt_message_number(&self) -> u8 {
+        self.0.get_bits(0..5) as u8
+    }
+
+    /// Does the port (which must be a Root Port) implement the RP PIO registers?
+    pub fn root_port_extensions(&self) -> bool {
+        self.0.get_bit(5)
+    }
+
+    pub fn poisoned_tlp_egress_blocking_supported(&self) -> bool {
+        self.0.get_bit(6)
+    }
+
+    pub fn software_triggering_supported(&self) -> bool {
+        self.0.get_bit(7)
+    }
+
+    /// The number of dwords of RP PIO Header Log, ImpSpec Log and TLP Prefix Log that are implemented.
+    pub fn rp_pio_log_size(&self) -> u8 {
+        self.0.get_bits(8..12) as u8
+    }
+
+    pub fn dl_active_err_cor_signaling_supported(&self) -> bool {
+        self.0.get_bit(12)
+    }
+}
+
+/// The DPC Control register.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct DpcControl(u16);
+
+impl DpcControl {
+    pub fn trigger(&self) -> DpcTrigger {
+        match self.0.get_bits(0..2) {
+            0b00 => DpcTrigger::Disabled,
+            0b01 => DpcTrigger::Fatal,
+            _ => DpcTrigger::NonFatalOrFatal,
+        }
+    }
+
+    pub fn set_trigger(&mut self, trigger: DpcTrigger) {
+        self.0.set_bits(0..2, trigger as u16);
+    }
+
+    /// Does the port complete requests it receives while contained with Unsupported Request (rather than
+    /// Completer Abort)?
+    pub fn completion_control(&self) -> bool {
+        self.0.get_bit(2)
+    }
+
+    pub fn set_completion_control(&mut self, unsupported_request: bool) {
+        self.0.set_bit(2, unsupported_request);
+    }
+
+    pub fn interrupt_enable(&self) -> bool {
+        self.0.get_bit(3)
+    }
+
+    pub fn set_interrupt_enable(&mut self, enabled: bool) {
+        self.0.set_bit(3, enabled);
+    }
+
+    /// Does the port send `ERR_COR` when containment is triggered?
+    pub fn err_cor_enable(&self) -> bool {
+        self.0.get_bit(4)
+    }
+
+    pub fn set_err_cor_enable(&mut self, enabled: bool) {
+        self.0.set_bit(4, enabled);
+    }
+
+    pub fn poisoned_tlp_egress_blocking(&self) -> bool {
+        self.0.get_bit(5)
+    }
+
+    pub fn set_poisoned_tlp_egress_blocking(&mut self, enabled: bool) {
+        self.0.set_bit(5, enabled);
+    }
+
+    /// Does the port send `ERR_COR` when its link becomes active again after containment?
+    pub fn dl_active_err_cor_enable(&self) -> bool {
+        self.0.get_bit(7)
+    }
+
+    pub fn set_dl_active_err_cor_enable(&mut self, enabled: bool) {
+        self.0.set_bit(7, enabled);
+    }
+}
+
+/// The DPC Status register.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct DpcStatus(u16);
+
+impl DpcStatus {
+    /// Is the port's link contained?
+    pub fn trigger_status(&self) -> bool {
+        self.0.get_bit(0)
+    }
+
+    /// Why the port's link was contained. Only valid if [`DpcStatus::trigger_status`] is set.
+    pub fn trigger_reason(&self) -> DpcTriggerReason {
+        match (self.0.get_bits(1..3), self.0.get_bits(5..7)) {
+            (0b00, _) => DpcTriggerReason::UnmaskedUncorrectableError,
+            (0b01, _) => DpcTriggerReason::NonFatalMessage,
+            (0b10, _) => DpcTriggerReason::FatalMessage,
+            (_, 0b00) => DpcTriggerReason::RpPioError,
+            (_, 0b01) => DpcTriggerReason::SoftwareTrigger,
+            _ => DpcTriggerReason::Unknown,
+        }
+    }
+
+    pub fn interrupt_status(&self) -> bool {
+        self.0.get_bit(3)
+    }
+
+    /// Is a Root Port still busy with internal activity after containment? It can't be released until this is
+    /// clear.
+    pub fn root_port_busy(&self) -> bool {
+        self.0.get_bit(4)
+    }
+
+    /// The bit in the RP PIO Status register of the error that was detected first.
+    pub fn rp_pio_first_error_pointer(&self) -> u8 {
+        self.0.get_bits(8..13) as u8
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod tests {
+    use super::*;
+    use crate::{
+        access::{MockConfigSpace, MockDelay, MockFunction},
+        capability::PciExtendedCapability,
+        EndpointHeader,
+    };
+
+    const ADDRESS: PciAddress = PciAddress(0x0000_00e0);
+
+    /// A Downstream Port with a DPC capability with the given DPC Capability, Control and Status registers.
+    fn mock_dpc(capabilities: u16, control: u16, status: u16) -> MockConfigSpace {
+        let mut function = MockFunction::bridge(0, 1, 1);
+        function.add_extended_capability(0x100, 0x001D, 1);
+        function.set(0x104, (control as u32) << 16 | capabilities as u32);
+        function.set_write_mask(0x104, 0x00ff_0000);
+        function.set(0x108, status as u32);
+        function.set_rw1c_mask(0x108, 0x0000_0009);
+        for offset in (0x10c..0x120).step_by(4) {
+            function.set_write_mask(offset, 0xffff_ffff);
+        }
+        let mut config = MockConfigSpace::new();
+        config.add_function(ADDRESS, function);
+        config
+    }
+
+    fn dpc(config: &MockConfigSpace) -> DpcCapability {
+        EndpointHeader(ADDRESS)
+            .extended_capabilities(config)
+            .find_map(|capability| match capability {
+                PciExtendedCapability::DownstreamPortContainment(dpc) => Some(dpc),
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn enable() {
+        // Interrupts, ERR_COR, Poisoned TLP Egress Blocking and DL_Active ERR_COR enabled
+        let config = mock_dpc(0x10e3, 0x00b8, 0x0000);
+        let dpc = dpc(&config);
+
+        dpc.enable(DpcTrigger::Fatal, &config);
+        assert_eq!(dpc.control(&config), DpcControl(0x00b9));
+        dpc.enable(DpcTrigger::NonFatalOrFatal, &config);
+        assert_eq!(dpc.control(&config).trigger(), DpcTrigger::NonFatalOrFatal);
+        assert_eq!(dpc.control(&config), DpcControl(0x00ba));
+        dpc.disable(&config);
+        assert_eq!(dpc.control(&config), DpcControl(0x00b8));
+        assert_eq!(dpc.capabilities(&config), DpcCapabilities(0x10e3));
+    }
+
+    #[test]
+    fn release() {
+        // Contained, with an interrupt pending
+        let mut config = mock_dpc(0x0000, 0x0001, 0x0009);
+        config.function(ADDRESS).unwrap().set_rw1c_mask(0x108, 0xffff_ffff);
+        config.function(ADDRESS).unwrap().set(0x108, 0x0300_0169);
+        let dpc = dpc(&config);
+        let delay = MockDelay::new();
+
+        assert!(dpc.is_contained(&config));
+        assert_eq!(dpc.release(&delay, &config), Ok(()));
+        assert!(!dpc.is_contained(&config));
+        assert!(!dpc.status(&config).interrupt_status());
+
+        /*
+         * Only DPC Trigger Status and DPC Interrupt Status are written, even if other bits are writable.
+         */
+        assert_eq!(unsafe { config.read(ADDRESS, 0x108) }, 0x0300_0160);
+        assert!(delay.delays().is_empty());
+    }
+
+    #[test]
+    fn release_root_port_busy() {
+        // A Root Port with RP Extensions for DPC, contained and still busy
+        let config = mock_dpc(0x0420, 0x0001, 0x0011);
+        let dpc = dpc(&config);
+        let delay = MockDelay::new();
+
+        assert_eq!(dpc.release(&delay, &config), Err(DpcError::RootPortBusy));
+        assert_eq!(delay.delays().len(), RP_BUSY_POLL_LIMIT as usize);
+        assert!(delay.delays().iter().all(|&delay| delay == RP_BUSY_POLL_INTERVAL_US));
+        assert!(dpc.is_contained(&config));
+    }
+
+    #[test]
+    fn trigger_reasons() {
+        assert_eq!(DpcStatus(0x0001).trigger_reason(), DpcTriggerReason::UnmaskedUncorrectableError);
+        assert_eq!(DpcStatus(0x0003).trigger_reason(), DpcTriggerReason::NonFatalMessage);
+        assert_eq!(DpcStatus(0x0005).trigger_reason(), DpcTriggerReason::FatalMessage);
+        assert_eq!(DpcStatus(0x0007).trigger_reason(), DpcTriggerReason::RpPioError);
+        assert_eq!(DpcStatus(0x0027).trigger_reason(), DpcTriggerReason::SoftwareTrigger);
+        assert_eq!(DpcStatus(0x0047).trigger_reason(), DpcTriggerReason::Unknown);
+
+        /*
+         * The Trigger Reason Extension is only used when the Trigger Reason says so.
+         */
+        assert_eq!(DpcStatus(0x0025).trigger_reason(), DpcTriggerReason::FatalMessage);
+    }
+
+    #[test]
+    fn error_source() {
+        let mut config = mock_dpc(0x0000, 0x0002, 0x0003);
+        config.function(ADDRESS).unwrap().set(0x108, 0x0208_0003);
+        assert_eq!(dpc(&config).error_source(&config), PciAddress::new(0, 2, 1, 0));
+    }
+
+    #[test]
+    fn rp_pio_registers() {
+        let config = mock_dpc(0x0000, 0x0000, 0x0000);
+        let port = dpc(&config);
+        port.set_rp_pio_mask(RpPioErrors::all(), &config);
+        assert_eq!(unsafe { config.read(ADDRESS, 0x110) }, 0);
+        assert_eq!(port.rp_pio_mask(&config), None);
+        assert_eq!(port.rp_pio_header_log(&config), None);
+        assert_eq!(port.rp_pio_impspec_log(&config), None);
+
+        // RP Extensions for DPC, with a Header Log and ImpSpec Log
+        let config = mock_dpc(0x0520, 0x0000, 0x0000);
+        let root_port = dpc(&config);
+        root_port.set_rp_pio_mask(RpPioErrors::MEMORY_COMPLETION_TIMEOUT, &config);
+        assert_eq!(root_port.rp_pio_mask(&config), Some(RpPioErrors::MEMORY_COMPLETION_TIMEOUT));
+        assert_eq!(root_port.rp_pio_status(&config), Some(RpPioErrors::empty()));
+        assert_eq!(root_port.rp_pio_header_log(&config), Some(TlpHeader([0; 4])));
+        assert_eq!(root_port.rp_pio_impspec_log(&config), Some(0));
+        assert_eq!(root_port.rp_pio_tlp_prefix_log(0, &config), None);
+    }
+}
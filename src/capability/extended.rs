@@ -0,0 +1,210 @@
+use crate::{
+    capability::{AcsCapability, AerCapability, DpcCapability, PciCapabilityAddress, SriovCapability},
+    ConfigRegionAccess,
+    PciAddress,
+};
+use bit_field::BitField;
+
+/// PCI Express extended capabilities, which live in the extended configuration space (from offset `0x100`).
+#[derive(Clone, Debug)]
+pub enum PciExtendedCapability {
+    /// Advanced Error Reporting capability, Cap ID = `0x0001`
+    AdvancedErrorReporting(AerCapability),
+    /// Virtual Channel capability, Cap ID = `0x0002` or `0x0009`
+    VirtualChannel(PciCapabilityAddress),
+    /// Device Serial Number capability, Cap ID = `0x0003`
+    DeviceSerialNumber(PciCapabilityAddress),
+    /// Power Budgeting capability, Cap ID = `0x0004`
+    PowerBudgeting(PciCapabilityAddress),
+    /// Root Complex Link Declaration capability, Cap ID = `0x0005`
+    RootComplexLinkDeclaration(PciCapabilityAddress),
+    /// Root Complex Internal Link Control capability, Cap ID = `0x0006`
+    RootComplexInternalLinkControl(PciCapabilityAddress),
+    /// Root Complex Event Collector Endpoint Association capability, Cap ID = `0x0007`
+    RootComplexEventCollectorEndpointAssociation(PciCapabilityAddress),
+    /// Multi-Function Virtual Channel capability, Cap ID = `0x0008`
+    MultiFunctionVirtualChannel(PciCapabilityAddress),
+    /// Root Complex Register Block capability, Cap ID = `0x000A`
+    RootComplexRegisterBlock(PciCapabilityAddress),
+    /// Vendor-specific extended capability, Cap ID = `0x000B`
+    Vendor(PciCapabilityAddress),
+    /// Access Control Services capability, Cap ID = `0x000D`
+    AccessControlServices(AcsCapability),
+    /// Alternative Routing-ID Interpretation capability, Cap ID = `0x000E`
+    AlternativeRoutingId(PciCapabilityAddress),
+    /// Address Translation Services capability, Cap ID = `0x000F`
+    AddressTranslationServices(PciCapabilityAddress),
+    /// Single Root I/O Virtualization capability, Cap ID = `0x0010`
+    SingleRootIoVirtualization(SriovCapability),
+    /// Multi-Root I/O Virtualization capability, Cap ID = `0x0011`
+    MultiRootIoVirtualization(PciCapabilityAddress),
+    /// Multicast capability, Cap ID = `0x0012`
+    Multicast(PciCapabilityAddress),
+    /// Page Request Interface capability, Cap ID = `0x0013`
+    PageRequest(PciCapabilityAddress),
+    /// Resizable BAR capability, Cap ID = `0x0015`
+    ResizableBar(PciCapabilityAddress),
+    /// Dynamic Power Allocation capability, Cap ID = `0x0016`
+    DynamicPowerAllocation(PciCapabilityAddress),
+    /// TLP Processing Hints Requester capability, Cap ID = `0x0017`
+    TphRequester(PciCapabilityAddress),
+    /// Latency Tolerance Reporting capability, Cap ID = `0x0018`
+    LatencyToleranceReporting(PciCapabilityAddress),
+    /// Secondary PCI Express capability, Cap ID = `0x0019`
+    SecondaryPciExpress(PciCapabilityAddress),
+    /// Process Address Space ID capability, Cap ID = `0x001B`
+    ProcessAddressSpaceId(PciCapabilityAddress),
+    /// Downstream Port Containment capability, Cap ID = `0x001D`
+    DownstreamPortContainment(DpcCapability),
+    /// L1 PM Substates capability, Cap ID = `0x001E`
+    L1PmSubstates(PciCapabilityAddress),
+    /// Precision Time Measurement capability, Cap ID = `0x001F`
+    PrecisionTimeMeasurement(PciCapabilityAddress),
+    /// Data Link Feature capability, Cap ID = `0x0025`
+    DataLinkFeature(PciCapabilityAddress),
+    /// Physical Layer 16.0 GT/s capability, Cap ID = `0x0026`
+    PhysicalLayer16GT(PciCapabilityAddress),
+    /// Unknown extended capability
+    Unknown { address: PciCapabilityAddress, id: u16 },
+}
+
+impl PciExtendedCapability {
+    fn parse(id: u16, address: PciCapabilityAddress) -> Option<PciExtendedCapability> {
+        match id {
+            0x0000 => None, // null capability
+            0x0001 => Some(PciExtendedCapability::AdvancedErrorReporting(AerCapability::new(address))),
+            0x0002 | 0x0009 => Some(PciExtendedCapability::VirtualChannel(address)),
+            0x0003 => Some(PciExtendedCapability::DeviceSerialNumber(address)),
+            0x0004 => Some(PciExtendedCapability::PowerBudgeting(address)),
+            0x0005 => Some(PciExtendedCapability::RootComplexLinkDeclaration(address)),
+            0x0006 => Some(PciExtendedCapability::RootComplexInternalLinkControl(address)),
+            0x0007 => Some(PciExtendedCapability::RootComplexEventCollectorEndpointAssociation(address)),
+            0x0008 => Some(PciExtendedCapability::MultiFunctionVirtualChannel(address)),
+            0x000A => Some(PciExtendedCapability::RootComplexRegisterBlock(address)),
+            0x000B => Some(PciExtendedCapability::Vendor(address)),
+            0x000D => Some(PciExtendedCapability::AccessControlServices(AcsCapability::new(address))),
+            0x000E => Some(PciExtendedCapability::AlternativeRoutingId(address)),
+            0x000F => Some(PciExtendedCapability::AddressTranslationServices(address)),
+            0x0010 => Some(PciExtendedCapability::SingleRootIoVirtualization(SriovCapability::new(address))),
+            0x0011 => Some(PciExtendedCapability::MultiRootIoVirtualization(address)),
+            0x0012 => Some(PciExtendedCapability::Multicast(address)),
+            0x0013 => Some(PciExtendedCapability::PageRequest(address)),
+            0x0015 => Some(PciExtendedCapability::ResizableBar(address)),
+            0x0016 => Some(PciExtendedCapability::DynamicPowerAllocation(address)),
+            0x0017 => Some(PciExtendedCapability::TphRequester(address)),
+            0x0018 => Some(PciExtendedCapability::LatencyToleranceReporting(address)),
+            0x0019 => Some(PciExtendedCapability::SecondaryPciExpress(address)),
+            0x001B => Some(PciExtendedCapability::ProcessAddressSpaceId(address)),
+            0x001D => Some(PciExtendedCapability::DownstreamPortContainment(DpcCapability::new(address))),
+            0x001E => Some(PciExtendedCapability::L1PmSubstates(address)),
+            0x001F => Some(PciExtendedCapability::PrecisionTimeMeasurement(address)),
+            0x0025 => Some(PciExtendedCapability::DataLinkFeature(address)),
+            0x0026 => Some(PciExtendedCapability::PhysicalLayer16GT(address)),
+            _ => Some(PciExtendedCapability::Unknown { address, id }),
+        }
+    }
+
+    /// The location of the capability's header.
+    pub fn address(&self) -> &PciCapabilityAddress {
+        match self {
+            PciExtendedCapability::AdvancedErrorReporting(capability) => capability.address(),
+            PciExtendedCapability::AccessControlServices(capability) => capability.address(),
+            PciExtendedCapability::DownstreamPortContainment(capability) => capability.address(),
+            PciExtendedCapability::SingleRootIoVirtualization(capability) => capability.address(),
+            PciExtendedCapability::VirtualChannel(address)
+            | PciExtendedCapability::DeviceSerialNumber(address)
+            | PciExtendedCapability::PowerBudgeting(address)
+            | PciExtendedCapability::RootComplexLinkDeclaration(address)
+            | PciExtendedCapability::RootComplexInternalLinkControl(address)
+            | PciExtendedCapability::RootComplexEventCollectorEndpointAssociation(address)
+            | PciExtendedCapability::MultiFunctionVirtualChannel(address)
+            | PciExtendedCapability::RootComplexRegisterBlock(address)
+            | PciExtendedCapability::Vendor(address)
+            | PciExtendedCapability::AlternativeRoutingId(address)
+            | PciExtendedCapability::AddressTranslationServices(address)
+            | PciExtendedCapability::MultiRootIoVirtualization(address)
+            | PciExtendedCapability::Multicast(address)
+            | PciExtendedCapability::PageRequest(address)
+            | PciExtendedCapability::ResizableBar(address)
+            | PciExtendedCapability::DynamicPowerAllocation(address)
+            | PciExtendedCapability::TphRequester(address)
+            | PciExtendedCapability::LatencyToleranceReporting(address)
+            | PciExtendedCapability::SecondaryPciExpress(address)
+            | PciExtendedCapability::ProcessAddressSpaceId(address)
+            | PciExtendedCapability::L1PmSubstates(address)
+            | PciExtendedCapability::PrecisionTimeMeasurement(address)
+            | PciExtendedCapability::DataLinkFeature(address)
+            | PciExtendedCapability::PhysicalLayer16GT(address)
+            | PciExtendedCapability::Unknown { address, .. } => address,
+        }
+    }
+
+    /// The version of the capability's structure, from its header.
+    pub fn version(&self, access: &impl ConfigRegionAccess) -> u8 {
+        let address = self.address();
+        unsafe { access.read(address.address, address.offset) }.get_bits(16..20) as u8
+    }
+}
+
+/// Iterates over the extended capabilities of a function. Each extended capability starts with a header of the
+/// form:
+/// ```ignore
+///     32               20      16                               0
+///      +----------------+-------+-------------------------------+
+///      |  Next pointer  |Version|         Capability ID         |
+///      +----------------+-------+-------------------------------+
+/// ```
+/// and the first one is always at offset `0x100`. Functions without extended capabilities (including
+/// conventional PCI functions, and functions accessed through a mechanism that can't reach the extended
+/// configuration space) have a header of either `0` or `0xffffffff` there, which ends the iteration.
+///
+/// As the list is built by the device, the iterator does not trust it: it stops at pointers that are not in the
+/// extended configuration space, and at pointers to capabilities it has already visited, so a list that loops
+/// back on itself can't make it run forever.
+pub struct ExtendedCapabilityIterator<'a, T: ConfigRegionAccess> {
+    address: PciAddress,
+    offset: u16,
+    /// Bitmap of the dwords of the extended configuration space that have already been visited
+    visited: [u64; 15],
+    access: &'a T,
+}
+
+impl<'a, T: ConfigRegionAccess> ExtendedCapabilityIterator<'a, T> {
+    pub(crate) fn new(address: PciAddress, access: &'a T) -> ExtendedCapabilityIterator<'a, T> {
+        ExtendedCapabilityIterator { address, offset: 0x100, visited: [0; 15], access }
+    }
+}
+
+impl<'a, T: ConfigRegionAccess> Iterator for ExtendedCapabilityIterator<'a, T> {
+    type Item = PciExtendedCapability;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.offset < 0x100 || self.offset >= 0x1000 {
+                return None;
+            }
+
+            let dword = (self.offset as usize - 0x100) / 4;
+            if self.visited[dword / 64].get_bit(dword % 64) {
+                return None;
+            }
+            self.visited[dword / 64].set_bit(dword % 64, true);
+
+            let data = unsafe { self.access.read(self.address, self.offset) };
+            if data == 0 || data == 0xffffffff {
+                return None;
+            }
+
+            let id = data.get_bits(0..16) as u16;
+            let next_ptr = data.get_bits(20..32) as u16 & !0x3;
+            let cap = PciExtendedCapability::parse(
+                id,
+                PciCapabilityAddress { address: self.address, offset: self.offset },
+            );
+            self.offset = next_ptr;
+            if let Some(cap) = cap {
+                return Some(cap);
+            }
+        }
+    }
+}
@@ -2,9 +2,80 @@ use crate::{ConfigRegionAccess, PciAddress};
 use bit_field::BitField;
 use core::fmt::Formatter;
 
+mod acs;
+mod aer;
+mod dpc;
+mod extended;
 mod msi;
+mod msix;
+mod pci_express;
+mod power_management;
+mod sriov;
+mod vpd;
 
+pub use acs::{AcsCapability, AcsFlags};
+pub use aer::{
+    AerCapability,
+    AerControl,
+    CorrectableErrors,
+    RootErrorCommand,
+    RootErrorStatus,
+    TlpHeader,
+    UncorrectableErrors,
+};
+pub use dpc::{
+    DpcCapabilities,
+    DpcCapability,
+    DpcControl,
+    DpcError,
+    DpcStatus,
+    DpcTrigger,
+    DpcTriggerReason,
+    RpPioErrors,
+};
+pub use extended::{ExtendedCapabilityIterator, PciExtendedCapability};
 pub use msi::{MsiCapability, MultipleMessageSupport, TriggerMode};
+pub use msix::{MsiXCapability, MsiXPendingBitArray, MsiXTable};
+pub use pci_express::{
+    Aspm,
+    CompletionTimeout,
+    DeviceCapabilities,
+    DeviceCapabilities2,
+    DeviceControl,
+    DeviceControl2,
+    DevicePortType,
+    DeviceStatus,
+    IndicatorState,
+    LinkCapabilities,
+    LinkCapabilities2,
+    LinkControl,
+    LinkControl2,
+    LinkControl2Error,
+    LinkSpeed,
+    LinkStatus,
+    LinkStatus2,
+    Obff,
+    PciExpressCapability,
+    RootControl,
+    RootStatus,
+    SlotCapabilities,
+    SlotControl,
+    SlotStatus,
+    TransferSize,
+};
+pub use power_management::{Delay, PmeSupport, PowerManagementCapability, PowerState, PowerStateError};
+pub use sriov::{SriovCapabilities, SriovCapability, SriovControl, SriovError};
+pub use vpd::{
+    Vpd,
+    VpdCapability,
+    VpdError,
+    VpdKeyword,
+    VpdKeywordIterator,
+    VpdKeywords,
+    VpdParseError,
+    VpdResource,
+    VpdResourceIterator,
+};
 
 #[derive(Clone)]
 pub struct PciCapabilityAddress {
@@ -22,11 +93,11 @@ impl core::fmt::Debug for PciCapabilityAddress {
 #[derive(Clone, Debug)]
 pub enum PciCapability {
     /// Power management capability, Cap ID = `0x01`
-    PowerManagement(PciCapabilityAddress),
+    PowerManagement(PowerManagementCapability),
     /// Accelerated graphics port capability, Cap ID = `0x02`
     AcceleratedGraphicsPort(PciCapabilityAddress),
     /// Vital product data capability, Cap ID = `0x3`
-    VitalProductData(PciCapabilityAddress),
+    VitalProductData(VpdCapability),
     /// Slot identification capability, Cap ID = `0x04`
     SlotIdentification(PciCapabilityAddress),
     /// Message signalling interrupts capability, Cap ID = `0x05`
@@ -50,9 +121,9 @@ pub enum PciCapability {
     /// AGP Target PCI-PCI bridge capability, Cap ID = `0x0E`
     AGP3(PciCapabilityAddress),
     /// PCI Express capability, Cap ID = `0x10`
-    PciExpress(PciCapabilityAddress),
+    PciExpress(PciExpressCapability),
     /// MSI-X capability, Cap ID = `0x11`
-    MsiX(PciCapabilityAddress),
+    MsiX(MsiXCapability),
     /// Unknown capability
     Unknown {
         address: PciCapabilityAddress,
@@ -61,12 +132,17 @@ pub enum PciCapability {
 }
 
 impl PciCapability {
-    fn parse(id: u8, address: PciCapabilityAddress, extension: u16) -> Option<PciCapability> {
+    fn parse(
+        id: u8,
+        address: PciCapabilityAddress,
+        extension: u16,
+        access: &impl ConfigRegionAccess,
+    ) -> Option<PciCapability> {
         match id {
             0x00 => None, // null capability
-            0x01 => Some(PciCapability::PowerManagement(address)),
+            0x01 => Some(PciCapability::PowerManagement(PowerManagementCapability::new(address, extension))),
             0x02 => Some(PciCapability::AcceleratedGraphicsPort(address)),
-            0x03 => Some(PciCapability::VitalProductData(address)),
+            0x03 => Some(PciCapability::VitalProductData(VpdCapability::new(address))),
             0x04 => Some(PciCapability::SlotIdentification(address)),
             0x05 => Some(PciCapability::Msi(MsiCapability::new(address, extension))),
             0x06 => Some(PciCapability::CompactPCIHotswap(address)),
@@ -78,8 +154,8 @@ impl PciCapability {
             0x0C => Some(PciCapability::PciHotPlugControl(address)),
             0x0D => Some(PciCapability::BridgeSubsystemVendorId(address)),
             0x0E => Some(PciCapability::AGP3(address)),
-            0x10 => Some(PciCapability::PciExpress(address)),
-            0x11 => Some(PciCapability::MsiX(address)),
+            0x10 => Some(PciCapability::PciExpress(PciExpressCapability::new(address, extension))),
+            0x11 => Some(PciCapability::MsiX(MsiXCapability::new(address, extension, access))),
             _ => Some(PciCapability::Unknown { address, id }),
         }
     }
@@ -124,6 +200,7 @@ impl<'a, T: ConfigRegionAccess> Iterator for CapabilityIterator<'a, T> {
                     offset: self.offset,
                 },
                 extension,
+                self.access,
             );
             self.offset = next_ptr as u16;
             if let Some(cap) = cap {
@@ -0,0 +1,349 @@
+use crate::{access::Mmio, capability::PciCapabilityAddress, ConfigRegionAccess};
+use bit_field::BitField;
+
+/// The MSI-X capability. Unlike MSI, the message address and data of each vector are not in the configuration
+/// space, but in the MSI-X Table, which lives in memory mapped by one of the function's BARs. A second structure,
+/// the Pending Bit Array, tracks which vectors have a pending message.
+#[derive(Debug, Clone)]
+pub struct MsiXCapability {
+    address: PciCapabilityAddress,
+    table_size: u16,
+    table: u32,
+    pba: u32,
+}
+
+impl MsiXCapability {
+    pub(crate) fn new(
+        address: PciCapabilityAddress,
+        control: u16,
+        access: &impl ConfigRegionAccess,
+    ) -> MsiXCapability {
+        let table = unsafe { access.read(address.address, address.offset + 0x4) };
+        let pba = unsafe { access.read(address.address, address.offset + 0x8) };
+        MsiXCapability { address, table_size: control.get_bits(0..11) + 1, table, pba }
+    }
+
+    /// The number of vectors in the MSI-X Table.
+    #[inline]
+    pub fn table_size(&self) -> u16 {
+        self.table_size
+    }
+
+    /// Which BAR the MSI-X Table is mapped by.
+    #[inline]
+    pub fn table_bar(&self) -> u8 {
+        self.table.get_bits(0..3) as u8
+    }
+
+    /// The offset of the MSI-X Table from the start of the BAR it is mapped by.
+    #[inline]
+    pub fn table_offset(&self) -> u32 {
+        self.table & !0x7
+    }
+
+    /// Which BAR the Pending Bit Array is mapped by.
+    #[inline]
+    pub fn pba_bar(&self) -> u8 {
+        self.pba.get_bits(0..3) as u8
+    }
+
+    /// The offset of the Pending Bit Array from the start of the BAR it is mapped by.
+    #[inline]
+    pub fn pba_offset(&self) -> u32 {
+        self.pba & !0x7
+    }
+
+    /// Is MSI-X capability enabled?
+    pub fn is_enabled(&self, access: &impl ConfigRegionAccess) -> bool {
+        let reg = unsafe { access.read(self.address.address, self.address.offset) };
+        reg.get_bit(31)
+    }
+
+    /// Enable or disable MSI-X capability
+    pub fn set_enabled(&self, enabled: bool, access: &impl ConfigRegionAccess) {
+        let mut reg = unsafe { access.read(self.address.address, self.address.offset) };
+        reg.set_bit(31, enabled);
+        unsafe { access.write(self.address.address, self.address.offset, reg) };
+    }
+
+    /// Are all of the function's vectors masked, regardless of their own mask bits?
+    pub fn is_function_masked(&self, access: &impl ConfigRegionAccess) -> bool {
+        let reg = unsafe { access.read(self.address.address, self.address.offset) };
+        reg.get_bit(30)
+    }
+
+    /// Mask or unmask all of the function's vectors. While the function is masked, messages are recorded in the
+    /// Pending Bit Array instead of being sent.
+    pub fn set_function_masked(&self, masked: bool, access: &impl ConfigRegionAccess) {
+        let mut reg = unsafe { access.read(self.address.address, self.address.offset) };
+        reg.set_bit(30, masked);
+        unsafe { access.write(self.address.address, self.address.offset, reg) };
+    }
+
+    /// Get the MSI-X Table, given the address at which the BAR returned by [`MsiXCapability::table_bar`] is
+    /// mapped.
+    ///
+    /// # Safety
+    /// The whole BAR must be accessible through `mmio` from `bar_base`.
+    pub unsafe fn table<'a, M: Mmio>(&self, bar_base: usize, mmio: &'a M) -> MsiXTable<'a, M> {
+        MsiXTable { base: bar_base + self.table_offset() as usize, size: self.table_size, mmio }
+    }
+
+    /// Get the Pending Bit Array, given the address at which the BAR returned by [`MsiXCapability::pba_bar`] is
+    /// mapped.
+    ///
+    /// # Safety
+    /// The whole BAR must be accessible through `mmio` from `bar_base`.
+    pub unsafe fn pending_bit_array<'a, M: Mmio>(
+        &self,
+        bar_base: usize,
+        mmio: &'a M,
+    ) -> MsiXPendingBitArray<'a, M> {
+        MsiXPendingBitArray { base: bar_base + self.pba_offset() as usize, size: self.table_size, mmio }
+    }
+}
+
+/// The MSI-X Table. Each vector has an entry of the form:
+/// ```ignore
+///     32                                                              0
+///      +--------------------------------------------------------------+
+///      |                  Message Address (lower 32)                  | 0x0
+///      +--------------------------------------------------------------+
+///      |                  Message Address (upper 32)                  | 0x4
+///      +--------------------------------------------------------------+
+///      |                         Message Data                         | 0x8
+///      +--------------------------------------------------------------+
+///      |                        Vector Control                        | 0xc
+///      +--------------------------------------------------------------+
+/// ```
+///
+/// ### Panics
+/// All methods that take a vector panic if it is not less than [`MsiXTable::len`].
+pub struct MsiXTable<'a, M: Mmio> {
+    base: usize,
+    size: u16,
+    mmio: &'a M,
+}
+
+impl<'a, M: Mmio> MsiXTable<'a, M> {
+    /// The number of vectors in the table.
+    pub fn len(&self) -> u16 {
+        self.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    fn entry_address(&self, vector: u16) -> usize {
+        assert!(vector < self.size, "MSI-X vector out of range");
+        self.base + vector as usize * 16
+    }
+
+    /// Get the message address and data of a vector.
+    pub fn entry(&self, vector: u16) -> (u64, u32) {
+        let entry = self.entry_address(vector);
+        unsafe {
+            let mut address = self.mmio.read32(entry) as u64;
+            address.set_bits(32..64, self.mmio.read32(entry + 0x4) as u64);
+            (address, self.mmio.read32(entry + 0x8))
+        }
+    }
+
+    /// Set where a vector's messages are sent, and the data they carry. The vector should be masked while this
+    /// is done.
+    pub fn set_entry(&self, vector: u16, address: u64, data: u32) {
+        let entry = self.entry_address(vector);
+        unsafe {
+            self.mmio.write32(entry, address.get_bits(0..32) as u32);
+            self.mmio.write32(entry + 0x4, address.get_bits(32..64) as u32);
+            self.mmio.write32(entry + 0x8, data);
+        }
+    }
+
+    /// Is a vector masked?
+    pub fn is_masked(&self, vector: u16) -> bool {
+        let entry = self.entry_address(vector);
+        unsafe { self.mmio.read32(entry + 0xc) }.get_bit(0)
+    }
+
+    /// Mask or unmask a vector. While a vector is masked, its messages are recorded in the Pending Bit Array
+    /// instead of being sent.
+    pub fn set_masked(&self, vector: u16, masked: bool) {
+        let entry = self.entry_address(vector);
+        unsafe {
+            let mut control = self.mmio.read32(entry + 0xc);
+            control.set_bit(0, masked);
+            self.mmio.write32(entry + 0xc, control);
+        }
+    }
+}
+
+/// The MSI-X Pending Bit Array, which has a bit for each vector that is set while it has a message pending.
+///
+/// ### Panics
+/// All methods that take a vector panic if it is not less than the size of the MSI-X Table.
+pub struct MsiXPendingBitArray<'a, M: Mmio> {
+    base: usize,
+    size: u16,
+    mmio: &'a M,
+}
+
+impl<'a, M: Mmio> MsiXPendingBitArray<'a, M> {
+    /// Does a vector have a message pending?
+    pub fn is_pending(&self, vector: u16) -> bool {
+        assert!(vector < self.size, "MSI-X vector out of range");
+        let dword = unsafe { self.mmio.read32(self.base + (vector as usize / 32) * 4) };
+        dword.get_bit(vector as usize % 32)
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod tests {
+    use super::*;
+    use crate::{
+        access::{MockConfigSpace, MockFunction},
+        capability::PciCapability,
+        EndpointHeader,
+        PciAddress,
+    };
+    use alloc::{vec, vec::Vec};
+    use core::cell::RefCell;
+
+    const ADDRESS: PciAddress = PciAddress(0x0000_0100);
+    const BAR_BASE: usize = 0xfe00_0000;
+
+    /// An `Mmio` backed by a buffer covering a 16KiB BAR.
+    struct BufferMmio {
+        buffer: RefCell<Vec<u32>>,
+    }
+
+    impl BufferMmio {
+        fn new() -> BufferMmio {
+            BufferMmio { buffer: RefCell::new(vec![0; 0x4000 / 4]) }
+        }
+
+        fn get(&self, offset: usize) -> u32 {
+            self.buffer.borrow()[offset / 4]
+        }
+
+        fn set(&self, offset: usize, value: u32) {
+            self.buffer.borrow_mut()[offset / 4] = value;
+        }
+    }
+
+    impl Mmio for BufferMmio {
+        unsafe fn read32(&self, address: usize) -> u32 {
+            assert_eq!(address & 0x3, 0);
+            self.get(address - BAR_BASE)
+        }
+
+        unsafe fn write32(&self, address: usize, value: u32) {
+            assert_eq!(address & 0x3, 0);
+            self.set(address - BAR_BASE, value);
+        }
+    }
+
+    /// A function with an MSI-X capability of 8 vectors, with the table in BAR 2 at `0x2000` and the PBA in BAR 4
+    /// at `0x3000`.
+    fn mock_msix() -> MockConfigSpace {
+        let mut function = MockFunction::endpoint();
+        function.add_capability(0x50, 0x11);
+        function.set(0x50, function.get(0x50) | 0x0007 << 16);
+        function.set_write_mask(0x50, 0xc000_0000);
+        function.set(0x54, 0x0000_2002);
+        function.set(0x58, 0x0000_3004);
+        let mut config = MockConfigSpace::new();
+        config.add_function(ADDRESS, function);
+        config
+    }
+
+    fn msix(config: &MockConfigSpace) -> MsiXCapability {
+        EndpointHeader(ADDRESS)
+            .capabilities(config)
+            .find_map(|capability| match capability {
+                PciCapability::MsiX(msix) => Some(msix),
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn table_location() {
+        let config = mock_msix();
+        let msix = msix(&config);
+
+        assert_eq!(msix.table_size(), 8);
+        assert_eq!((msix.table_bar(), msix.table_offset()), (2, 0x2000));
+        assert_eq!((msix.pba_bar(), msix.pba_offset()), (4, 0x3000));
+    }
+
+    #[test]
+    fn message_control() {
+        let config = mock_msix();
+        let msix = msix(&config);
+        let header = unsafe { config.read(ADDRESS, 0x50) };
+
+        msix.set_function_masked(true, &config);
+        msix.set_enabled(true, &config);
+        assert!(msix.is_enabled(&config));
+        assert!(msix.is_function_masked(&config));
+        assert_eq!(unsafe { config.read(ADDRESS, 0x50) }, header | 0xc000_0000);
+
+        msix.set_function_masked(false, &config);
+        assert!(msix.is_enabled(&config));
+        assert!(!msix.is_function_masked(&config));
+        assert_eq!(unsafe { config.read(ADDRESS, 0x50) }, header | 0x8000_0000);
+
+        msix.set_enabled(false, &config);
+        assert_eq!(unsafe { config.read(ADDRESS, 0x50) }, header);
+    }
+
+    #[test]
+    fn table_entries() {
+        let config = mock_msix();
+        let msix = msix(&config);
+        let mmio = BufferMmio::new();
+        let table = unsafe { msix.table(BAR_BASE, &mmio) };
+        assert_eq!(table.len(), 8);
+
+        table.set_entry(3, 0x1_fee0_0000, 0x4041);
+        assert_eq!(mmio.get(0x2030), 0xfee0_0000);
+        assert_eq!(mmio.get(0x2034), 0x0000_0001);
+        assert_eq!(mmio.get(0x2038), 0x0000_4041);
+        assert_eq!(table.entry(3), (0x1_fee0_0000, 0x4041));
+        assert_eq!(table.entry(2), (0, 0));
+
+        /*
+         * The reserved bits of Vector Control are preserved.
+         */
+        mmio.set(0x207c, 0xabcd_0000);
+        table.set_masked(7, true);
+        assert!(table.is_masked(7));
+        assert_eq!(mmio.get(0x207c), 0xabcd_0001);
+        table.set_masked(7, false);
+        assert!(!table.is_masked(7));
+        assert_eq!(mmio.get(0x207c), 0xabcd_0000);
+    }
+
+    #[test]
+    fn pending_bit_array() {
+        let config = mock_msix();
+        let msix = msix(&config);
+        let mmio = BufferMmio::new();
+        let pba = unsafe { msix.pending_bit_array(BAR_BASE, &mmio) };
+
+        mmio.set(0x3000, 0x0000_0044);
+        assert!(pba.is_pending(2));
+        assert!(pba.is_pending(6));
+        assert!(!pba.is_pending(7));
+    }
+
+    #[test]
+    #[should_panic(expected = "MSI-X vector out of range")]
+    fn vector_out_of_range() {
+        let config = mock_msix();
+        let mmio = BufferMmio::new();
+        unsafe { msix(&config).table(BAR_BASE, &mmio) }.set_masked(8, true);
+    }
+}
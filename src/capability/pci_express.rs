@@ -0,0 +1,1557 @@
+use crate::{capability::PciCapabilityAddress, ConfigRegionAccess};
+use bit_field::BitField;
+
+/// The type of a PCI Express function, and where it sits in the hierarchy.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DevicePortType {
+    Endpoint,
+    LegacyEndpoint,
+    RootComplexIntegratedEndpoint,
+    RootComplexEventCollector,
+    RootPort,
+    /// Upstream port of a switch
+    UpstreamPort,
+    /// Downstream port of a switch
+    DownstreamPort,
+    /// A bridge with a PCI Express primary side, and a conventional PCI or PCI-X secondary side
+    PcieToPciBridge,
+    /// A bridge with a conventional PCI or PCI-X primary side, and a PCI Express secondary side
+    PciToPcieBridge,
+    Unknown(u8),
+}
+
+impl From<u8> for DevicePortType {
+    fn from(value: u8) -> Self {
+        match value {
+            0b0000 => DevicePortType::Endpoint,
+            0b0001 => DevicePortType::LegacyEndpoint,
+            0b1001 => DevicePortType::RootComplexIntegratedEndpoint,
+            0b1010 => DevicePortType::RootComplexEventCollector,
+            0b0100 => DevicePortType::RootPort,
+            0b0101 => DevicePortType::UpstreamPort,
+            0b0110 => DevicePortType::DownstreamPort,
+            0b0111 => DevicePortType::PcieToPciBridge,
+            0b1000 => DevicePortType::PciToPcieBridge,
+            t => DevicePortType::Unknown(t),
+        }
+    }
+}
+
+/// Sizes used for the maximum payload and maximum read request size of a function.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum TransferSize {
+    B128 = 0b000,
+    B256 = 0b001,
+    B512 = 0b010,
+    B1024 = 0b011,
+    B2048 = 0b100,
+    B4096 = 0b101,
+}
+
+impl TransferSize {
+    fn from_bits(value: u16) -> TransferSize {
+        match value {
+            0b000 => TransferSize::B128,
+            0b001 => TransferSize::B256,
+            0b010 => TransferSize::B512,
+            0b011 => TransferSize::B1024,
+            0b100 => TransferSize::B2048,
+            _ => TransferSize::B4096,
+        }
+    }
+
+    /// The size in bytes.
+    pub fn bytes(&self) -> u16 {
+        128 << (*self as u16)
+    }
+}
+
+/// The speed of a PCI Express link.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum LinkSpeed {
+    /// 2.5 GT/s, as introduced by PCI Express 1.0
+    Gt2_5,
+    /// 5.0 GT/s, as introduced by PCI Express 2.0
+    Gt5,
+    /// 8.0 GT/s, as introduced by PCI Express 3.0
+    Gt8,
+    /// 16.0 GT/s, as introduced by PCI Express 4.0
+    Gt16,
+    /// 32.0 GT/s, as introduced by PCI Express 5.0
+    Gt32,
+    /// 64.0 GT/s, as introduced by PCI Express 6.0
+    Gt64,
+    Unknown(u8),
+}
+
+impl LinkSpeed {
+    fn from_bits(value: u8) -> LinkSpeed {
+        match value {
+            1 => LinkSpeed::Gt2_5,
+            2 => LinkSpeed::Gt5,
+            3 => LinkSpeed::Gt8,
+            4 => LinkSpeed::Gt16,
+            5 => LinkSpeed::Gt32,
+            6 => LinkSpeed::Gt64,
+            s => LinkSpeed::Unknown(s),
+        }
+    }
+
+    fn bits(&self) -> u8 {
+        match self {
+            LinkSpeed::Gt2_5 => 1,
+            LinkSpeed::Gt5 => 2,
+            LinkSpeed::Gt8 => 3,
+            LinkSpeed::Gt16 => 4,
+            LinkSpeed::Gt32 => 5,
+            LinkSpeed::Gt64 => 6,
+            LinkSpeed::Unknown(s) => *s,
+        }
+    }
+}
+
+/// The range that a function's Completion Timeout is set within. The exact timeout within the range is up to the
+/// function.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CompletionTimeout {
+    /// The function's default range, which is between 50µs and 50ms
+    Default = 0b0000,
+    /// 50µs to 100µs
+    Us50To100 = 0b0001,
+    /// 1ms to 10ms
+    Ms1To10 = 0b0010,
+    /// 16ms to 55ms
+    Ms16To55 = 0b0101,
+    /// 65ms to 210ms
+    Ms65To210 = 0b0110,
+    /// 260ms to 900ms
+    Ms260To900 = 0b1001,
+    /// 1s to 3.5s
+    S1To3_5 = 0b1010,
+    /// 4s to 13s
+    S4To13 = 0b1101,
+    /// 17s to 64s
+    S17To64 = 0b1110,
+}
+
+impl CompletionTimeout {
+    fn from_bits(value: u16) -> Option<CompletionTimeout> {
+        match value {
+            0b0000 => Some(CompletionTimeout::Default),
+            0b0001 => Some(CompletionTimeout::Us50To100),
+            0b0010 => Some(CompletionTimeout::Ms1To10),
+            0b0101 => Some(CompletionTimeout::Ms16To55),
+            0b0110 => Some(CompletionTimeout::Ms65To210),
+            0b1001 => Some(CompletionTimeout::Ms260To900),
+            0b1010 => Some(CompletionTimeout::S1To3_5),
+            0b1101 => Some(CompletionTimeout::S4To13),
+            0b1110 => Some(CompletionTimeout::S17To64),
+            _ => None,
+        }
+    }
+}
+
+/// How Optimized Buffer Flush/Fill is signalled.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Obff {
+    Disabled = 0b00,
+    /// Signalled with OBFF messages, variation A
+    MessageVariationA = 0b01,
+    /// Signalled with OBFF messages, variation B
+    MessageVariationB = 0b10,
+    /// Signalled with the WAKE# signal
+    WakeSignaling = 0b11,
+}
+
+impl Obff {
+    fn from_bits(value: u16) -> Obff {
+        match value {
+            0b00 => Obff::Disabled,
+            0b01 => Obff::MessageVariationA,
+            0b10 => Obff::MessageVariationB,
+            _ => Obff::WakeSignaling,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LinkControl2Error {
+    /// The link speed is [`LinkSpeed::Unknown`] with a value that doesn't fit in the Target Link Speed field.
+    LinkSpeedOutOfRange,
+    /// The Transmit Margin is more than `7`.
+    TransmitMarginOutOfRange,
+}
+
+bitflags::bitflags! {
+    /// Active State Power Management states of a link.
+    #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+    pub struct Aspm: u8 {
+        const L0S = 1 << 0;
+        const L1 = 1 << 1;
+    }
+}
+
+/// The state of one of a slot's indicators.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum IndicatorState {
+    On = 0b01,
+    Blink = 0b10,
+    Off = 0b11,
+}
+
+impl IndicatorState {
+    fn from_bits(value: u16) -> Option<IndicatorState> {
+        match value {
+            0b01 => Some(IndicatorState::On),
+            0b10 => Some(IndicatorState::Blink),
+            0b11 => Some(IndicatorState::Off),
+            _ => None,
+        }
+    }
+}
+
+/// The PCI Express capability, which describes and controls a function's PCI Express features:
+/// ```ignore
+///     32                            16               8              0
+///      +-----------------------------+---------------+--------------+
+///      |   PCI Express Capabilities  | Next Pointer  |    Cap ID    | 0x00
+///      +-----------------------------+---------------+--------------+
+///      |                   Device Capabilities                      | 0x04
+///      +-----------------------------+------------------------------+
+///      |        Device Status        |       Device Control         | 0x08
+///      +-----------------------------+------------------------------+
+///      |                    Link Capabilities                       | 0x0c
+///      +-----------------------------+------------------------------+
+///      |         Link Status         |        Link Control          | 0x10
+///      +-----------------------------+------------------------------+
+///      |                    Slot Capabilities                       | 0x14
+///      +-----------------------------+------------------------------+
+///      |         Slot Status         |        Slot Control          | 0x18
+///      +-----------------------------+------------------------------+
+///      |     Root Capabilities       |        Root Control          | 0x1c
+///      +-----------------------------+------------------------------+
+///      |                       Root Status                          | 0x20
+///      +------------------------------------------------------------+
+///      |                  Device Capabilities 2                     | 0x24
+///      +-----------------------------+------------------------------+
+///      |       Device Status 2       |      Device Control 2        | 0x28
+///      +-----------------------------+------------------------------+
+///      |                   Link Capabilities 2                      | 0x2c
+///      +-----------------------------+------------------------------+
+///      |        Link Status 2        |       Link Control 2         | 0x30
+///      +-----------------------------+------------------------------+
+/// ```
+///
+/// ### Note
+/// The Link registers are only implemented by functions with a link (i.e. not by Root Complex Integrated
+/// Endpoints and Event Collectors), the Slot registers only by downstream ports with a slot (see
+/// [`PciExpressCapability::slot_implemented`]), and the Root registers only by Root Ports and Root Complex
+/// Event Collectors. Version 1 of the capability ends at the Root Status register. Reading a register the function
+/// doesn't implement returns `None`, and updating or clearing it does nothing.
+#[derive(Debug, Clone)]
+pub struct PciExpressCapability {
+    address: PciCapabilityAddress,
+    capabilities: u16,
+}
+
+impl PciExpressCapability {
+    pub(crate) fn new(address: PciCapabilityAddress, capabilities: u16) -> PciExpressCapability {
+        PciExpressCapability { address, capabilities }
+    }
+
+    /// The version of the capability's structure.
+    #[inline]
+    pub fn version(&self) -> u8 {
+        self.capabilities.get_bits(0..4) as u8
+    }
+
+    #[inline]
+    pub fn device_port_type(&self) -> DevicePortType {
+        DevicePortType::from(self.capabilities.get_bits(4..8) as u8)
+    }
+
+    /// Is the link of this port connected to a slot (rather than to an integrated component)?
+    #[inline]
+    pub fn slot_implemented(&self) -> bool {
+        self.capabilities.get_bit(8)
+    }
+
+    /// The MSI or MSI-X vector used for interrupts generated by this capability's status registers.
+    #[inline]
+    pub fn interrupt_message_number(&self) -> u8 {
+        self.capabilities.get_bits(9..14) as u8
+    }
+
+    fn read(&self, offset: u16, access: &impl ConfigRegionAccess) -> u32 {
+        unsafe { access.read(self.address.address, self.address.offset + offset) }
+    }
+
+    /// Read a register that only some functions implement, returning `None` if this one doesn't.
+    fn read_implemented(&self, implemented: bool, offset: u16, access: &impl ConfigRegionAccess) -> Option<u32> {
+        if !implemented {
+            return None;
+        }
+        Some(self.read(offset, access))
+    }
+
+    fn write(&self, offset: u16, value: u32, access: &impl ConfigRegionAccess) {
+        unsafe { access.write(self.address.address, self.address.offset + offset, value) }
+    }
+
+    /// Does the function have a link, and so implement the Link registers? Root Complex Integrated Endpoints and
+    /// Event Collectors don't.
+    fn has_link_registers(&self) -> bool {
+        matches!(
+            self.device_port_type(),
+            DevicePortType::Endpoint
+                | DevicePortType::LegacyEndpoint
+                | DevicePortType::RootPort
+                | DevicePortType::UpstreamPort
+                | DevicePortType::DownstreamPort
+                | DevicePortType::PcieToPciBridge
+                | DevicePortType::PciToPcieBridge
+        )
+    }
+
+    /// Only downstream ports can be connected to a slot, and only those that are implement the Slot registers.
+    fn has_slot_registers(&self) -> bool {
+        matches!(
+            self.device_port_type(),
+            DevicePortType::RootPort | DevicePortType::DownstreamPort | DevicePortType::PciToPcieBridge
+        ) && self.slot_implemented()
+    }
+
+    fn has_root_registers(&self) -> bool {
+        matches!(self.device_port_type(), DevicePortType::RootPort | DevicePortType::RootComplexEventCollector)
+    }
+
+    /// The Device 2 and Link 2 registers were added in version 2 of the capability.
+    fn has_version_2_registers(&self) -> bool {
+        self.version() >= 2
+    }
+
+    fn has_link_2_registers(&self) -> bool {
+        self.has_version_2_registers() && self.has_link_registers()
+    }
+
+    /// Update the control half of a register that is shared with a status register. The status half is written
+    /// as zero, so none of its RW1C bits are cleared.
+    fn update_control(&self, offset: u16, f: impl Fn(u16) -> u16, access: &impl ConfigRegionAccess) {
+        let control = f(self.read(offset, access).get_bits(0..16) as u16);
+        self.write(offset, control as u32, access);
+    }
+
+    /// Clear the given RW1C bits in the status half of a register that is shared with a control register.
+    fn clear_status(&self, offset: u16, bits: u16, access: &impl ConfigRegionAccess) {
+        let mut data = self.read(offset, access);
+        data.set_bits(16..32, bits as u32);
+        self.write(offset, data, access);
+    }
+
+    pub fn device_capabilities(&self, access: &impl ConfigRegionAccess) -> DeviceCapabilities {
+        DeviceCapabilities(self.read(0x04, access))
+    }
+
+    pub fn device_control(&self, access: &impl ConfigRegionAccess) -> DeviceControl {
+        DeviceControl(self.read(0x08, access).get_bits(0..16) as u16)
+    }
+
+    pub fn update_device_control<F>(&self, access: &impl ConfigRegionAccess, f: F)
+    where
+        F: Fn(DeviceControl) -> DeviceControl,
+    {
+        self.update_control(0x08, |control| f(DeviceControl(control)).0, access);
+    }
+
+    pub fn device_status(&self, access: &impl ConfigRegionAccess) -> DeviceStatus {
+        DeviceStatus(self.read(0x08, access).get_bits(16..32) as u16)
+    }
+
+    /// Clear the error bits that are set in `status`. Passing the value returned by
+    /// [`PciExpressCapability::device_status`] clears the errors that were seen, without losing any that have
+    /// been detected since.
+    pub fn clear_device_status(&self, status: DeviceStatus, access: &impl ConfigRegionAccess) {
+        self.clear_status(0x08, status.0 & DeviceStatus::RW1C_MASK, access);
+    }
+
+    pub fn link_capabilities(&self, access: &impl ConfigRegionAccess) -> Option<LinkCapabilities> {
+        self.read_implemented(self.has_link_registers(), 0x0c, access).map(LinkCapabilities)
+    }
+
+    pub fn link_control(&self, access: &impl ConfigRegionAccess) -> Option<LinkControl> {
+        self.read_implemented(self.has_link_registers(), 0x10, access)
+            .map(|data| LinkControl(data.get_bits(0..16) as u16))
+    }
+
+    pub fn update_link_control<F>(&self, access: &impl ConfigRegionAccess, f: F)
+    where
+        F: Fn(LinkControl) -> LinkControl,
+    {
+        if self.has_link_registers() {
+            self.update_control(0x10, |control| f(LinkControl(control)).0, access);
+        }
+    }
+
+    /// Ask the port to retrain its link. Progress can be followed with [`LinkStatus::link_training`].
+    pub fn retrain_link(&self, access: &impl ConfigRegionAccess) {
+        self.update_link_control(access, |mut control| {
+            control.set_retrain_link(true);
+            control
+        });
+    }
+
+    pub fn link_status(&self, access: &impl ConfigRegionAccess) -> Option<LinkStatus> {
+        self.read_implemented(self.has_link_registers(), 0x10, access)
+            .map(|data| LinkStatus(data.get_bits(16..32) as u16))
+    }
+
+    /// Clear the bandwidth status bits that are set in `status`.
+    pub fn clear_link_status(&self, status: LinkStatus, access: &impl ConfigRegionAccess) {
+        if self.has_link_registers() {
+            self.clear_status(0x10, status.0 & LinkStatus::RW1C_MASK, access);
+        }
+    }
+
+    pub fn slot_capabilities(&self, access: &impl ConfigRegionAccess) -> Option<SlotCapabilities> {
+        self.read_implemented(self.has_slot_registers(), 0x14, access).map(SlotCapabilities)
+    }
+
+    pub fn slot_control(&self, access: &impl ConfigRegionAccess) -> Option<SlotControl> {
+        self.read_implemented(self.has_slot_registers(), 0x18, access)
+            .map(|data| SlotControl(data.get_bits(0..16) as u16))
+    }
+
+    pub fn update_slot_control<F>(&self, access: &impl ConfigRegionAccess, f: F)
+    where
+        F: Fn(SlotControl) -> SlotControl,
+    {
+        if self.has_slot_registers() {
+            self.update_control(0x18, |control| f(SlotControl(control)).0, access);
+        }
+    }
+
+    pub fn slot_status(&self, access: &impl ConfigRegionAccess) -> Option<SlotStatus> {
+        self.read_implemented(self.has_slot_registers(), 0x18, access)
+            .map(|data| SlotStatus(data.get_bits(16..32) as u16))
+    }
+
+    /// Clear the event bits that are set in `status`.
+    pub fn clear_slot_status(&self, status: SlotStatus, access: &impl ConfigRegionAccess) {
+        if self.has_slot_registers() {
+            self.clear_status(0x18, status.0 & SlotStatus::RW1C_MASK, access);
+        }
+    }
+
+    pub fn root_control(&self, access: &impl ConfigRegionAccess) -> Option<RootControl> {
+        self.read_implemented(self.has_root_registers(), 0x1c, access)
+            .map(|data| RootControl::from_bits_truncate(data.get_bits(0..16) as u16))
+    }
+
+    pub fn update_root_control<F>(&self, access: &impl ConfigRegionAccess, f: F)
+    where
+        F: Fn(RootControl) -> RootControl,
+    {
+        if self.has_root_registers() {
+            self.update_control(0x1c, |control| f(RootControl::from_bits_retain(control)).bits(), access);
+        }
+    }
+
+    /// Can the Root Port return Configuration Request Retry Status completions to software? Always `false` for
+    /// functions without the Root registers.
+    pub fn crs_software_visibility_supported(&self, access: &impl ConfigRegionAccess) -> bool {
+        self.has_root_registers() && self.read(0x1c, access).get_bit(16)
+    }
+
+    pub fn root_status(&self, access: &impl ConfigRegionAccess) -> Option<RootStatus> {
+        self.read_implemented(self.has_root_registers(), 0x20, access).map(RootStatus)
+    }
+
+    /// Clear the PME Status bit of the Root Status register, allowing the next PME to be reported.
+    pub fn clear_root_pme_status(&self, access: &impl ConfigRegionAccess) {
+        if self.has_root_registers() {
+            self.write(0x20, 1 << 16, access);
+        }
+    }
+
+    pub fn device_capabilities_2(&self, access: &impl ConfigRegionAccess) -> Option<DeviceCapabilities2> {
+        self.read_implemented(self.has_version_2_registers(), 0x24, access).map(DeviceCapabilities2)
+    }
+
+    pub fn device_control_2(&self, access: &impl ConfigRegionAccess) -> Option<DeviceControl2> {
+        self.read_implemented(self.has_version_2_registers(), 0x28, access)
+            .map(|data| DeviceControl2(data.get_bits(0..16) as u16))
+    }
+
+    pub fn update_device_control_2<F>(&self, access: &impl ConfigRegionAccess, f: F)
+    where
+        F: Fn(DeviceControl2) -> DeviceControl2,
+    {
+        if self.has_version_2_registers() {
+            self.update_control(0x28, |control| f(DeviceControl2(control)).0, access);
+        }
+    }
+
+    pub fn link_capabilities_2(&self, access: &impl ConfigRegionAccess) -> Option<LinkCapabilities2> {
+        self.read_implemented(self.has_link_2_registers(), 0x2c, access).map(LinkCapabilities2)
+    }
+
+    pub fn link_control_2(&self, access: &impl ConfigRegionAccess) -> Option<LinkControl2> {
+        self.read_implemented(self.has_link_2_registers(), 0x30, access)
+            .map(|data| LinkControl2(data.get_bits(0..16) as u16))
+    }
+
+    pub fn update_link_control_2<F>(&self, access: &impl ConfigRegionAccess, f: F)
+    where
+        F: Fn(LinkControl2) -> LinkControl2,
+    {
+        if self.has_link_2_registers() {
+            self.update_control(0x30, |control| f(LinkControl2(control)).0, access);
+        }
+    }
+
+    pub fn link_status_2(&self, access: &impl ConfigRegionAccess) -> Option<LinkStatus2> {
+        self.read_implemented(self.has_link_2_registers(), 0x30, access)
+            .map(|data| LinkStatus2(data.get_bits(16..32) as u16))
+    }
+
+    /// Clear the Link Equalization Request bit of the Link Status 2 register, once the request has been handled.
+    pub fn clear_link_equalization_request(&self, access: &impl ConfigRegionAccess) {
+        if self.has_link_2_registers() {
+            self.clear_status(0x30, 1 << 5, access);
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct DeviceCapabilities(u32);
+
+impl DeviceCapabilities {
+    pub fn max_payload_size_supported(&self) -> TransferSize {
+        TransferSize::from_bits(self.0.get_bits(0..3) as u16)
+    }
+
+    /// How many of the most significant bits of the Function Number can be used to extend the Tag field.
+    pub fn phantom_functions_supported(&self) -> u8 {
+        self.0.get_bits(3..5) as u8
+    }
+
+    /// Does the function support 8-bit Tags (rather than 5-bit ones)?
+    pub fn extended_tag_field_supported(&self) -> bool {
+        self.0.get_bit(5)
+    }
+
+    /// The encoded latency the endpoint can absorb when transitioning from L0s to L0.
+    pub fn endpoint_l0s_acceptable_latency(&self) -> u8 {
+        self.0.get_bits(6..9) as u8
+    }
+
+    /// The encoded latency the endpoint can absorb when transitioning from L1 to L0.
+    pub fn endpoint_l1_acceptable_latency(&self) -> u8 {
+        self.0.get_bits(9..12) as u8
+    }
+
+    pub fn role_based_error_reporting(&self) -> bool {
+        self.0.get_bit(15)
+    }
+
+    /// The power limit of the slot the upstream port is connected to, as `(value, scale)`, where the limit in
+    /// watts is `value` multiplied by `1.0`, `0.1`, `0.01` or `0.001` for scales `0..=3`.
+    pub fn captured_slot_power_limit(&self) -> (u8, u8) {
+        (self.0.get_bits(18..26) as u8, self.0.get_bits(26..28) as u8)
+    }
+
+    pub fn function_level_reset_capable(&self) -> bool {
+        self.0.get_bit(28)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct DeviceControl(u16);
+
+impl DeviceControl {
+    pub fn correctable_error_reporting(&self) -> bool {
+        self.0.get_bit(0)
+    }
+
+    pub fn set_correctable_error_reporting(&mut self, enabled: bool) {
+        self.0.set_bit(0, enabled);
+    }
+
+    pub fn non_fatal_error_reporting(&self) -> bool {
+        self.0.get_bit(1)
+    }
+
+    pub fn set_non_fatal_error_reporting(&mut self, enabled: bool) {
+        self.0.set_bit(1, enabled);
+    }
+
+    pub fn fatal_error_reporting(&self) -> bool {
+        self.0.get_bit(2)
+    }
+
+    pub fn set_fatal_error_reporting(&mut self, enabled: bool) {
+        self.0.set_bit(2, enabled);
+    }
+
+    pub fn unsupported_request_reporting(&self) -> bool {
+        self.0.get_bit(3)
+    }
+
+    pub fn set_unsupported_request_reporting(&mut self, enabled: bool) {
+        self.0.set_bit(3, enabled);
+    }
+
+    pub fn relaxed_ordering(&self) -> bool {
+        self.0.get_bit(4)
+    }
+
+    pub fn set_relaxed_ordering(&mut self, enabled: bool) {
+        self.0.set_bit(4, enabled);
+    }
+
+    pub fn max_payload_size(&self) -> TransferSize {
+        TransferSize::from_bits(self.0.get_bits(5..8))
+    }
+
+    /// Set the maximum TLP payload size. This must not be larger than the
+    /// [supported size](DeviceCapabilities::max_payload_size_supported), or than that of any other function the
+    /// function communicates with.
+    pub fn set_max_payload_size(&mut self, size: TransferSize) {
+        self.0.set_bits(5..8, size as u16);
+    }
+
+    pub fn extended_tag_field(&self) -> bool {
+        self.0.get_bit(8)
+    }
+
+    pub fn set_extended_tag_field(&mut self, enabled: bool) {
+        self.0.set_bit(8, enabled);
+    }
+
+    pub fn phantom_functions(&self) -> bool {
+        self.0.get_bit(9)
+    }
+
+    pub fn set_phantom_functions(&mut self, enabled: bool) {
+        self.0.set_bit(9, enabled);
+    }
+
+    pub fn aux_power_pm(&self) -> bool {
+        self.0.get_bit(10)
+    }
+
+    pub fn set_aux_power_pm(&mut self, enabled: bool) {
+        self.0.set_bit(10, enabled);
+    }
+
+    pub fn no_snoop(&self) -> bool {
+        self.0.get_bit(11)
+    }
+
+    pub fn set_no_snoop(&mut self, enabled: bool) {
+        self.0.set_bit(11, enabled);
+    }
+
+    pub fn max_read_request_size(&self) -> TransferSize {
+        TransferSize::from_bits(self.0.get_bits(12..15))
+    }
+
+    pub fn set_max_read_request_size(&mut self, size: TransferSize) {
+        self.0.set_bits(12..15, size as u16);
+    }
+
+    /// On endpoints that are [FLR-capable](DeviceCapabilities::function_level_reset_capable), setting this bit
+    /// starts a Function Level Reset. On PCI Express to PCI bridges, it is the Bridge Configuration Retry Enable
+    /// bit.
+    pub fn set_initiate_function_level_reset(&mut self, initiate: bool) {
+        self.0.set_bit(15, initiate);
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct DeviceStatus(u16);
+
+impl DeviceStatus {
+    const RW1C_MASK: u16 = 0b1111;
+
+    pub fn correctable_error_detected(&self) -> bool {
+        self.0.get_bit(0)
+    }
+
+    pub fn non_fatal_error_detected(&self) -> bool {
+        self.0.get_bit(1)
+    }
+
+    pub fn fatal_error_detected(&self) -> bool {
+        self.0.get_bit(2)
+    }
+
+    pub fn unsupported_request_detected(&self) -> bool {
+        self.0.get_bit(3)
+    }
+
+    pub fn aux_power_detected(&self) -> bool {
+        self.0.get_bit(4)
+    }
+
+    /// Does the function have non-posted requests that have not been completed?
+    pub fn transactions_pending(&self) -> bool {
+        self.0.get_bit(5)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct LinkCapabilities(u32);
+
+impl LinkCapabilities {
+    pub fn max_link_speed(&self) -> LinkSpeed {
+        LinkSpeed::from_bits(self.0.get_bits(0..4) as u8)
+    }
+
+    /// The maximum number of lanes of the link.
+    pub fn max_link_width(&self) -> u8 {
+        self.0.get_bits(4..10) as u8
+    }
+
+    pub fn aspm_support(&self) -> Aspm {
+        Aspm::from_bits_truncate(self.0.get_bits(10..12) as u8)
+    }
+
+    /// The encoded time the port takes to transition from L0s to L0.
+    pub fn l0s_exit_latency(&self) -> u8 {
+        self.0.get_bits(12..15) as u8
+    }
+
+    /// The encoded time the port takes to transition from L1 to L0.
+    pub fn l1_exit_latency(&self) -> u8 {
+        self.0.get_bits(15..18) as u8
+    }
+
+    pub fn clock_power_management(&self) -> bool {
+        self.0.get_bit(18)
+    }
+
+    pub fn surprise_down_error_reporting_capable(&self) -> bool {
+        self.0.get_bit(19)
+    }
+
+    pub fn data_link_layer_link_active_reporting_capable(&self) -> bool {
+        self.0.get_bit(20)
+    }
+
+    pub fn link_bandwidth_notification_capable(&self) -> bool {
+        self.0.get_bit(21)
+    }
+
+    pub fn aspm_optionality_compliance(&self) -> bool {
+        self.0.get_bit(22)
+    }
+
+    pub fn port_number(&self) -> u8 {
+        self.0.get_bits(24..32) as u8
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct LinkControl(u16);
+
+impl LinkControl {
+    pub fn aspm_control(&self) -> Aspm {
+        Aspm::from_bits_truncate(self.0.get_bits(0..2) as u8)
+    }
+
+    pub fn set_aspm_control(&mut self, aspm: Aspm) {
+        self.0.set_bits(0..2, aspm.bits() as u16);
+    }
+
+    /// Is the Read Completion Boundary 128 bytes (rather than 64 bytes)?
+    pub fn read_completion_boundary_128(&self) -> bool {
+        self.0.get_bit(3)
+    }
+
+    pub fn set_read_completion_boundary_128(&mut self, enabled: bool) {
+        self.0.set_bit(3, enabled);
+    }
+
+    pub fn link_disable(&self) -> bool {
+        self.0.get_bit(4)
+    }
+
+    pub fn set_link_disable(&mut self, disabled: bool) {
+        self.0.set_bit(4, disabled);
+    }
+
+    /// Setting this bit on a downstream port makes it retrain its link. It always reads as `false`.
+    pub fn set_retrain_link(&mut self, retrain: bool) {
+        self.0.set_bit(5, retrain);
+    }
+
+    pub fn common_clock_configuration(&self) -> bool {
+        self.0.get_bit(6)
+    }
+
+    pub fn set_common_clock_configuration(&mut self, enabled: bool) {
+        self.0.set_bit(6, enabled);
+    }
+
+    pub fn extended_synch(&self) -> bool {
+        self.0.get_bit(7)
+    }
+
+    pub fn set_extended_synch(&mut self, enabled: bool) {
+        self.0.set_bit(7, enabled);
+    }
+
+    pub fn clock_power_management(&self) -> bool {
+        self.0.get_bit(8)
+    }
+
+    pub fn set_clock_power_management(&mut self, enabled: bool) {
+        self.0.set_bit(8, enabled);
+    }
+
+    pub fn hardware_autonomous_width_disable(&self) -> bool {
+        self.0.get_bit(9)
+    }
+
+    pub fn set_hardware_autonomous_width_disable(&mut self, disabled: bool) {
+        self.0.set_bit(9, disabled);
+    }
+
+    pub fn link_bandwidth_management_interrupt(&self) -> bool {
+        self.0.get_bit(10)
+    }
+
+    pub fn set_link_bandwidth_management_interrupt(&mut self, enabled: bool) {
+        self.0.set_bit(10, enabled);
+    }
+
+    pub fn link_autonomous_bandwidth_interrupt(&self) -> bool {
+        self.0.get_bit(11)
+    }
+
+    pub fn set_link_autonomous_bandwidth_interrupt(&mut self, enabled: bool) {
+        self.0.set_bit(11, enabled);
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct LinkStatus(u16);
+
+impl LinkStatus {
+    const RW1C_MASK: u16 = 0b1100_0000_0000_0000;
+
+    pub fn current_link_speed(&self) -> LinkSpeed {
+        LinkSpeed::from_bits(self.0.get_bits(0..4) as u8)
+    }
+
+    /// The number of lanes the link trained to.
+    pub fn negotiated_link_width(&self) -> u8 {
+        self.0.get_bits(4..10) as u8
+    }
+
+    /// Is the link currently training (or retraining)?
+    pub fn link_training(&self) -> bool {
+        self.0.get_bit(11)
+    }
+
+    pub fn slot_clock_configuration(&self) -> bool {
+        self.0.get_bit(12)
+    }
+
+    pub fn data_link_layer_link_active(&self) -> bool {
+        self.0.get_bit(13)
+    }
+
+    pub fn link_bandwidth_management_status(&self) -> bool {
+        self.0.get_bit(14)
+    }
+
+    pub fn link_autonomous_bandwidth_status(&self) -> bool {
+        self.0.get_bit(15)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SlotCapabilities(u32);
+
+impl SlotCapabilities {
+    pub fn attention_button_present(&self) -> bool {
+        self.0.get_bit(0)
+    }
+
+    pub fn power_controller_present(&self) -> bool {
+        self.0.get_bit(1)
+    }
+
+    pub fn mrl_sensor_present(&self) -> bool {
+        self.0.get_bit(2)
+    }
+
+    pub fn attention_indicator_present(&self) -> bool {
+        self.0.get_bit(3)
+    }
+
+    pub fn power_indicator_present(&self) -> bool {
+        self.0.get_bit(4)
+    }
+
+    pub fn hot_plug_surprise(&self) -> bool {
+        self.0.get_bit(5)
+    }
+
+    pub fn hot_plug_capable(&self) -> bool {
+        self.0.get_bit(6)
+    }
+
+    /// The power limit of the slot, as `(value, scale)`, where the limit in watts is `value` multiplied by
+    /// `1.0`, `0.1`, `0.01` or `0.001` for scales `0..=3`.
+    pub fn slot_power_limit(&self) -> (u8, u8) {
+        (self.0.get_bits(7..15) as u8, self.0.get_bits(15..17) as u8)
+    }
+
+    pub fn electromechanical_interlock_present(&self) -> bool {
+        self.0.get_bit(17)
+    }
+
+    pub fn no_command_completed_support(&self) -> bool {
+        self.0.get_bit(18)
+    }
+
+    pub fn physical_slot_number(&self) -> u16 {
+        self.0.get_bits(19..32) as u16
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SlotControl(u16);
+
+impl SlotControl {
+    pub fn attention_button_pressed_enable(&self) -> bool {
+        self.0.get_bit(0)
+    }
+
+    pub fn set_attention_button_pressed_enable(&mut self, enabled: bool) {
+        self.0.set_bit(0, enabled);
+    }
+
+    pub fn power_fault_detected_enable(&self) -> bool {
+        self.0.get_bit(1)
+    }
+
+    pub fn set_power_fault_detected_enable(&mut self, enabled: bool) {
+        self.0.set_bit(1, enabled);
+    }
+
+    pub fn mrl_sensor_changed_enable(&self) -> bool {
+        self.0.get_bit(2)
+    }
+
+    pub fn set_mrl_sensor_changed_enable(&mut self, enabled: bool) {
+        self.0.set_bit(2, enabled);
+    }
+
+    pub fn presence_detect_changed_enable(&self) -> bool {
+        self.0.get_bit(3)
+    }
+
+    pub fn set_presence_detect_changed_enable(&mut self, enabled: bool) {
+        self.0.set_bit(3, enabled);
+    }
+
+    pub fn command_completed_interrupt_enable(&self) -> bool {
+        self.0.get_bit(4)
+    }
+
+    pub fn set_command_completed_interrupt_enable(&mut self, enabled: bool) {
+        self.0.set_bit(4, enabled);
+    }
+
+    pub fn hot_plug_interrupt_enable(&self) -> bool {
+        self.0.get_bit(5)
+    }
+
+    pub fn set_hot_plug_interrupt_enable(&mut self, enabled: bool) {
+        self.0.set_bit(5, enabled);
+    }
+
+    /// The state of the attention indicator, or `None` if the reserved encoding is used.
+    pub fn attention_indicator(&self) -> Option<IndicatorState> {
+        IndicatorState::from_bits(self.0.get_bits(6..8))
+    }
+
+    pub fn set_attention_indicator(&mut self, state: IndicatorState) {
+        self.0.set_bits(6..8, state as u16);
+    }
+
+    /// The state of the power indicator, or `None` if the reserved encoding is used.
+    pub fn power_indicator(&self) -> Option<IndicatorState> {
+        IndicatorState::from_bits(self.0.get_bits(8..10))
+    }
+
+    pub fn set_power_indicator(&mut self, state: IndicatorState) {
+        self.0.set_bits(8..10, state as u16);
+    }
+
+    /// Is power to the slot switched on?
+    pub fn power_on(&self) -> bool {
+        !self.0.get_bit(10)
+    }
+
+    pub fn set_power_on(&mut self, on: bool) {
+        self.0.set_bit(10, !on);
+    }
+
+    /// Setting this bit toggles the state of the slot's electromechanical interlock. It always reads as
+    /// `false`.
+    pub fn set_toggle_electromechanical_interlock(&mut self, toggle: bool) {
+        self.0.set_bit(11, toggle);
+    }
+
+    pub fn data_link_layer_state_changed_enable(&self) -> bool {
+        self.0.get_bit(12)
+    }
+
+    pub fn set_data_link_layer_state_changed_enable(&mut self, enabled: bool) {
+        self.0.set_bit(12, enabled);
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SlotStatus(u16);
+
+impl SlotStatus {
+    const RW1C_MASK: u16 = 0b1_0001_1111;
+
+    pub fn attention_button_pressed(&self) -> bool {
+        self.0.get_bit(0)
+    }
+
+    pub fn power_fault_detected(&self) -> bool {
+        self.0.get_bit(1)
+    }
+
+    pub fn mrl_sensor_changed(&self) -> bool {
+        self.0.get_bit(2)
+    }
+
+    pub fn presence_detect_changed(&self) -> bool {
+        self.0.get_bit(3)
+    }
+
+    pub fn command_completed(&self) -> bool {
+        self.0.get_bit(4)
+    }
+
+    /// Is the Manually-operated Retention Latch open?
+    pub fn mrl_open(&self) -> bool {
+        self.0.get_bit(5)
+    }
+
+    /// Is a card present in the slot?
+    pub fn presence_detected(&self) -> bool {
+        self.0.get_bit(6)
+    }
+
+    pub fn electromechanical_interlock_engaged(&self) -> bool {
+        self.0.get_bit(7)
+    }
+
+    pub fn data_link_layer_state_changed(&self) -> bool {
+        self.0.get_bit(8)
+    }
+}
+
+bitflags::bitflags! {
+    #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+    pub struct RootControl: u16 {
+        const SYSTEM_ERROR_ON_CORRECTABLE_ERROR = 1 << 0;
+        const SYSTEM_ERROR_ON_NON_FATAL_ERROR = 1 << 1;
+        const SYSTEM_ERROR_ON_FATAL_ERROR = 1 << 2;
+        const PME_INTERRUPT_ENABLE = 1 << 3;
+        const CRS_SOFTWARE_VISIBILITY_ENABLE = 1 << 4;
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct RootStatus(u32);
+
+impl RootStatus {
+    /// The Requester ID of the function that sent the last PME.
+    pub fn pme_requester_id(&self) -> u16 {
+        self.0.get_bits(0..16) as u16
+    }
+
+    pub fn pme_status(&self) -> bool {
+        self.0.get_bit(16)
+    }
+
+    /// Is another PME waiting to be reported once the PME Status bit has been cleared?
+    pub fn pme_pending(&self) -> bool {
+        self.0.get_bit(17)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct DeviceCapabilities2(u32);
+
+impl DeviceCapabilities2 {
+    /// The encoded Completion Timeout ranges the function supports.
+    pub fn completion_timeout_ranges(&self) -> u8 {
+        self.0.get_bits(0..4) as u8
+    }
+
+    pub fn completion_timeout_disable_supported(&self) -> bool {
+        self.0.get_bit(4)
+    }
+
+    pub fn ari_forwarding_supported(&self) -> bool {
+        self.0.get_bit(5)
+    }
+
+    pub fn atomic_op_routing_supported(&self) -> bool {
+        self.0.get_bit(6)
+    }
+
+    pub fn atomic_op_32_completer_supported(&self) -> bool {
+        self.0.get_bit(7)
+    }
+
+    pub fn atomic_op_64_completer_supported(&self) -> bool {
+        self.0.get_bit(8)
+    }
+
+    pub fn cas_128_completer_supported(&self) -> bool {
+        self.0.get_bit(9)
+    }
+
+    pub fn ltr_supported(&self) -> bool {
+        self.0.get_bit(11)
+    }
+
+    pub fn tph_completer_supported(&self) -> u8 {
+        self.0.get_bits(12..14) as u8
+    }
+
+    pub fn obff_supported(&self) -> u8 {
+        self.0.get_bits(18..20) as u8
+    }
+
+    pub fn extended_fmt_field_supported(&self) -> bool {
+        self.0.get_bit(20)
+    }
+
+    pub fn end_end_tlp_prefix_supported(&self) -> bool {
+        self.0.get_bit(21)
+    }
+
+    pub fn max_end_end_tlp_prefixes(&self) -> u8 {
+        match self.0.get_bits(22..24) {
+            0 => 4,
+            n => n as u8,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct DeviceControl2(u16);
+
+impl DeviceControl2 {
+    /// The range the Completion Timeout is set within, or `None` if it is set to a reserved value.
+    pub fn completion_timeout_value(&self) -> Option<CompletionTimeout> {
+        CompletionTimeout::from_bits(self.0.get_bits(0..4))
+    }
+
+    /// Set the range the Completion Timeout is set within. The range should be one of those reported by
+    /// [`DeviceCapabilities2::completion_timeout_ranges`].
+    pub fn set_completion_timeout_value(&mut self, value: CompletionTimeout) {
+        self.0.set_bits(0..4, value as u16);
+    }
+
+    pub fn completion_timeout_disable(&self) -> bool {
+        self.0.get_bit(4)
+    }
+
+    pub fn set_completion_timeout_disable(&mut self, disabled: bool) {
+        self.0.set_bit(4, disabled);
+    }
+
+    pub fn ari_forwarding(&self) -> bool {
+        self.0.get_bit(5)
+    }
+
+    pub fn set_ari_forwarding(&mut self, enabled: bool) {
+        self.0.set_bit(5, enabled);
+    }
+
+    pub fn atomic_op_requester(&self) -> bool {
+        self.0.get_bit(6)
+    }
+
+    pub fn set_atomic_op_requester(&mut self, enabled: bool) {
+        self.0.set_bit(6, enabled);
+    }
+
+    pub fn atomic_op_egress_blocking(&self) -> bool {
+        self.0.get_bit(7)
+    }
+
+    pub fn set_atomic_op_egress_blocking(&mut self, enabled: bool) {
+        self.0.set_bit(7, enabled);
+    }
+
+    pub fn ido_request(&self) -> bool {
+        self.0.get_bit(8)
+    }
+
+    pub fn set_ido_request(&mut self, enabled: bool) {
+        self.0.set_bit(8, enabled);
+    }
+
+    pub fn ido_completion(&self) -> bool {
+        self.0.get_bit(9)
+    }
+
+    pub fn set_ido_completion(&mut self, enabled: bool) {
+        self.0.set_bit(9, enabled);
+    }
+
+    pub fn ltr_mechanism(&self) -> bool {
+        self.0.get_bit(10)
+    }
+
+    pub fn set_ltr_mechanism(&mut self, enabled: bool) {
+        self.0.set_bit(10, enabled);
+    }
+
+    pub fn obff(&self) -> Obff {
+        Obff::from_bits(self.0.get_bits(13..15))
+    }
+
+    pub fn set_obff(&mut self, value: Obff) {
+        self.0.set_bits(13..15, value as u16);
+    }
+
+    pub fn end_end_tlp_prefix_blocking(&self) -> bool {
+        self.0.get_bit(15)
+    }
+
+    pub fn set_end_end_tlp_prefix_blocking(&mut self, enabled: bool) {
+        self.0.set_bit(15, enabled);
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct LinkCapabilities2(u32);
+
+impl LinkCapabilities2 {
+    /// Does the port support `speed`? Ports that predate this register report no supported speeds, and their
+    /// supported speeds must be taken from [`LinkCapabilities::max_link_speed`] instead.
+    pub fn supports_link_speed(&self, speed: LinkSpeed) -> bool {
+        match speed.bits() {
+            bit @ 1..=7 => self.0.get_bit(bit as usize),
+            _ => false,
+        }
+    }
+
+    pub fn crosslink_supported(&self) -> bool {
+        self.0.get_bit(8)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct LinkControl2(u16);
+
+impl LinkControl2 {
+    /// The upper limit on the link speed. On a downstream port, this takes effect when the link is retrained.
+    pub fn target_link_speed(&self) -> LinkSpeed {
+        LinkSpeed::from_bits(self.0.get_bits(0..4) as u8)
+    }
+
+    pub fn set_target_link_speed(&mut self, speed: LinkSpeed) -> Result<(), LinkControl2Error> {
+        if speed.bits() > 0xf {
+            return Err(LinkControl2Error::LinkSpeedOutOfRange);
+        }
+        self.0.set_bits(0..4, speed.bits() as u16);
+        Ok(())
+    }
+
+    pub fn enter_compliance(&self) -> bool {
+        self.0.get_bit(4)
+    }
+
+    pub fn set_enter_compliance(&mut self, enabled: bool) {
+        self.0.set_bit(4, enabled);
+    }
+
+    pub fn hardware_autonomous_speed_disable(&self) -> bool {
+        self.0.get_bit(5)
+    }
+
+    pub fn set_hardware_autonomous_speed_disable(&mut self, disabled: bool) {
+        self.0.set_bit(5, disabled);
+    }
+
+    /// Is -3.5 dB de-emphasis (rather than -6 dB) selected when the link runs at 5.0 GT/s?
+    pub fn selectable_de_emphasis(&self) -> bool {
+        self.0.get_bit(6)
+    }
+
+    pub fn set_selectable_de_emphasis(&mut self, enabled: bool) {
+        self.0.set_bit(6, enabled);
+    }
+
+    /// The Transmit Margin, between `0` and `7`. `0` is the normal operating range; the other values are only
+    /// used for testing.
+    pub fn transmit_margin(&self) -> u8 {
+        self.0.get_bits(7..10) as u8
+    }
+
+    pub fn set_transmit_margin(&mut self, value: u8) -> Result<(), LinkControl2Error> {
+        if value > 0b111 {
+            return Err(LinkControl2Error::TransmitMarginOutOfRange);
+        }
+        self.0.set_bits(7..10, value as u16);
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct LinkStatus2(u16);
+
+impl LinkStatus2 {
+    /// Is the current de-emphasis level -3.5 dB (rather than -6 dB)?
+    pub fn current_de_emphasis(&self) -> bool {
+        self.0.get_bit(0)
+    }
+
+    pub fn equalization_complete(&self) -> bool {
+        self.0.get_bit(1)
+    }
+
+    pub fn equalization_phase_1_successful(&self) -> bool {
+        self.0.get_bit(2)
+    }
+
+    pub fn equalization_phase_2_successful(&self) -> bool {
+        self.0.get_bit(3)
+    }
+
+    pub fn equalization_phase_3_successful(&self) -> bool {
+        self.0.get_bit(4)
+    }
+
+    pub fn link_equalization_request(&self) -> bool {
+        self.0.get_bit(5)
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod tests {
+    use super::*;
+    use crate::{
+        access::{MockConfigSpace, MockFunction},
+        capability::PciCapability,
+        EndpointHeader,
+        PciAddress,
+    };
+
+    const ADDRESS: PciAddress = PciAddress(0x0000_0100);
+
+    /*
+     * PCI Express Capabilities registers, with the version in bits 0..4, the port type in bits 4..8 and Slot
+     * Implemented in bit 8.
+     */
+    const ENDPOINT_V1: u16 = 0x0001;
+    const ENDPOINT: u16 = 0x0002;
+    const INTEGRATED_ENDPOINT: u16 = 0x0092;
+    const EVENT_COLLECTOR: u16 = 0x00a2;
+    const ROOT_PORT_WITH_SLOT: u16 = 0x0142;
+    const DOWNSTREAM_PORT: u16 = 0x0062;
+
+    /// A function with a PCI Express capability with the given PCI Express Capabilities register. All of the
+    /// capability's control registers are writable, and its status registers have their RW1C bits.
+    fn mock_pcie(function: MockFunction, capabilities: u16) -> MockConfigSpace {
+        let mut function = function;
+        function.add_capability(0x40, 0x10);
+        function.set(0x40, function.get(0x40) | (capabilities as u32) << 16);
+        for &(offset, write_mask, rw1c_mask) in [
+            (0x48, 0x0000_ffff, 0x000f_0000),
+            (0x50, 0x0000_ffff, 0xc000_0000),
+            (0x58, 0x0000_ffff, 0x011f_0000),
+            (0x5c, 0x0000_001f, 0x0000_0000),
+            (0x60, 0x0000_0000, 0x0001_0000),
+            (0x68, 0x0000_ffff, 0x0000_0000),
+            (0x70, 0x0000_ffff, 0x0020_0000),
+        ]
+        .iter()
+        {
+            function.set_write_mask(offset, write_mask);
+            function.set_rw1c_mask(offset, rw1c_mask);
+        }
+        let mut config = MockConfigSpace::new();
+        config.add_function(ADDRESS, function);
+        config
+    }
+
+    fn pci_express(config: &MockConfigSpace) -> PciExpressCapability {
+        EndpointHeader(ADDRESS)
+            .capabilities(config)
+            .find_map(|capability| match capability {
+                PciCapability::PciExpress(pcie) => Some(pcie),
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn update_control_preserves_status() {
+        let mut config = mock_pcie(MockFunction::endpoint(), ENDPOINT);
+        // All of the Device Status error bits, and both Link Status bandwidth bits, are set
+        config.function(ADDRESS).unwrap().set(0x48, 0x000f_0000);
+        config.function(ADDRESS).unwrap().set(0x50, 0xc000_0000);
+        let pcie = pci_express(&config);
+
+        pcie.update_device_control(&config, |mut control| {
+            control.set_fatal_error_reporting(true);
+            control.set_max_payload_size(TransferSize::B256);
+            control
+        });
+        let control = pcie.device_control(&config);
+        assert!(control.fatal_error_reporting());
+        assert_eq!(control.max_payload_size(), TransferSize::B256);
+        assert_eq!(pcie.device_status(&config), DeviceStatus(0b1111));
+
+        pcie.retrain_link(&config);
+        assert_eq!(config.function(ADDRESS).unwrap().get(0x50), 0xc000_0020);
+        let status = pcie.link_status(&config).unwrap();
+        assert!(status.link_bandwidth_management_status());
+        assert!(status.link_autonomous_bandwidth_status());
+    }
+
+    #[test]
+    fn clear_status_preserves_control() {
+        let mut config = mock_pcie(MockFunction::endpoint(), ENDPOINT);
+        // Correctable and Fatal errors detected, with reporting of both enabled
+        config.function(ADDRESS).unwrap().set(0x48, 0x0005_0005);
+        config.function(ADDRESS).unwrap().set(0x70, 0x0020_0003);
+        let pcie = pci_express(&config);
+
+        /*
+         * Errors detected after the status was read aren't cleared.
+         */
+        let status = pcie.device_status(&config);
+        config.function(ADDRESS).unwrap().set(0x48, 0x0007_0005);
+        pcie.clear_device_status(status, &config);
+        assert_eq!(pcie.device_status(&config), DeviceStatus(0b0010));
+        assert_eq!(pcie.device_control(&config), DeviceControl(0x0005));
+
+        pcie.clear_link_equalization_request(&config);
+        assert_eq!(config.function(ADDRESS).unwrap().get(0x70), 0x0000_0003);
+        assert_eq!(pcie.link_control_2(&config).unwrap().target_link_speed(), LinkSpeed::Gt8);
+    }
+
+    #[test]
+    fn version_2_registers() {
+        let config = mock_pcie(MockFunction::endpoint(), ENDPOINT);
+        let pcie = pci_express(&config);
+        pcie.update_device_control_2(&config, |mut control| {
+            control.set_ari_forwarding(true);
+            control
+        });
+        assert!(pcie.device_control_2(&config).unwrap().ari_forwarding());
+        assert!(pcie.device_capabilities_2(&config).is_some());
+        assert!(pcie.link_capabilities_2(&config).is_some());
+        assert!(pcie.link_status_2(&config).is_some());
+
+        /*
+         * Version 1 of the capability ends at the Root Status register, so the registers after it must not be
+         * touched.
+         */
+        let mut config = mock_pcie(MockFunction::endpoint(), ENDPOINT_V1);
+        config.function(ADDRESS).unwrap().set(0x70, 0x0020_0000);
+        let pcie = pci_express(&config);
+        assert_eq!(pcie.version(), 1);
+        assert!(pcie.link_control(&config).is_some());
+        assert_eq!(pcie.device_capabilities_2(&config), None);
+        assert_eq!(pcie.device_control_2(&config), None);
+        assert_eq!(pcie.link_capabilities_2(&config), None);
+        assert_eq!(pcie.link_control_2(&config), None);
+        assert_eq!(pcie.link_status_2(&config), None);
+
+        pcie.update_device_control_2(&config, |mut control| {
+            control.set_ari_forwarding(true);
+            control
+        });
+        pcie.update_link_control_2(&config, |mut control| {
+            control.set_target_link_speed(LinkSpeed::Gt5).unwrap();
+            control
+        });
+        pcie.clear_link_equalization_request(&config);
+        assert_eq!(config.function(ADDRESS).unwrap().get(0x68), 0);
+        assert_eq!(config.function(ADDRESS).unwrap().get(0x70), 0x0020_0000);
+    }
+
+    #[test]
+    fn port_type_registers() {
+        /*
+         * Root Complex Integrated Endpoints have no link, and Endpoints have neither Slot nor Root registers.
+         */
+        let mut config = mock_pcie(MockFunction::endpoint(), INTEGRATED_ENDPOINT);
+        let pcie = pci_express(&config);
+        assert_eq!(pcie.device_port_type(), DevicePortType::RootComplexIntegratedEndpoint);
+        assert_eq!(pcie.link_capabilities(&config), None);
+        assert_eq!(pcie.link_control(&config), None);
+        assert_eq!(pcie.link_status(&config), None);
+        assert_eq!(pcie.link_control_2(&config), None);
+        assert!(pcie.device_control_2(&config).is_some());
+        pcie.retrain_link(&config);
+        assert_eq!(config.function(ADDRESS).unwrap().get(0x50), 0);
+
+        let mut config = mock_pcie(MockFunction::endpoint(), ENDPOINT);
+        config.function(ADDRESS).unwrap().set(0x60, 0x0001_0000);
+        let pcie = pci_express(&config);
+        assert_eq!(pcie.slot_capabilities(&config), None);
+        assert_eq!(pcie.slot_control(&config), None);
+        assert_eq!(pcie.root_control(&config), None);
+        assert_eq!(pcie.root_status(&config), None);
+        pcie.clear_root_pme_status(&config);
+        assert_eq!(config.function(ADDRESS).unwrap().get(0x60), 0x0001_0000);
+
+        /*
+         * Only downstream ports that are connected to a slot have the Slot registers.
+         */
+        let mut config = mock_pcie(MockFunction::bridge(0, 1, 1), DOWNSTREAM_PORT);
+        let pcie = pci_express(&config);
+        assert_eq!(pcie.slot_capabilities(&config), None);
+        assert_eq!(pcie.slot_status(&config), None);
+        pcie.update_slot_control(&config, |mut control| {
+            control.set_power_on(true);
+            control
+        });
+        assert_eq!(config.function(ADDRESS).unwrap().get(0x58), 0);
+        assert!(pcie.link_control(&config).is_some());
+        assert_eq!(pcie.root_control(&config), None);
+
+        let mut config = mock_pcie(MockFunction::bridge(0, 1, 1), ROOT_PORT_WITH_SLOT);
+        config.function(ADDRESS).unwrap().set(0x60, 0x0001_0000);
+        let pcie = pci_express(&config);
+        assert!(pcie.slot_capabilities(&config).is_some());
+        pcie.update_slot_control(&config, |mut control| {
+            control.set_power_on(true);
+            control
+        });
+        assert!(pcie.slot_control(&config).unwrap().power_on());
+        pcie.update_root_control(&config, |control| control | RootControl::PME_INTERRUPT_ENABLE);
+        assert_eq!(pcie.root_control(&config), Some(RootControl::PME_INTERRUPT_ENABLE));
+        assert!(pcie.root_status(&config).unwrap().pme_status());
+        pcie.clear_root_pme_status(&config);
+        assert!(!pcie.root_status(&config).unwrap().pme_status());
+
+        let config = mock_pcie(MockFunction::endpoint(), EVENT_COLLECTOR);
+        let pcie = pci_express(&config);
+        assert!(pcie.root_control(&config).is_some());
+        assert_eq!(pcie.link_control(&config), None);
+        assert_eq!(pcie.slot_control(&config), None);
+    }
+}
@@ -0,0 +1,331 @@
+use crate::{capability::PciCapabilityAddress, ConfigRegionAccess};
+use bit_field::BitField;
+use core::convert::TryFrom;
+
+/// Provides delays for operations that require the function to be left alone for some time, such as power state
+/// transitions.
+pub trait Delay {
+    /// Wait for at least `microseconds` microseconds.
+    fn delay_us(&self, microseconds: u32);
+}
+
+/// The power state of a function. `D0` is the fully-powered state, and each later state uses less power, and
+/// takes longer to return to `D0` from.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum PowerState {
+    D0 = 0b00,
+    D1 = 0b01,
+    D2 = 0b10,
+    D3Hot = 0b11,
+}
+
+impl TryFrom<u8> for PowerState {
+    type Error = ();
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0b00 => Ok(PowerState::D0),
+            0b01 => Ok(PowerState::D1),
+            0b10 => Ok(PowerState::D2),
+            0b11 => Ok(PowerState::D3Hot),
+            _ => Err(()),
+        }
+    }
+}
+
+bitflags::bitflags! {
+    /// The power states from which a function can assert PME#.
+    #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+    pub struct PmeSupport: u8 {
+        const D0 = 1 << 0;
+        const D1 = 1 << 1;
+        const D2 = 1 << 2;
+        const D3_HOT = 1 << 3;
+        const D3_COLD = 1 << 4;
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PowerStateError {
+    /// The function doesn't support the requested power state.
+    Unsupported(PowerState),
+    /// A function can only move to a lower-power state, or back to `D0`.
+    InvalidTransition { from: PowerState, to: PowerState },
+}
+
+/// The Power Management capability, which has the form:
+/// ```ignore
+///     32              24              16              8               0
+///      +-------------------------------+---------------+---------------+
+///      | Power Management Capabilities |     Next      |    Cap ID     | 0x0
+///      |             (PMC)             |    Pointer    |               |
+///      +---------------+---------------+---------------+---------------+
+///      |     Data      |  Bridge Ext.  | Power Management Control and  | 0x4
+///      |               |  (PMCSR_BSE)  |        Status (PMCSR)         |
+///      +---------------+---------------+-------------------------------+
+/// ```
+#[derive(Debug, Clone)]
+pub struct PowerManagementCapability {
+    address: PciCapabilityAddress,
+    capabilities: u16,
+}
+
+impl PowerManagementCapability {
+    pub(crate) fn new(address: PciCapabilityAddress, capabilities: u16) -> PowerManagementCapability {
+        PowerManagementCapability { address, capabilities }
+    }
+
+    /// The version of the PCI Power Management specification the function complies with.
+    #[inline]
+    pub fn version(&self) -> u8 {
+        self.capabilities.get_bits(0..3) as u8
+    }
+
+    /// Does the function need the PCI clock to be running to generate PME#?
+    #[inline]
+    pub fn pme_clock(&self) -> bool {
+        self.capabilities.get_bit(3)
+    }
+
+    /// Does the function need device-specific initialization after moving to `D0`, beyond what its class
+    /// driver would do?
+    #[inline]
+    pub fn device_specific_initialization(&self) -> bool {
+        self.capabilities.get_bit(5)
+    }
+
+    /// The maximum current the function draws from the 3.3V auxiliary supply in D3cold, in mA. `None` means that
+    /// the function doesn't report its auxiliary current here (it may instead use the Data register).
+    pub fn aux_current(&self) -> Option<u16> {
+        match self.capabilities.get_bits(6..9) {
+            0b000 => None,
+            0b001 => Some(55),
+            0b010 => Some(100),
+            0b011 => Some(160),
+            0b100 => Some(220),
+            0b101 => Some(270),
+            0b110 => Some(320),
+            _ => Some(375),
+        }
+    }
+
+    /// Does the function support `state`? All functions support `D0` and `D3Hot`.
+    pub fn supports(&self, state: PowerState) -> bool {
+        match state {
+            PowerState::D0 | PowerState::D3Hot => true,
+            PowerState::D1 => self.capabilities.get_bit(9),
+            PowerState::D2 => self.capabilities.get_bit(10),
+        }
+    }
+
+    /// The power states from which the function can assert PME#.
+    #[inline]
+    pub fn pme_support(&self) -> PmeSupport {
+        PmeSupport::from_bits_truncate(self.capabilities.get_bits(11..16) as u8)
+    }
+
+    fn read_pmcsr(&self, access: &impl ConfigRegionAccess) -> u32 {
+        unsafe { access.read(self.address.address, self.address.offset + 0x4) }
+    }
+
+    /// Write to PMCSR. PME_Status is RW1C, so it is only set in `data` if it should be cleared.
+    fn write_pmcsr(&self, data: u32, access: &impl ConfigRegionAccess) {
+        unsafe { access.write(self.address.address, self.address.offset + 0x4, data) }
+    }
+
+    /// The current power state of the function.
+    pub fn power_state(&self, access: &impl ConfigRegionAccess) -> PowerState {
+        PowerState::try_from(self.read_pmcsr(access).get_bits(0..2) as u8).unwrap()
+    }
+
+    /// Is the No_Soft_Reset bit set? If it is, the function keeps its configuration state when moving from
+    /// `D3Hot` to `D0`. Otherwise, the function is reset and must be configured again.
+    pub fn no_soft_reset(&self, access: &impl ConfigRegionAccess) -> bool {
+        self.read_pmcsr(access).get_bit(3)
+    }
+
+    /// Move the function to `state`, waiting for as long as the specification requires the function to be left
+    /// alone after the transition: 10ms when moving to or from `D3Hot`, 200μs when moving to or from `D2`, and
+    /// no time otherwise.
+    ///
+    /// Returns whether the function kept its configuration state. This is only `false` after moving from
+    /// `D3Hot` to `D0` on a function without [`no_soft_reset`](PowerManagementCapability::no_soft_reset), in
+    /// which case the function has been reset and its BARs, Command register, etc. must be restored.
+    pub fn set_power_state(
+        &self,
+        state: PowerState,
+        delay: &impl Delay,
+        access: &impl ConfigRegionAccess,
+    ) -> Result<bool, PowerStateError> {
+        if !self.supports(state) {
+            return Err(PowerStateError::Unsupported(state));
+        }
+
+        let mut pmcsr = self.read_pmcsr(access);
+        let current = PowerState::try_from(pmcsr.get_bits(0..2) as u8).unwrap();
+        if current == state {
+            return Ok(true);
+        }
+        if state != PowerState::D0 && state < current {
+            return Err(PowerStateError::InvalidTransition { from: current, to: state });
+        }
+
+        let no_soft_reset = pmcsr.get_bit(3);
+        pmcsr.set_bits(0..2, state as u32);
+        pmcsr.set_bit(15, false);
+        self.write_pmcsr(pmcsr, access);
+
+        if current == PowerState::D3Hot || state == PowerState::D3Hot {
+            delay.delay_us(10_000);
+        } else if current == PowerState::D2 || state == PowerState::D2 {
+            delay.delay_us(200);
+        }
+
+        Ok(current != PowerState::D3Hot || no_soft_reset)
+    }
+
+    /// Is the function allowed to assert PME#?
+    pub fn is_pme_enabled(&self, access: &impl ConfigRegionAccess) -> bool {
+        self.read_pmcsr(access).get_bit(8)
+    }
+
+    /// Allow or stop the function asserting PME#
+    pub fn set_pme_enabled(&self, enabled: bool, access: &impl ConfigRegionAccess) {
+        let mut pmcsr = self.read_pmcsr(access);
+        pmcsr.set_bit(8, enabled);
+        pmcsr.set_bit(15, false);
+        self.write_pmcsr(pmcsr, access);
+    }
+
+    /// Has the function asserted PME#? This is independent of whether PME# is enabled.
+    pub fn pme_status(&self, access: &impl ConfigRegionAccess) -> bool {
+        self.read_pmcsr(access).get_bit(15)
+    }
+
+    /// Clear the PME_Status bit, which stops the function asserting PME#.
+    pub fn clear_pme_status(&self, access: &impl ConfigRegionAccess) {
+        let mut pmcsr = self.read_pmcsr(access);
+        pmcsr.set_bit(15, true);
+        self.write_pmcsr(pmcsr, access);
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod tests {
+    use super::*;
+    use crate::{
+        access::{MockConfigSpace, MockDelay, MockFunction},
+        capability::PciCapability,
+        EndpointHeader,
+        PciAddress,
+    };
+
+    const ADDRESS: PciAddress = PciAddress(0x0000_0100);
+
+    /// A function with a Power Management capability with the given PMC and PMCSR.
+    fn mock_pm(pmc: u16, pmcsr: u32) -> MockConfigSpace {
+        let mut function = MockFunction::endpoint();
+        function.add_capability(0x40, 0x01);
+        function.set(0x40, function.get(0x40) | (pmc as u32) << 16);
+        function.set(0x44, pmcsr);
+        function.set_write_mask(0x44, 0x0000_0103);
+        function.set_rw1c_mask(0x44, 0x0000_8000);
+        let mut config = MockConfigSpace::new();
+        config.add_function(ADDRESS, function);
+        config
+    }
+
+    fn power_management(config: &MockConfigSpace) -> PowerManagementCapability {
+        EndpointHeader(ADDRESS)
+            .capabilities(config)
+            .find_map(|capability| match capability {
+                PciCapability::PowerManagement(pm) => Some(pm),
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn transition_delays() {
+        // D1 and D2 supported
+        let config = mock_pm(0x0603, 0x0000_0000);
+        let pm = power_management(&config);
+        let delay = MockDelay::new();
+
+        for &state in [PowerState::D1, PowerState::D2, PowerState::D3Hot, PowerState::D0, PowerState::D2].iter() {
+            pm.set_power_state(state, &delay, &config).unwrap();
+            assert_eq!(pm.power_state(&config), state);
+        }
+        assert_eq!(delay.delays(), [200, 10_000, 10_000, 200]);
+
+        /*
+         * Moving to the current state does nothing.
+         */
+        assert_eq!(pm.set_power_state(PowerState::D2, &delay, &config), Ok(true));
+        assert_eq!(delay.delays().len(), 4);
+    }
+
+    #[test]
+    fn no_soft_reset() {
+        let delay = MockDelay::new();
+
+        let config = mock_pm(0x0003, 0x0000_0000);
+        let pm = power_management(&config);
+        assert!(!pm.no_soft_reset(&config));
+        assert_eq!(pm.set_power_state(PowerState::D3Hot, &delay, &config), Ok(true));
+        assert_eq!(pm.set_power_state(PowerState::D0, &delay, &config), Ok(false));
+
+        let config = mock_pm(0x0003, 0x0000_0008);
+        let pm = power_management(&config);
+        assert!(pm.no_soft_reset(&config));
+        assert_eq!(pm.set_power_state(PowerState::D3Hot, &delay, &config), Ok(true));
+        assert_eq!(pm.set_power_state(PowerState::D0, &delay, &config), Ok(true));
+    }
+
+    #[test]
+    fn preserves_pme_status() {
+        // PME# asserted and enabled
+        let config = mock_pm(0x0003, 0x0000_8100);
+        let pm = power_management(&config);
+        let delay = MockDelay::new();
+
+        pm.set_power_state(PowerState::D3Hot, &delay, &config).unwrap();
+        pm.set_pme_enabled(false, &config);
+        assert!(pm.pme_status(&config));
+        assert!(!pm.is_pme_enabled(&config));
+        assert_eq!(pm.power_state(&config), PowerState::D3Hot);
+
+        pm.clear_pme_status(&config);
+        assert!(!pm.pme_status(&config));
+        assert_eq!(pm.power_state(&config), PowerState::D3Hot);
+    }
+
+    #[test]
+    fn invalid_states() {
+        let config = mock_pm(0x0003, 0x0000_0000);
+        let pm = power_management(&config);
+        let delay = MockDelay::new();
+
+        assert!(!pm.supports(PowerState::D1));
+        assert_eq!(
+            pm.set_power_state(PowerState::D1, &delay, &config),
+            Err(PowerStateError::Unsupported(PowerState::D1))
+        );
+        assert_eq!(
+            pm.set_power_state(PowerState::D2, &delay, &config),
+            Err(PowerStateError::Unsupported(PowerState::D2))
+        );
+        assert_eq!(pm.power_state(&config), PowerState::D0);
+
+        /*
+         * A function can't move from D3hot to a higher-power state other than D0.
+         */
+        let config = mock_pm(0x0603, 0x0000_0003);
+        let pm = power_management(&config);
+        assert_eq!(
+            pm.set_power_state(PowerState::D1, &delay, &config),
+            Err(PowerStateError::InvalidTransition { from: PowerState::D3Hot, to: PowerState::D1 })
+        );
+        assert!(delay.delays().is_empty());
+    }
+}
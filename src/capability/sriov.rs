@@ -0,0 +1,435 @@
+use crate::{
+    capability::{Delay, PciCapabilityAddress},
+    read_bar,
+    Bar,
+    BarDecode,
+    BarError,
+    BarIterator,
+    ConfigRegionAccess,
+    DeviceId,
+    PciAddress,
+    MAX_BARS,
+};
+use bit_field::BitField;
+
+/// How long VFs must be left alone after they are enabled, before their configuration space is accessed.
+const VF_ENABLE_DELAY_US: u32 = 100_000;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SriovError {
+    /// The requested number of VFs is more than the PF supports.
+    TooManyVfs { requested: u16, total: u16 },
+    /// VFs are already enabled. The number of VFs can only be changed while they are disabled.
+    AlreadyEnabled,
+    /// VFs can't be enabled without creating at least one. Use [`SriovCapability::disable_vfs`] to turn them off.
+    NoVfs,
+}
+
+/// The Single Root I/O Virtualization capability, which lets a Physical Function (PF) create a number of
+/// light-weight Virtual Functions (VFs) that can be assigned to guests. It has the form:
+/// ```ignore
+///     32                              16               8              0
+///      +-------------------------------+-------------------------------+
+///      |       PCI Express Extended Capability Header                  | 0x00
+///      +---------------------------------------------------------------+
+///      |                    SR-IOV Capabilities                        | 0x04
+///      +-------------------------------+-------------------------------+
+///      |         SR-IOV Status         |        SR-IOV Control         | 0x08
+///      +-------------------------------+-------------------------------+
+///      |           TotalVFs            |          InitialVFs           | 0x0c
+///      +---------------+---------------+-------------------------------+
+///      |   Reserved    | Function Link |            NumVFs             | 0x10
+///      +---------------+---------------+-------------------------------+
+///      |           VF Stride           |        First VF Offset        | 0x14
+///      +-------------------------------+-------------------------------+
+///      |          VF Device ID         |           Reserved            | 0x18
+///      +-------------------------------+-------------------------------+
+///      |                     Supported Page Sizes                      | 0x1c
+///      +---------------------------------------------------------------+
+///      |                       System Page Size                        | 0x20
+///      +---------------------------------------------------------------+
+///      |                      VF BAR0 - VF BAR5                        | 0x24
+///      +---------------------------------------------------------------+
+///      |             VF Migration State Array Offset                   | 0x3c
+///      +---------------------------------------------------------------+
+/// ```
+#[derive(Debug, Clone)]
+pub struct SriovCapability {
+    address: PciCapabilityAddress,
+}
+
+impl SriovCapability {
+    pub(crate) fn new(address: PciCapabilityAddress) -> SriovCapability {
+        SriovCapability { address }
+    }
+
+    /// The location of the capability's header.
+    pub fn address(&self) -> &PciCapabilityAddress {
+        &self.address
+    }
+
+    fn read(&self, offset: u16, access: &impl ConfigRegionAccess) -> u32 {
+        unsafe { access.read(self.address.address, self.address.offset + offset) }
+    }
+
+    fn write(&self, offset: u16, value: u32, access: &impl ConfigRegionAccess) {
+        unsafe { access.write(self.address.address, self.address.offset + offset, value) }
+    }
+
+    pub fn capabilities(&self, access: &impl ConfigRegionAccess) -> SriovCapabilities {
+        SriovCapabilities(self.read(0x04, access))
+    }
+
+    pub fn control(&self, access: &impl ConfigRegionAccess) -> SriovControl {
+        SriovControl(self.read(0x08, access).get_bits(0..16) as u16)
+    }
+
+    /// Update the SR-IOV Control register. The SR-IOV Status register shares its dword, and is written as zero so
+    /// its RW1C bit is not cleared.
+    pub fn update_control<F>(&self, access: &impl ConfigRegionAccess, f: F)
+    where
+        F: Fn(SriovControl) -> SriovControl,
+    {
+        let control = f(self.control(access));
+        self.write(0x08, control.0 as u32, access);
+    }
+
+    /// Has a VF Migration been requested? See the SR-IOV specification for how VF Migration works.
+    pub fn vf_migration_status(&self, access: &impl ConfigRegionAccess) -> bool {
+        self.read(0x08, access).get_bit(16)
+    }
+
+    pub fn clear_vf_migration_status(&self, access: &impl ConfigRegionAccess) {
+        let mut data = self.read(0x08, access);
+        data.set_bit(16, true);
+        self.write(0x08, data, access);
+    }
+
+    /// The number of VFs initially associated with the PF. Unless VF Migration is supported, this is the same as
+    /// [`SriovCapability::total_vfs`].
+    pub fn initial_vfs(&self, access: &impl ConfigRegionAccess) -> u16 {
+        self.read(0x0c, access).get_bits(0..16) as u16
+    }
+
+    /// The maximum number of VFs the PF can be configured with.
+    pub fn total_vfs(&self, access: &impl ConfigRegionAccess) -> u16 {
+        self.read(0x0c, access).get_bits(16..32) as u16
+    }
+
+    /// The number of VFs the PF is configured with.
+    pub fn num_vfs(&self, access: &impl ConfigRegionAccess) -> u16 {
+        self.read(0x10, access).get_bits(0..16) as u16
+    }
+
+    /// Set the number of VFs. The First VF Offset and VF Stride can change when this does, so they must be read
+    /// again afterwards. This can only be done while VFs are disabled.
+    pub fn set_num_vfs(&self, num_vfs: u16, access: &impl ConfigRegionAccess) -> Result<(), SriovError> {
+        if self.control(access).vf_enable() {
+            return Err(SriovError::AlreadyEnabled);
+        }
+        let total = self.total_vfs(access);
+        if num_vfs > total {
+            return Err(SriovError::TooManyVfs { requested: num_vfs, total });
+        }
+
+        let mut data = self.read(0x10, access);
+        data.set_bits(0..16, num_vfs as u32);
+        self.write(0x10, data, access);
+        Ok(())
+    }
+
+    /// The function number of the PF that this PF's VFs depend on, or this PF's own function number if they
+    /// don't depend on another PF.
+    pub fn function_dependency_link(&self, access: &impl ConfigRegionAccess) -> u8 {
+        self.read(0x10, access).get_bits(16..24) as u8
+    }
+
+    /// The offset of the first VF's Routing ID from the PF's Routing ID.
+    pub fn first_vf_offset(&self, access: &impl ConfigRegionAccess) -> u16 {
+        self.read(0x14, access).get_bits(0..16) as u16
+    }
+
+    /// The difference between the Routing IDs of consecutive VFs.
+    pub fn vf_stride(&self, access: &impl ConfigRegionAccess) -> u16 {
+        self.read(0x14, access).get_bits(16..32) as u16
+    }
+
+    /// The Device ID that the PF's VFs report. VFs have the same Vendor ID as the PF, but their Vendor ID and
+    /// Device ID registers read as `0xffff`.
+    pub fn vf_device_id(&self, access: &impl ConfigRegionAccess) -> DeviceId {
+        self.read(0x18, access).get_bits(16..32) as u16
+    }
+
+    /// The page sizes the PF supports. If bit `n` is set, pages of `4KiB << n` are supported.
+    pub fn supported_page_sizes(&self, access: &impl ConfigRegionAccess) -> u32 {
+        self.read(0x1c, access)
+    }
+
+    /// The page size VF BARs are aligned to, in the same format as
+    /// [`SriovCapability::supported_page_sizes`].
+    pub fn system_page_size(&self, access: &impl ConfigRegionAccess) -> u32 {
+        self.read(0x20, access)
+    }
+
+    /// Set the page size VF BARs are aligned to. Exactly one bit, of a supported page size, must be set. This
+    /// changes the sizes of the VF BARs, so they must be sized again afterwards.
+    pub fn set_system_page_size(&self, page_size: u32, access: &impl ConfigRegionAccess) {
+        self.write(0x20, page_size, access);
+    }
+
+    /// Get the contents of the VF BAR in a given slot. This behaves like
+    /// [`EndpointHeader::bar`](crate::EndpointHeader::bar), but the size is that of the BAR of each VF. The BARs
+    /// of the VFs are laid out consecutively from the address, so the total size of the region is the size
+    /// multiplied by [`SriovCapability::num_vfs`]. VF Memory Space Enable is cleared while the BAR is sized, and
+    /// restored afterwards.
+    pub fn vf_bar(&self, slot: u8, access: &impl ConfigRegionAccess) -> Result<Option<Bar>, BarError> {
+        read_bar(self.address.address, self.address.offset + 0x24, MAX_BARS as u8, slot, self.decode(), access)
+    }
+
+    /// Iterate over the VF BARs. This behaves like [`EndpointHeader::bars`](crate::EndpointHeader::bars).
+    pub fn vf_bars<'a, T: ConfigRegionAccess>(&self, access: &'a T) -> BarIterator<'a, T> {
+        BarIterator::new(self.address.address, self.address.offset + 0x24, MAX_BARS as u8, self.decode(), access)
+    }
+
+    fn decode(&self) -> BarDecode {
+        BarDecode::SriovControl { offset: self.address.offset + 0x08 }
+    }
+
+    /// Create `num_vfs` VFs, enable them and their memory space, and wait the 100ms the specification requires
+    /// before they can be accessed. The VF BARs and the System Page Size should be set up first, and ARI Capable
+    /// Hierarchy set if the PF is the lowest-numbered PF of a device in an ARI hierarchy.
+    pub fn enable_vfs(
+        &self,
+        num_vfs: u16,
+        delay: &impl Delay,
+        access: &impl ConfigRegionAccess,
+    ) -> Result<(), SriovError> {
+        if num_vfs == 0 {
+            return Err(SriovError::NoVfs);
+        }
+        self.set_num_vfs(num_vfs, access)?;
+        self.update_control(access, |mut control| {
+            control.set_vf_enable(true);
+            control.set_vf_memory_space_enable(true);
+            control
+        });
+        delay.delay_us(VF_ENABLE_DELAY_US);
+        Ok(())
+    }
+
+    /// Disable the PF's VFs and their memory space.
+    pub fn disable_vfs(&self, access: &impl ConfigRegionAccess) {
+        self.update_control(access, |mut control| {
+            control.set_vf_enable(false);
+            control.set_vf_memory_space_enable(false);
+            control
+        });
+    }
+
+    /// Get the address of the VF with the given index (from `0`). Routing IDs of VFs can cross bus boundaries, so
+    /// VFs can be on a different bus to the PF. Returns `None` if there is no such VF, or if its Routing ID would
+    /// overflow the last bus.
+    pub fn vf_address(&self, index: u16, access: &impl ConfigRegionAccess) -> Option<PciAddress> {
+        if index >= self.num_vfs(access) {
+            return None;
+        }
+        vf_address(self.address.address, self.first_vf_offset(access), self.vf_stride(access), index)
+    }
+
+    /// Iterate over the addresses of every VF. Iteration stops early if a VF's Routing ID would overflow the last
+    /// bus.
+    pub fn vf_addresses(&self, access: &impl ConfigRegionAccess) -> impl Iterator<Item = PciAddress> {
+        let pf = self.address.address;
+        let offset = self.first_vf_offset(access);
+        let stride = self.vf_stride(access);
+        (0..self.num_vfs(access)).map_while(move |index| vf_address(pf, offset, stride, index))
+    }
+}
+
+fn vf_address(pf: PciAddress, offset: u16, stride: u16, index: u16) -> Option<PciAddress> {
+    let routing_id = pf.routing_id() as u32 + offset as u32 + stride as u32 * index as u32;
+    if routing_id > u16::MAX as u32 {
+        return None;
+    }
+    Some(PciAddress::from_routing_id(pf.segment(), routing_id as u16))
+}
+
+/// The SR-IOV Capabilities register.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SriovCapabilities(u32);
+
+impl SriovCapabilities {
+    pub fn vf_migration_capable(&self) -> bool {
+        self.0.get_bit(0)
+    }
+
+    /// Does the PF keep the ARI Capable Hierarchy bit across resets and power state changes?
+    pub fn ari_capable_hierarchy_preserved(&self) -> bool {
+        self.0.get_bit(1)
+    }
+
+    pub fn vf_10bit_tag_requester_supported(&self) -> bool {
+        self.0.get_bit(2)
+    }
+
+    /// The MSI or MSI-X vector used for VF Migration interrupts.
+    pub fn vf_migration_interrupt_message_number(&self) -> u16 {
+        self.0.get_bits(21..32) as u16
+    }
+}
+
+/// The SR-IOV Control register.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SriovControl(u16);
+
+impl SriovControl {
+    pub fn vf_enable(&self) -> bool {
+        self.0.get_bit(0)
+    }
+
+    pub fn set_vf_enable(&mut self, enabled: bool) {
+        self.0.set_bit(0, enabled);
+    }
+
+    pub fn vf_migration_enable(&self) -> bool {
+        self.0.get_bit(1)
+    }
+
+    pub fn set_vf_migration_enable(&mut self, enabled: bool) {
+        self.0.set_bit(1, enabled);
+    }
+
+    pub fn vf_migration_interrupt_enable(&self) -> bool {
+        self.0.get_bit(2)
+    }
+
+    pub fn set_vf_migration_interrupt_enable(&mut self, enabled: bool) {
+        self.0.set_bit(2, enabled);
+    }
+
+    /// Do the VFs decode their memory BARs? VFs don't implement the Memory Space Enable bit of their own Command
+    /// registers.
+    pub fn vf_memory_space_enable(&self) -> bool {
+        self.0.get_bit(3)
+    }
+
+    pub fn set_vf_memory_space_enable(&mut self, enabled: bool) {
+        self.0.set_bit(3, enabled);
+    }
+
+    /// Is the PF in a hierarchy that supports Alternative Routing-ID Interpretation? This changes the First VF
+    /// Offset and VF Stride, as more functions can be packed onto a bus.
+    pub fn ari_capable_hierarchy(&self) -> bool {
+        self.0.get_bit(4)
+    }
+
+    pub fn set_ari_capable_hierarchy(&mut self, enabled: bool) {
+        self.0.set_bit(4, enabled);
+    }
+
+    pub fn vf_10bit_tag_requester_enable(&self) -> bool {
+        self.0.get_bit(5)
+    }
+
+    pub fn set_vf_10bit_tag_requester_enable(&mut self, enabled: bool) {
+        self.0.set_bit(5, enabled);
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod tests {
+    use super::*;
+    use crate::{
+        access::{MockConfigSpace, MockDelay, MockFunction},
+        capability::PciExtendedCapability,
+        EndpointHeader,
+        HeaderType,
+    };
+    use alloc::vec::Vec;
+    use core::cell::RefCell;
+
+    const ADDRESS: PciAddress = PciAddress(0x0000_0100);
+
+    fn mock_pf(f: impl FnOnce(&mut MockFunction)) -> MockConfigSpace {
+        let mut function = MockFunction::new(0x8086, 0x1234, HeaderType::Endpoint);
+        function.add_extended_capability(0x100, 0x0010, 1);
+        // VF Enable, VF Migration Enable, VF MSE and ARI Capable Hierarchy
+        function.set_write_mask(0x108, 0x0000_001b);
+        // 8 VFs
+        function.set(0x10c, 0x0008_0008);
+        function.set_write_mask(0x110, 0x0000_ffff);
+        f(&mut function);
+        let mut config = MockConfigSpace::new();
+        config.add_function(ADDRESS, function);
+        config
+    }
+
+    fn sriov(config: &MockConfigSpace) -> SriovCapability {
+        EndpointHeader(ADDRESS)
+            .extended_capabilities(config)
+            .find_map(|capability| match capability {
+                PciExtendedCapability::SingleRootIoVirtualization(sriov) => Some(sriov),
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn enable_vfs() {
+        let config = mock_pf(|_| ());
+        let sriov = sriov(&config);
+
+        assert_eq!(sriov.enable_vfs(0, &MockDelay::new(), &config), Err(SriovError::NoVfs));
+        assert_eq!(sriov.enable_vfs(9, &MockDelay::new(), &config), Err(SriovError::TooManyVfs { requested: 9, total: 8 }));
+        assert!(!sriov.control(&config).vf_enable());
+
+        sriov.enable_vfs(4, &MockDelay::new(), &config).unwrap();
+        assert_eq!(sriov.num_vfs(&config), 4);
+        assert!(sriov.control(&config).vf_enable());
+        assert!(sriov.control(&config).vf_memory_space_enable());
+        assert_eq!(sriov.enable_vfs(4, &MockDelay::new(), &config), Err(SriovError::AlreadyEnabled));
+    }
+
+    #[test]
+    fn vf_bar_sizing_disables_vf_memory_space() {
+        struct SizingRecorder {
+            config: MockConfigSpace,
+            controls: RefCell<Vec<u32>>,
+        }
+
+        impl ConfigRegionAccess for SizingRecorder {
+            fn function_exists(&self, address: PciAddress) -> bool {
+                self.config.function_exists(address)
+            }
+
+            unsafe fn read(&self, address: PciAddress, offset: u16) -> u32 {
+                unsafe { self.config.read(address, offset) }
+            }
+
+            unsafe fn write(&self, address: PciAddress, offset: u16, value: u32) {
+                if offset == 0x124 && value | 0xf == 0xffffffff {
+                    let control = unsafe { self.config.read(address, 0x108) } & 0xffff;
+                    self.controls.borrow_mut().push(control);
+                }
+                unsafe { self.config.write(address, offset, value) }
+            }
+        }
+
+        let config = mock_pf(|function| {
+            // A 16KiB VF BAR, with VFs and their memory space enabled
+            function.set(0x124, 0xfe00_0000);
+            function.set_write_mask(0x124, 0xffff_c000);
+            function.set(0x108, 0x0000_0009);
+        });
+        let sriov = sriov(&config);
+        let access = SizingRecorder { config, controls: RefCell::new(Vec::new()) };
+
+        assert!(matches!(
+            sriov.vf_bar(0, &access),
+            Ok(Some(Bar::Memory32 { address: 0xfe00_0000, size: 0x4000, prefetchable: false }))
+        ));
+        assert_eq!(*access.controls.borrow(), [0x0001]);
+        assert_eq!(unsafe { access.read(ADDRESS, 0x108) } & 0xffff, 0x0009);
+        assert_eq!(unsafe { access.read(ADDRESS, 0x124) }, 0xfe00_0000);
+    }
+}
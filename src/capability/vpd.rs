@@ -0,0 +1,638 @@
+use crate::{
+    capability::{Delay, PciCapabilityAddress},
+    ConfigRegionAccess,
+};
+use bit_field::BitField;
+use core::str;
+
+/// How many times the VPD Flag is polled before an access is given up on.
+const VPD_POLL_LIMIT: u32 = 1250;
+/// How long to wait between polls of the VPD Flag, in microseconds. Together with `VPD_POLL_LIMIT`, this allows an
+/// access 125ms to complete.
+const VPD_POLL_INTERVAL_US: u32 = 100;
+
+/// The VPD Address register is 15 bits wide.
+const VPD_MAX_ADDRESS: u16 = 0x7fff;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum VpdError {
+    /// VPD can only be accessed a dword at a time, so addresses must be 4-byte aligned.
+    Misaligned,
+    /// The address doesn't fit in the 15-bit VPD Address register.
+    OutOfRange,
+    /// The function didn't complete the access in time.
+    Timeout,
+}
+
+/// The Vital Product Data capability, which gives access to a small amount of storage (usually an EEPROM)
+/// describing the device, such as its part and serial numbers. It has the form:
+/// ```ignore
+///     32              24              16              8               0
+///      +-+-----------------------------+---------------+---------------+
+///      |F|         VPD Address         |     Next      |    Cap ID     | 0x0
+///      | |                             |    Pointer    |               |
+///      +-+-----------------------------+---------------+---------------+
+///      |                           VPD Data                            | 0x4
+///      +---------------------------------------------------------------+
+/// ```
+/// Accesses are started by writing the address along with the F(lag) bit, and the function flips the flag when the
+/// access has completed. The contents of VPD can be parsed with [`Vpd`].
+#[derive(Debug, Clone)]
+pub struct VpdCapability {
+    address: PciCapabilityAddress,
+}
+
+impl VpdCapability {
+    pub(crate) fn new(address: PciCapabilityAddress) -> VpdCapability {
+        VpdCapability { address }
+    }
+
+    /// Read the dword of VPD at `address`.
+    pub fn read(
+        &self,
+        address: u16,
+        delay: &impl Delay,
+        access: &impl ConfigRegionAccess,
+    ) -> Result<u32, VpdError> {
+        Self::check_address(address)?;
+
+        self.write_address(address, false, access);
+        self.wait_for_flag(true, delay, access)?;
+        Ok(unsafe { access.read(self.address.address, self.address.offset + 0x4) })
+    }
+
+    /// Read VPD starting at `address` into `buffer`. The whole buffer is filled, so reading must not run past the
+    /// end of the VPD address space.
+    pub fn read_bytes(
+        &self,
+        address: u16,
+        buffer: &mut [u8],
+        delay: &impl Delay,
+        access: &impl ConfigRegionAccess,
+    ) -> Result<(), VpdError> {
+        for (i, chunk) in buffer.chunks_mut(4).enumerate() {
+            let chunk_address = address as usize + i * 4;
+            if chunk_address > VPD_MAX_ADDRESS as usize {
+                return Err(VpdError::OutOfRange);
+            }
+
+            let data = self.read(chunk_address as u16, delay, access)?.to_le_bytes();
+            chunk.copy_from_slice(&data[..chunk.len()]);
+        }
+        Ok(())
+    }
+
+    /// Write a dword of VPD at `address`. Only the read-write parts of VPD (such as the `VPD-W` resource) should
+    /// be written to.
+    pub fn write(
+        &self,
+        address: u16,
+        data: u32,
+        delay: &impl Delay,
+        access: &impl ConfigRegionAccess,
+    ) -> Result<(), VpdError> {
+        Self::check_address(address)?;
+
+        unsafe {
+            access.write(self.address.address, self.address.offset + 0x4, data);
+        }
+        self.write_address(address, true, access);
+        self.wait_for_flag(false, delay, access)
+    }
+
+    fn check_address(address: u16) -> Result<(), VpdError> {
+        if address > VPD_MAX_ADDRESS {
+            Err(VpdError::OutOfRange)
+        } else if address & 0x3 != 0 {
+            Err(VpdError::Misaligned)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn write_address(&self, address: u16, flag: bool, access: &impl ConfigRegionAccess) {
+        let mut data = unsafe { access.read(self.address.address, self.address.offset) };
+        data.set_bits(16..31, address as u32);
+        data.set_bit(31, flag);
+        unsafe {
+            access.write(self.address.address, self.address.offset, data);
+        }
+    }
+
+    fn wait_for_flag(
+        &self,
+        flag: bool,
+        delay: &impl Delay,
+        access: &impl ConfigRegionAccess,
+    ) -> Result<(), VpdError> {
+        for _ in 0..VPD_POLL_LIMIT {
+            if unsafe { access.read(self.address.address, self.address.offset) }.get_bit(31) == flag {
+                return Ok(());
+            }
+            delay.delay_us(VPD_POLL_INTERVAL_US);
+        }
+        Err(VpdError::Timeout)
+    }
+}
+
+/*
+ * Tags of the resources that make up VPD. Large resource tags have bit 7 set, and are followed by a 16-bit length.
+ * Small resource tags have the length in bits 0..3.
+ */
+const TAG_IDENTIFIER_STRING: u8 = 0x82;
+const TAG_VPD_R: u8 = 0x90;
+const TAG_VPD_W: u8 = 0x91;
+const TAG_END: u8 = 0x0f;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum VpdParseError {
+    /// A resource extends past the end of the data.
+    Truncated { offset: usize },
+}
+
+/// The contents of a function's VPD, which is made up of a series of resources:
+/// ```ignore
+///     +---------------------------+
+///     | Identifier String (0x82)  |  The name of the device
+///     +---------------------------+
+///     | VPD-R (0x90)              |  Read-only keywords, e.g. PN, SN, EC, MN, and the RV checksum
+///     +---------------------------+
+///     | VPD-W (0x91)              |  Read-write keywords
+///     +---------------------------+
+///     | End Tag (0x78)            |
+///     +---------------------------+
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct Vpd<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Vpd<'a> {
+    /// Parse VPD that has been read from the function, e.g. with [`VpdCapability::read_bytes`]. `data` should
+    /// start at VPD address `0`.
+    pub fn new(data: &'a [u8]) -> Vpd<'a> {
+        Vpd { data }
+    }
+
+    /// Iterate over the resources in the VPD. Iteration stops at the End Tag, or after a resource can't be parsed.
+    pub fn resources(&self) -> VpdResourceIterator<'a> {
+        VpdResourceIterator { data: self.data, offset: 0, done: false }
+    }
+
+    /// Get the Identifier String, which names the device.
+    pub fn identifier(&self) -> Option<&'a str> {
+        self.resources().map_while(Result::ok).find_map(|resource| match resource {
+            VpdResource::IdentifierString(data) => str::from_utf8(data).ok(),
+            _ => None,
+        })
+    }
+
+    /// Get the read-only keywords, from the `VPD-R` resource.
+    pub fn read_only(&self) -> Option<VpdKeywords<'a>> {
+        self.resources().map_while(Result::ok).find_map(|resource| match resource {
+            VpdResource::ReadOnly(keywords) => Some(keywords),
+            _ => None,
+        })
+    }
+
+    /// Get the read-write keywords, from the `VPD-W` resource.
+    pub fn read_write(&self) -> Option<VpdKeywords<'a>> {
+        self.resources().map_while(Result::ok).find_map(|resource| match resource {
+            VpdResource::ReadWrite(keywords) => Some(keywords),
+            _ => None,
+        })
+    }
+
+    /// Get the data of a keyword, looking first in the read-only keywords and then in the read-write ones.
+    pub fn keyword(&self, keyword: [u8; 2]) -> Option<&'a [u8]> {
+        self.read_only()
+            .and_then(|keywords| keywords.get(keyword))
+            .or_else(|| self.read_write().and_then(|keywords| keywords.get(keyword)))
+    }
+
+    /// Get the part number of the device (the `PN` keyword).
+    pub fn part_number(&self) -> Option<&'a str> {
+        self.keyword_str(*b"PN")
+    }
+
+    /// Get the serial number of the device (the `SN` keyword).
+    pub fn serial_number(&self) -> Option<&'a str> {
+        self.keyword_str(*b"SN")
+    }
+
+    /// Get the engineering change level of the device (the `EC` keyword).
+    pub fn engineering_change(&self) -> Option<&'a str> {
+        self.keyword_str(*b"EC")
+    }
+
+    /// Get the manufacturer ID of the device (the `MN` keyword).
+    pub fn manufacturer_id(&self) -> Option<&'a str> {
+        self.keyword_str(*b"MN")
+    }
+
+    /// Check the `RV` keyword of the read-only keywords, whose first byte is chosen so that all of the bytes from
+    /// the start of VPD up to and including it sum to zero. Returns `None` if there is no `RV` keyword.
+    pub fn checksum_is_valid(&self) -> Option<bool> {
+        let checksum = self.read_only()?.iter().find(|keyword| keyword.keyword == *b"RV")?;
+        if checksum.data.is_empty() {
+            return Some(false);
+        }
+
+        let sum = self.data[..=checksum.offset].iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+        Some(sum == 0)
+    }
+
+    fn keyword_str(&self, keyword: [u8; 2]) -> Option<&'a str> {
+        self.keyword(keyword).and_then(|data| str::from_utf8(data).ok())
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum VpdResource<'a> {
+    IdentifierString(&'a [u8]),
+    ReadOnly(VpdKeywords<'a>),
+    ReadWrite(VpdKeywords<'a>),
+    /// A resource this crate doesn't know about. For large resources, `tag` includes bit 7. For small resources,
+    /// it doesn't include the length.
+    Other {
+        tag: u8,
+        data: &'a [u8],
+    },
+}
+
+/// Iterates over the resources of VPD. Created by [`Vpd::resources`].
+pub struct VpdResourceIterator<'a> {
+    data: &'a [u8],
+    offset: usize,
+    done: bool,
+}
+
+impl<'a> Iterator for VpdResourceIterator<'a> {
+    type Item = Result<VpdResource<'a>, VpdParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done || self.offset >= self.data.len() {
+            return None;
+        }
+
+        let offset = self.offset;
+        let tag = self.data[offset];
+        let (tag, start, length) = if tag.get_bit(7) {
+            if self.data.len() < offset + 3 {
+                self.done = true;
+                return Some(Err(VpdParseError::Truncated { offset }));
+            }
+            (tag, offset + 3, u16::from_le_bytes([self.data[offset + 1], self.data[offset + 2]]) as usize)
+        } else {
+            (tag.get_bits(3..7), offset + 1, tag.get_bits(0..3) as usize)
+        };
+
+        if tag == TAG_END {
+            self.done = true;
+            return None;
+        }
+        if self.data.len() < start + length {
+            self.done = true;
+            return Some(Err(VpdParseError::Truncated { offset }));
+        }
+
+        let data = &self.data[start..(start + length)];
+        self.offset = start + length;
+
+        Some(Ok(match tag {
+            TAG_IDENTIFIER_STRING => VpdResource::IdentifierString(data),
+            TAG_VPD_R => VpdResource::ReadOnly(VpdKeywords { data, offset: start }),
+            TAG_VPD_W => VpdResource::ReadWrite(VpdKeywords { data, offset: start }),
+            _ => VpdResource::Other { tag, data },
+        }))
+    }
+}
+
+/// The keywords of a `VPD-R` or `VPD-W` resource. Each keyword is two ASCII characters, followed by a length byte
+/// and the keyword's data.
+#[derive(Clone, Copy, Debug)]
+pub struct VpdKeywords<'a> {
+    data: &'a [u8],
+    /// The offset of `data` from the start of VPD.
+    offset: usize,
+}
+
+impl<'a> VpdKeywords<'a> {
+    /// Iterate over the keywords. Iteration stops if a keyword runs past the end of the resource.
+    pub fn iter(&self) -> VpdKeywordIterator<'a> {
+        VpdKeywordIterator { keywords: *self, position: 0 }
+    }
+
+    /// Get the data of `keyword`, if it is present.
+    pub fn get(&self, keyword: [u8; 2]) -> Option<&'a [u8]> {
+        self.iter().find(|field| field.keyword == keyword).map(|field| field.data)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct VpdKeyword<'a> {
+    pub keyword: [u8; 2],
+    pub data: &'a [u8],
+    /// The offset of `data` from the start of VPD.
+    offset: usize,
+}
+
+/// Iterates over the keywords of a `VPD-R` or `VPD-W` resource. Created by [`VpdKeywords::iter`].
+pub struct VpdKeywordIterator<'a> {
+    keywords: VpdKeywords<'a>,
+    position: usize,
+}
+
+impl<'a> Iterator for VpdKeywordIterator<'a> {
+    type Item = VpdKeyword<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let data = self.keywords.data;
+        let position = self.position;
+        if data.len() < position + 3 {
+            return None;
+        }
+
+        let length = data[position + 2] as usize;
+        let start = position + 3;
+        if data.len() < start + length {
+            return None;
+        }
+
+        self.position = start + length;
+        Some(VpdKeyword {
+            keyword: [data[position], data[position + 1]],
+            data: &data[start..(start + length)],
+            offset: self.keywords.offset + start,
+        })
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod tests {
+    use super::*;
+    use crate::{
+        access::{MockConfigSpace, MockDelay, MockFunction},
+        capability::PciCapability,
+        EndpointHeader,
+        PciAddress,
+    };
+    use alloc::{vec, vec::Vec};
+    use core::cell::{Cell, RefCell};
+
+    const ADDRESS: PciAddress = PciAddress(0x0000_0100);
+    const IDENTIFIER: &str = "Example Ethernet Adapter";
+
+    /// Build VPD made up of an Identifier String, a `VPD-R` resource holding `keywords` followed by an `RV`
+    /// keyword (a valid checksum and two reserved bytes), and an End Tag.
+    fn image(keywords: &[(&[u8; 2], &[u8])]) -> Vec<u8> {
+        let mut data = vec![TAG_IDENTIFIER_STRING];
+        data.extend_from_slice(&(IDENTIFIER.len() as u16).to_le_bytes());
+        data.extend_from_slice(IDENTIFIER.as_bytes());
+
+        let length = keywords.iter().map(|(_, value)| 3 + value.len()).sum::<usize>() + 6;
+        data.push(TAG_VPD_R);
+        data.extend_from_slice(&(length as u16).to_le_bytes());
+        for (keyword, value) in keywords {
+            data.extend_from_slice(&keyword[..]);
+            data.push(value.len() as u8);
+            data.extend_from_slice(value);
+        }
+        data.extend_from_slice(b"RV\x03");
+        let sum = data.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+        data.extend_from_slice(&[sum.wrapping_neg(), 0x00, 0x00]);
+
+        data.push(0x78);
+        data
+    }
+
+    fn adapter() -> Vec<u8> {
+        image(&[(b"PN", b"ABC-123"), (b"EC", b"A1"), (b"SN", b"SN00042"), (b"MN", b"8086")])
+    }
+
+    #[test]
+    fn identifier_and_keywords() {
+        let data = adapter();
+        let vpd = Vpd::new(&data);
+
+        assert_eq!(vpd.identifier(), Some(IDENTIFIER));
+        assert_eq!(vpd.part_number(), Some("ABC-123"));
+        assert_eq!(vpd.engineering_change(), Some("A1"));
+        assert_eq!(vpd.serial_number(), Some("SN00042"));
+        assert_eq!(vpd.manufacturer_id(), Some("8086"));
+        assert_eq!(vpd.keyword(*b"V1"), None);
+        assert!(vpd.read_write().is_none());
+
+        let keywords: Vec<[u8; 2]> = vpd.read_only().unwrap().iter().map(|keyword| keyword.keyword).collect();
+        assert_eq!(keywords, [*b"PN", *b"EC", *b"SN", *b"MN", *b"RV"]);
+    }
+
+    #[test]
+    fn checksum() {
+        let mut data = adapter();
+        assert_eq!(Vpd::new(&data).checksum_is_valid(), Some(true));
+
+        /*
+         * Only the bytes up to and including the checksum are covered, so the reserved bytes after it, and
+         * anything following the `VPD-R` resource, don't matter.
+         */
+        let end = data.len() - 1;
+        data[end - 2] = 0xff;
+        data.push(0xaa);
+        assert_eq!(Vpd::new(&data).checksum_is_valid(), Some(true));
+
+        data[3] ^= 0x20;
+        assert_eq!(Vpd::new(&data).checksum_is_valid(), Some(false));
+
+        // An Identifier String and a `VPD-R` resource with only a `PN` keyword
+        let data = [0x82, 0x01, 0x00, b'x', 0x90, 0x04, 0x00, b'P', b'N', 0x01, b'y', 0x78];
+        assert_eq!(Vpd::new(&data).checksum_is_valid(), None);
+    }
+
+    #[test]
+    fn truncated_large_tag() {
+        // The length of the large resource is cut off
+        let data = [0x82, 0x05];
+        let mut resources = Vpd::new(&data).resources();
+        assert!(matches!(resources.next(), Some(Err(VpdParseError::Truncated { offset: 0 }))));
+        assert!(resources.next().is_none());
+
+        /*
+         * A `VPD-R` resource claiming more data than there is. The resources before it can still be found.
+         */
+        let mut data = image(&[(b"PN", b"ABC-123")]);
+        data.truncate(data.len() - 3);
+        let vpd = Vpd::new(&data);
+        let vpd_r = 3 + IDENTIFIER.len();
+        let resource = vpd.resources().nth(1);
+        assert!(matches!(resource, Some(Err(VpdParseError::Truncated { offset })) if offset == vpd_r));
+        assert_eq!(vpd.identifier(), Some(IDENTIFIER));
+        assert!(vpd.read_only().is_none());
+        assert_eq!(vpd.part_number(), None);
+        assert_eq!(vpd.checksum_is_valid(), None);
+    }
+
+    #[test]
+    fn truncated_small_tag() {
+        // A small resource with tag `0x4`, and two bytes of data
+        let data = [0x22, 0x01, 0x02, 0x78];
+        let mut resources = Vpd::new(&data).resources();
+        assert!(matches!(resources.next(), Some(Ok(VpdResource::Other { tag: 0x4, data: [0x01, 0x02] }))));
+        assert!(resources.next().is_none());
+
+        let data = [0x22, 0x01];
+        let mut resources = Vpd::new(&data).resources();
+        assert!(matches!(resources.next(), Some(Err(VpdParseError::Truncated { offset: 0 }))));
+        assert!(resources.next().is_none());
+    }
+
+    #[test]
+    fn keyword_past_resource() {
+        /*
+         * The `SN` keyword claims 8 bytes, but only 2 are left in the `VPD-R` resource. The bytes after the
+         * resource must not be taken as part of the keyword.
+         */
+        let data = [
+            0x90, 0x0b, 0x00, b'P', b'N', 0x03, b'a', b'b', b'c', b'S', b'N', 0x08, b'd', b'e', 0x78, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00,
+        ];
+        let vpd = Vpd::new(&data);
+
+        assert_eq!(vpd.part_number(), Some("abc"));
+        assert_eq!(vpd.serial_number(), None);
+        assert_eq!(vpd.read_only().unwrap().iter().count(), 1);
+    }
+
+    /// Emulates a function with a VPD capability at `0x40`, backed by `storage`. Each access completes after the
+    /// capability has been polled `latency` more times, or never if `latency` is `None`.
+    struct VpdFunction {
+        config: RefCell<MockConfigSpace>,
+        storage: RefCell<Vec<u8>>,
+        latency: Option<u32>,
+        pending: Cell<Option<u32>>,
+    }
+
+    impl VpdFunction {
+        fn new(storage: Vec<u8>, latency: Option<u32>) -> VpdFunction {
+            let mut function = MockFunction::endpoint();
+            function.add_capability(0x40, 0x03);
+            function.set_write_mask(0x40, 0xffff_0000);
+            function.set_write_mask(0x44, 0xffff_ffff);
+            let mut config = MockConfigSpace::new();
+            config.add_function(ADDRESS, function);
+
+            VpdFunction {
+                config: RefCell::new(config),
+                storage: RefCell::new(storage),
+                latency,
+                pending: Cell::new(None),
+            }
+        }
+
+        fn complete(&self) {
+            let mut config = self.config.borrow_mut();
+            let function = config.function(ADDRESS).unwrap();
+            let header = function.get(0x40);
+            let address = header.get_bits(16..31) as usize;
+            let mut storage = self.storage.borrow_mut();
+
+            if header.get_bit(31) {
+                storage[address..(address + 4)].copy_from_slice(&function.get(0x44).to_le_bytes());
+            } else {
+                let mut data = [0; 4];
+                data.copy_from_slice(&storage[address..(address + 4)]);
+                function.set(0x44, u32::from_le_bytes(data));
+            }
+            function.set(0x40, header ^ (1 << 31));
+        }
+    }
+
+    impl ConfigRegionAccess for VpdFunction {
+        fn function_exists(&self, address: PciAddress) -> bool {
+            self.config.borrow().function_exists(address)
+        }
+
+        unsafe fn read(&self, address: PciAddress, offset: u16) -> u32 {
+            if offset == 0x40 {
+                match self.pending.get() {
+                    Some(0) => {
+                        self.pending.set(None);
+                        self.complete();
+                    }
+                    Some(polls) => self.pending.set(Some(polls - 1)),
+                    None => (),
+                }
+            }
+            unsafe { self.config.borrow().read(address, offset) }
+        }
+
+        unsafe fn write(&self, address: PciAddress, offset: u16, value: u32) {
+            unsafe { self.config.borrow().write(address, offset, value) };
+            if offset == 0x40 {
+                self.pending.set(self.latency);
+            }
+        }
+    }
+
+    fn vpd_capability(access: &VpdFunction) -> VpdCapability {
+        EndpointHeader(ADDRESS)
+            .capabilities(access)
+            .find_map(|capability| match capability {
+                PciCapability::VitalProductData(vpd) => Some(vpd),
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn read() {
+        let mut storage = adapter();
+        storage.resize(0x80, 0xff);
+        let access = VpdFunction::new(storage, Some(3));
+        let capability = vpd_capability(&access);
+        let delay = MockDelay::new();
+
+        /*
+         * The flag is polled once straight after the address is written, and then after each delay.
+         */
+        assert_eq!(capability.read(0x4, &delay, &access), Ok(u32::from_le_bytes(*b"xamp")));
+        assert_eq!(delay.delays(), [VPD_POLL_INTERVAL_US; 3]);
+
+        let mut data = vec![0; adapter().len()];
+        capability.read_bytes(0x0, &mut data, &delay, &access).unwrap();
+        assert_eq!(data, adapter());
+        assert_eq!(Vpd::new(&data).part_number(), Some("ABC-123"));
+
+        assert_eq!(capability.read(0x2, &delay, &access), Err(VpdError::Misaligned));
+        assert_eq!(capability.read(0x8000, &delay, &access), Err(VpdError::OutOfRange));
+    }
+
+    #[test]
+    fn write() {
+        let access = VpdFunction::new(vec![0; 16], Some(2));
+        let capability = vpd_capability(&access);
+        let delay = MockDelay::new();
+
+        assert_eq!(capability.write(0x8, 0xdead_beef, &delay, &access), Ok(()));
+        assert_eq!(delay.delays(), [VPD_POLL_INTERVAL_US; 2]);
+        assert_eq!(access.storage.borrow()[8..12], 0xdead_beef_u32.to_le_bytes());
+        assert_eq!(capability.read(0x8, &delay, &access), Ok(0xdead_beef));
+
+        assert_eq!(capability.write(0xe, 0, &delay, &access), Err(VpdError::Misaligned));
+    }
+
+    #[test]
+    fn timeout() {
+        let access = VpdFunction::new(vec![0; 16], None);
+        let capability = vpd_capability(&access);
+
+        let delay = MockDelay::new();
+        assert_eq!(capability.read(0x0, &delay, &access), Err(VpdError::Timeout));
+        assert_eq!(delay.delays().len(), VPD_POLL_LIMIT as usize);
+
+        let delay = MockDelay::new();
+        assert_eq!(capability.write(0x0, 0x1234_5678, &delay, &access), Err(VpdError::Timeout));
+        assert_eq!(delay.delays().len(), VPD_POLL_LIMIT as usize);
+        assert_eq!(*access.storage.borrow(), [0; 16]);
+    }
+}
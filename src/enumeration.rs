@@ -0,0 +1,398 @@
+use crate::{
+    BaseClass,
+    ConfigRegionAccess,
+    DeviceId,
+    DeviceRevision,
+    HeaderType,
+    Interface,
+    PciAddress,
+    PciHeader,
+    PciPciBridgeHeader,
+    SubClass,
+    VendorId,
+};
+
+/// A function found by a [`PciEnumerator`].
+#[derive(Clone, Copy, Debug)]
+pub struct EnumeratedFunction {
+    pub address: PciAddress,
+    pub vendor_id: VendorId,
+    pub device_id: DeviceId,
+    pub revision: DeviceRevision,
+    pub base_class: BaseClass,
+    pub sub_class: SubClass,
+    pub interface: Interface,
+    pub header_type: HeaderType,
+}
+
+impl EnumeratedFunction {
+    pub fn header(&self) -> PciHeader {
+        PciHeader::new(self.address)
+    }
+}
+
+/// Iterates over every function on a segment. Functions `1..8` of a device are only checked if function `0`
+/// reports that the device has multiple functions.
+///
+/// The enumerator can work in two modes:
+///    - brute-force, created with [`PciEnumerator::brute_force`], which checks every device on every bus
+///    - recursive, created with [`PciEnumerator::recursive`], which starts from a root bus and only checks the
+///      buses behind the PCI-PCI bridges it finds. This is much faster, but relies on the bridges' bus numbers
+///      having been set up (e.g. by firmware).
+///
+/// Buses are scanned in increasing order, and each bus is only ever scanned once, so misconfigured bridges can't
+/// cause the enumerator to loop. The enumerator doesn't allocate.
+pub struct PciEnumerator<'a, T: ConfigRegionAccess> {
+    access: &'a T,
+    segment: u16,
+    follow_bridges: bool,
+    /// Bitmap of buses that still have to be scanned
+    pending_buses: [u64; 4],
+    /// Bitmap of buses that have been scanned
+    scanned_buses: [u64; 4],
+    /// The bus currently being scanned, and the position of the next function to check on it
+    bus: Option<u8>,
+    device: u8,
+    function: u8,
+    multiple_functions: bool,
+}
+
+impl<'a, T: ConfigRegionAccess> PciEnumerator<'a, T> {
+    /// Create an enumerator that checks every device on every bus of `segment`.
+    pub fn brute_force(segment: u16, access: &'a T) -> PciEnumerator<'a, T> {
+        PciEnumerator::new(segment, [u64::MAX; 4], false, access)
+    }
+
+    /// Create an enumerator that only checks the devices on a single bus of `segment`.
+    pub fn single_bus(segment: u16, bus: u8, access: &'a T) -> PciEnumerator<'a, T> {
+        let mut pending_buses = [0; 4];
+        set_bus(&mut pending_buses, bus);
+        PciEnumerator::new(segment, pending_buses, false, access)
+    }
+
+    /// Create an enumerator that checks every device on `root_bus` of `segment`, and recursively on the buses
+    /// behind any PCI-PCI bridges it finds.
+    pub fn recursive(segment: u16, root_bus: u8, access: &'a T) -> PciEnumerator<'a, T> {
+        let mut pending_buses = [0; 4];
+        set_bus(&mut pending_buses, root_bus);
+        PciEnumerator::new(segment, pending_buses, true, access)
+    }
+
+    fn new(segment: u16, pending_buses: [u64; 4], follow_bridges: bool, access: &'a T) -> PciEnumerator<'a, T> {
+        PciEnumerator {
+            access,
+            segment,
+            follow_bridges,
+            pending_buses,
+            scanned_buses: [0; 4],
+            bus: None,
+            device: 0,
+            function: 0,
+            multiple_functions: false,
+        }
+    }
+
+    /// Move on to the next bus that has to be scanned, returning `false` if there are none left.
+    fn next_bus(&mut self) -> bool {
+        match (0..4).find(|&i| self.pending_buses[i] != 0) {
+            Some(i) => {
+                let bus = (i as u32 * 64 + self.pending_buses[i].trailing_zeros()) as u8;
+                clear_bus(&mut self.pending_buses, bus);
+                set_bus(&mut self.scanned_buses, bus);
+                self.bus = Some(bus);
+                self.device = 0;
+                self.function = 0;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn next_device(&mut self) {
+        self.device += 1;
+        self.function = 0;
+    }
+
+    fn next_function(&mut self) {
+        if self.function == 7 || !self.multiple_functions {
+            self.next_device();
+        } else {
+            self.function += 1;
+        }
+    }
+}
+
+impl<'a, T: ConfigRegionAccess> Iterator for PciEnumerator<'a, T> {
+    type Item = EnumeratedFunction;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let bus = match self.bus {
+                Some(bus) => bus,
+                None => {
+                    if self.next_bus() {
+                        continue;
+                    }
+                    return None;
+                }
+            };
+
+            if self.device == 32 {
+                self.bus = None;
+                continue;
+            }
+
+            let address = PciAddress::new(self.segment, bus, self.device, self.function);
+            if !self.access.function_exists(address) {
+                if self.function == 0 {
+                    self.next_device();
+                } else {
+                    self.next_function();
+                }
+                continue;
+            }
+
+            let header = PciHeader::new(address);
+            if self.function == 0 {
+                self.multiple_functions = header.has_multiple_functions(self.access);
+            }
+            self.next_function();
+
+            let (vendor_id, device_id) = header.id(self.access);
+            let (revision, base_class, sub_class, interface) = header.revision_and_class(self.access);
+            let header_type = header.header_type(self.access);
+
+            if self.follow_bridges && header_type == HeaderType::PciPciBridge {
+                let bridge = PciPciBridgeHeader::from_header(header, self.access).unwrap();
+                let secondary = bridge.secondary_bus_number(self.access);
+                let subordinate = bridge.subordinate_bus_number(self.access);
+
+                /*
+                 * A secondary bus number of `0` means that the bridge hasn't been configured.
+                 */
+                if secondary != 0 && secondary <= subordinate && !is_bus_set(&self.scanned_buses, secondary) {
+                    set_bus(&mut self.pending_buses, secondary);
+                }
+            }
+
+            return Some(EnumeratedFunction {
+                address,
+                vendor_id,
+                device_id,
+                revision,
+                base_class,
+                sub_class,
+                interface,
+                header_type,
+            });
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BusNumberingError {
+    /// The hierarchy has more bridges than there are bus numbers left on the segment.
+    OutOfBusNumbers,
+}
+
+/// Assign bus numbers to every PCI-PCI bridge below `root_bus` of `segment`, replacing any numbers they already
+/// have. Bridges are numbered depth-first, in the order they are found, starting from `root_bus + 1`: each
+/// bridge is given the next free bus number as its secondary bus, the hierarchy behind it is numbered, and its
+/// subordinate bus number is then set to the highest bus number behind it.
+///
+/// Returns the highest bus number that was assigned, which is `root_bus` if there are no bridges. The bridges'
+/// forwarding windows and the devices behind them are not touched.
+///
+/// If the bus numbers run out, the bridges that were numbered are left forwarding only the buses that were
+/// assigned behind them, and the bridges that weren't have their bus numbers cleared.
+pub fn assign_bus_numbers(
+    segment: u16,
+    root_bus: u8,
+    access: &impl ConfigRegionAccess,
+) -> Result<u8, BusNumberingError> {
+    let mut next_bus = root_bus as u16 + 1;
+    number_bus(segment, root_bus, &mut next_bus, access)
+}
+
+/// Number the bridges on `bus`, and the hierarchies behind them, returning the highest bus number below `bus`.
+fn number_bus(
+    segment: u16,
+    bus: u8,
+    next_bus: &mut u16,
+    access: &impl ConfigRegionAccess,
+) -> Result<u8, BusNumberingError> {
+    /*
+     * Clear the bus numbers of every bridge on the bus before numbering any of them, so bridges we haven't got to
+     * yet can't claim configuration transactions meant for buses we have numbered.
+     */
+    for function in PciEnumerator::single_bus(segment, bus, access) {
+        if let Some(bridge) = PciPciBridgeHeader::from_header(function.header(), access) {
+            bridge.set_bus_numbers(0, 0, 0, access);
+        }
+    }
+
+    let mut highest_bus = bus;
+    for function in PciEnumerator::single_bus(segment, bus, access) {
+        if let Some(bridge) = PciPciBridgeHeader::from_header(function.header(), access) {
+            if *next_bus > u8::MAX as u16 {
+                return Err(BusNumberingError::OutOfBusNumbers);
+            }
+            let secondary = *next_bus as u8;
+            *next_bus += 1;
+
+            /*
+             * Until we know how many buses are behind the bridge, let it forward configuration transactions for
+             * every bus above its secondary bus.
+             */
+            bridge.set_bus_numbers(bus, secondary, u8::MAX, access);
+            let subordinate = match number_bus(segment, secondary, next_bus, access) {
+                Ok(subordinate) => subordinate,
+                Err(err) => {
+                    /*
+                     * Buses are numbered depth-first, so every bus assigned since the secondary bus is behind
+                     * this bridge.
+                     */
+                    bridge.set_subordinate_bus_number((*next_bus - 1) as u8, access);
+                    return Err(err);
+                }
+            };
+            bridge.set_subordinate_bus_number(subordinate, access);
+            highest_bus = subordinate;
+        }
+    }
+
+    Ok(highest_bus)
+}
+
+fn set_bus(buses: &mut [u64; 4], bus: u8) {
+    buses[bus as usize / 64] |= 1 << (bus % 64);
+}
+
+fn clear_bus(buses: &mut [u64; 4], bus: u8) {
+    buses[bus as usize / 64] &= !(1 << (bus % 64));
+}
+
+fn is_bus_set(buses: &[u64; 4], bus: u8) -> bool {
+    buses[bus as usize / 64] & (1 << (bus % 64)) != 0
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod tests {
+    use super::*;
+    use crate::access::{MockConfigSpace, MockFunction};
+    use alloc::vec::Vec;
+
+    fn bus_numbers(config: &MockConfigSpace, address: PciAddress) -> (u8, u8, u8) {
+        let bridge = PciPciBridgeHeader::from_header(PciHeader::new(address), config).unwrap();
+        (
+            bridge.primary_bus_number(config),
+            bridge.secondary_bus_number(config),
+            bridge.subordinate_bus_number(config),
+        )
+    }
+
+    fn addresses(enumerator: impl Iterator<Item = EnumeratedFunction>) -> Vec<PciAddress> {
+        enumerator.map(|function| function.address).collect()
+    }
+
+    #[test]
+    fn multiple_functions() {
+        let mut config = MockConfigSpace::new();
+        let mut function = MockFunction::endpoint();
+        function.set_multiple_functions(true);
+        config.add_function(PciAddress::new(0, 0, 1, 0), function);
+        config.add_function(PciAddress::new(0, 0, 1, 5), MockFunction::endpoint());
+        // Only function 0 of a single-function device is checked
+        config.add_function(PciAddress::new(0, 0, 2, 0), MockFunction::endpoint());
+        config.add_function(PciAddress::new(0, 0, 2, 1), MockFunction::endpoint());
+        // Devices without a function 0 are skipped
+        config.add_function(PciAddress::new(0, 0, 3, 1), MockFunction::endpoint());
+
+        assert_eq!(
+            addresses(PciEnumerator::single_bus(0, 0, &config)),
+            [PciAddress::new(0, 0, 1, 0), PciAddress::new(0, 0, 1, 5), PciAddress::new(0, 0, 2, 0)]
+        );
+    }
+
+    #[test]
+    fn brute_force() {
+        let mut config = MockConfigSpace::new();
+        config.add_function(PciAddress::new(0, 0, 0, 0), MockFunction::endpoint());
+        config.add_function(PciAddress::new(0, 7, 31, 0), MockFunction::endpoint());
+        config.add_function(PciAddress::new(0, 255, 0, 0), MockFunction::endpoint());
+        config.add_function(PciAddress::new(1, 0, 1, 0), MockFunction::endpoint());
+
+        let functions: Vec<EnumeratedFunction> = PciEnumerator::brute_force(0, &config).collect();
+        assert_eq!(
+            functions.iter().map(|function| function.address).collect::<Vec<_>>(),
+            [PciAddress::new(0, 0, 0, 0), PciAddress::new(0, 7, 31, 0), PciAddress::new(0, 255, 0, 0)]
+        );
+        assert_eq!((functions[0].vendor_id, functions[0].device_id), (0x8086, 0x1234));
+        assert_eq!(functions[0].header_type, HeaderType::Endpoint);
+    }
+
+    #[test]
+    fn recursive() {
+        let mut config = MockConfigSpace::new();
+        config.add_function(PciAddress::new(0, 0, 0, 0), MockFunction::endpoint());
+        config.add_function(PciAddress::new(0, 0, 1, 0), MockFunction::bridge(0, 2, 3));
+        config.add_function(PciAddress::new(0, 2, 0, 0), MockFunction::bridge(2, 3, 3));
+        config.add_function(PciAddress::new(0, 3, 0, 0), MockFunction::endpoint());
+        // An unconfigured bridge, whose secondary bus isn't scanned
+        config.add_function(PciAddress::new(0, 0, 2, 0), MockFunction::bridge(0, 0, 0));
+        // A bus that isn't behind any bridge
+        config.add_function(PciAddress::new(0, 5, 0, 0), MockFunction::endpoint());
+        // A bridge that points back at the root bus doesn't cause it to be scanned again
+        config.add_function(PciAddress::new(0, 3, 1, 0), MockFunction::bridge(3, 0, 0xff));
+
+        assert_eq!(
+            addresses(PciEnumerator::recursive(0, 0, &config)),
+            [
+                PciAddress::new(0, 0, 0, 0),
+                PciAddress::new(0, 0, 1, 0),
+                PciAddress::new(0, 0, 2, 0),
+                PciAddress::new(0, 2, 0, 0),
+                PciAddress::new(0, 3, 0, 0),
+                PciAddress::new(0, 3, 1, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn assign_bus_numbers_depth_first() {
+        let mut config = MockConfigSpace::new();
+        // Stale bus numbers are replaced
+        config.add_function(PciAddress::new(0, 0, 1, 0), MockFunction::bridge(0, 9, 9));
+        config.add_function(PciAddress::new(0, 0, 2, 0), MockFunction::bridge(0, 0, 0));
+        config.add_function(PciAddress::new(0, 0, 3, 0), MockFunction::endpoint());
+
+        // There is nothing behind the bridges yet, so give them their children once they have been numbered
+        assert_eq!(assign_bus_numbers(0, 0, &config), Ok(2));
+        assert_eq!(bus_numbers(&config, PciAddress::new(0, 0, 1, 0)), (0, 1, 1));
+        assert_eq!(bus_numbers(&config, PciAddress::new(0, 0, 2, 0)), (0, 2, 2));
+
+        config.add_function(PciAddress::new(0, 1, 0, 0), MockFunction::bridge(0, 0, 0));
+        config.add_function(PciAddress::new(0, 1, 1, 0), MockFunction::bridge(0, 0, 0));
+        config.add_function(PciAddress::new(0, 2, 0, 0), MockFunction::endpoint());
+        assert_eq!(assign_bus_numbers(0, 0, &config), Ok(4));
+        assert_eq!(bus_numbers(&config, PciAddress::new(0, 0, 1, 0)), (0, 1, 3));
+        assert_eq!(bus_numbers(&config, PciAddress::new(0, 1, 0, 0)), (1, 2, 2));
+        assert_eq!(bus_numbers(&config, PciAddress::new(0, 1, 1, 0)), (1, 3, 3));
+        assert_eq!(bus_numbers(&config, PciAddress::new(0, 0, 2, 0)), (0, 4, 4));
+    }
+
+    #[test]
+    fn assign_bus_numbers_out_of_buses() {
+        let mut config = MockConfigSpace::new();
+        config.add_function(PciAddress::new(0, 0xfd, 0, 0), MockFunction::bridge(0, 0, 0));
+        config.add_function(PciAddress::new(0, 0xfd, 1, 0), MockFunction::bridge(0, 0, 0));
+        config.add_function(PciAddress::new(0, 0xfe, 0, 0), MockFunction::bridge(0, 0, 0));
+        config.add_function(PciAddress::new(0, 0xff, 0, 0), MockFunction::bridge(0, 0, 0));
+
+        assert_eq!(assign_bus_numbers(0, 0xfd, &config), Err(BusNumberingError::OutOfBusNumbers));
+        assert_eq!(bus_numbers(&config, PciAddress::new(0, 0xfd, 0, 0)), (0xfd, 0xfe, 0xff));
+        assert_eq!(bus_numbers(&config, PciAddress::new(0, 0xfe, 0, 0)), (0xfe, 0xff, 0xff));
+        assert_eq!(bus_numbers(&config, PciAddress::new(0, 0xff, 0, 0)), (0, 0, 0));
+        assert_eq!(bus_numbers(&config, PciAddress::new(0, 0xfd, 1, 0)), (0, 0, 0));
+    }
+}
@@ -1,14 +1,39 @@
 #![no_std]
 
+#[cfg(feature = "alloc")]
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
+
+pub mod access;
+#[cfg(feature = "alloc")]
+mod allocation;
 pub mod capability;
 pub mod device_type;
+mod enumeration;
 mod register;
+pub mod rom;
+#[cfg(feature = "alloc")]
+mod topology;
+
+#[cfg(feature = "alloc")]
+pub use allocation::{
+    assign_resources,
+    AllocationError,
+    Apertures,
+    AssignedResource,
+    Assignment,
+    ResourceKind,
+};
+pub use enumeration::{assign_bus_numbers, BusNumberingError, EnumeratedFunction, PciEnumerator};
+#[cfg(feature = "alloc")]
+pub use topology::{AcsState, PciTopology, TopologyFunction};
 
-pub use register::{CommandRegister, DevselTiming, StatusRegister};
+pub use register::{BridgeControl, CardBusBridgeControl, CommandRegister, DevselTiming, StatusRegister};
 
-use crate::capability::CapabilityIterator;
+use crate::capability::{CapabilityIterator, ExtendedCapabilityIterator};
 use bit_field::BitField;
-use core::fmt;
+use core::{fmt, ops::RangeInclusive};
 
 /// The address of a PCIe function.
 ///
@@ -48,6 +73,19 @@ impl PciAddress {
     pub fn function(&self) -> u8 {
         self.0.get_bits(0..3) as u8
     }
+
+    /// Create an address from a Routing ID (also called a Requester ID), the 16-bit bus/device/function number
+    /// that identifies a function in transactions and error messages.
+    pub fn from_routing_id(segment: u16, routing_id: u16) -> PciAddress {
+        let mut result = routing_id as u32;
+        result.set_bits(16..32, segment as u32);
+        PciAddress(result)
+    }
+
+    /// Get the Routing ID (also called a Requester ID) of the function, which is its address without the segment.
+    pub fn routing_id(&self) -> u16 {
+        self.0.get_bits(0..16) as u16
+    }
 }
 
 impl fmt::Display for PciAddress {
@@ -73,10 +111,23 @@ pub type SubsystemVendorId = u16;
 pub type InterruptLine = u8;
 pub type InterruptPin = u8;
 
-// TODO: documentation
+/// Provides access to the configuration space of PCI functions. How this is done depends on the platform - the
+/// [`access`] module contains implementations for the common mechanisms.
 pub trait ConfigRegionAccess {
+    /// Returns `true` if there is a function at `address`.
     fn function_exists(&self, address: PciAddress) -> bool;
+
+    /// Read the 32-bit register at `offset` in the configuration space of the function at `address`.
+    ///
+    /// # Safety
+    /// `offset` must be 4-byte aligned. Reading some registers has side effects on the function.
     unsafe fn read(&self, address: PciAddress, offset: u16) -> u32;
+
+    /// Write `value` to the 32-bit register at `offset` in the configuration space of the function at `address`.
+    ///
+    /// # Safety
+    /// `offset` must be 4-byte aligned. Writing to the configuration space can change how the function behaves,
+    /// including the addresses it decodes.
     unsafe fn write(&self, address: PciAddress, offset: u16, value: u32);
 }
 
@@ -117,6 +168,10 @@ impl PciHeader {
         PciHeader(address)
     }
 
+    pub fn address(&self) -> PciAddress {
+        self.0
+    }
+
     pub fn id(&self, access: &impl ConfigRegionAccess) -> (VendorId, DeviceId) {
         let id = unsafe { access.read(self.0, 0x00) };
         (id.get_bits(0..16) as VendorId, id.get_bits(16..32) as DeviceId)
@@ -169,9 +224,14 @@ impl PciHeader {
     where
         F: Fn(CommandRegister) -> CommandRegister,
     {
+        /*
+         * The Status register shares a dword with the Command register, and most of its bits are RW1C, so it is
+         * written as zero to avoid clearing them.
+         */
         let mut data = unsafe { access.read(self.0, 0x4) };
         let new_command = f(CommandRegister::from_bits_truncate(data.get_bits(0..16) as u16));
         data.set_bits(0..16, new_command.bits() as u32);
+        data.set_bits(16..32, 0);
         unsafe {
             access.write(self.0, 0x4, data);
         }
@@ -267,129 +327,81 @@ impl EndpointHeader {
         CapabilityIterator::new(self.0, pointer, access)
     }
 
+    pub fn extended_capabilities<'a, T: ConfigRegionAccess>(
+        &self,
+        access: &'a T,
+    ) -> ExtendedCapabilityIterator<'a, T> {
+        ExtendedCapabilityIterator::new(self.0, access)
+    }
+
     pub fn subsystem(&self, access: &impl ConfigRegionAccess) -> (SubsystemId, SubsystemVendorId) {
         let data = unsafe { access.read(self.0, 0x2c) };
         (data.get_bits(16..32) as u16, data.get_bits(0..16) as u16)
     }
 
-    /// Get the contents of a BAR in a given slot. Empty bars will return `None`.
+    /// Get the contents of a BAR in a given slot. Empty BARs (which read as zero) will return `Ok(None)`, and
+    /// BARs that can't be decoded (including ones that report a type but have no writable address bits) will
+    /// return an error.
+    ///
+    /// The BAR is sized by temporarily writing all ones to it. So that the device doesn't respond to a bogus range
+    /// of addresses meanwhile, the relevant decode bit (`IO_ENABLE` or `MEMORY_ENABLE`) is cleared in the Command
+    /// register while the BAR is sized, and restored afterwards.
     ///
     /// ### Note
     /// 64-bit memory BARs use two slots, so if one is decoded in e.g. slot #0, this method should not be called
-    /// for slot #1
-    pub fn bar(&self, slot: u8, access: &impl ConfigRegionAccess) -> Option<Bar> {
-        if slot >= 6 {
-            return None;
-        }
-
-        let offset = 0x10 + (slot as u16) * 4;
-        let bar = unsafe { access.read(self.0, offset) };
-
-        /*
-         * If bit 0 is `0`, the BAR is in memory. If it's `1`, it's in I/O.
-         */
-        if bar.get_bit(0) == false {
-            let prefetchable = bar.get_bit(3);
-            let address = bar.get_bits(4..32) << 4;
-
-            match bar.get_bits(1..3) {
-                0b00 => {
-                    let size = unsafe {
-                        access.write(self.0, offset, 0xfffffff0);
-                        let mut readback = access.read(self.0, offset);
-                        access.write(self.0, offset, address);
-
-                        /*
-                         * If the entire readback value is zero, the BAR is not implemented, so we return `None`.
-                         */
-                        if readback == 0x0 {
-                            return None;
-                        }
-
-                        readback.set_bits(0..4, 0);
-                        1 << readback.trailing_zeros()
-                    };
-                    Some(Bar::Memory32 { address, size, prefetchable })
-                }
+    /// for slot #1. [`EndpointHeader::bars`] handles this automatically.
+    pub fn bar(&self, slot: u8, access: &impl ConfigRegionAccess) -> Result<Option<Bar>, BarError> {
+        read_bar(self.0, 0x10, MAX_BARS as u8, slot, BarDecode::Command, access)
+    }
 
-                0b10 => {
-                    /*
-                     * If the BAR is 64 bit-wide and this slot is the last, there is no second slot to read.
-                     */
-                    if slot >= 5 {
-                        return None;
-                    }
-
-                    let address_upper = unsafe { access.read(self.0, offset + 4) };
-
-                    let size = unsafe {
-                        access.write(self.0, offset, 0xfffffff0);
-                        access.write(self.0, offset + 4, 0xffffffff);
-                        let mut readback_low = access.read(self.0, offset);
-                        let readback_high = access.read(self.0, offset + 4);
-                        access.write(self.0, offset, address);
-                        access.write(self.0, offset + 4, address_upper);
-
-                        /*
-                         * If the readback from the first slot is not 0, the size of the BAR is less than 4GiB.
-                         */
-                        readback_low.set_bits(0..4, 0);
-                        if readback_low != 0 {
-                            (1 << readback_low.trailing_zeros()) as u64
-                        } else {
-                            1u64 << ((readback_high.trailing_zeros() + 32) as u64)
-                        }
-                    };
-
-                    let address = {
-                        let mut address = address as u64;
-                        // TODO: do we need to mask off the lower bits on this?
-                        address.set_bits(32..64, address_upper as u64);
-                        address
-                    };
-
-                    Some(Bar::Memory64 { address, size, prefetchable })
-                }
-                // TODO: should we bother to return an error here?
-                _ => panic!("BAR Memory type is reserved!"),
-            }
-        } else {
-            Some(Bar::Io { port: bar.get_bits(2..32) << 2 })
-        }
+    /// Iterate over the BARs of this function, yielding each implemented BAR, or the error it couldn't be decoded
+    /// with, along with its slot. Empty BARs and the upper halves of 64-bit BARs are skipped.
+    pub fn bars<'a, T: ConfigRegionAccess>(&self, access: &'a T) -> BarIterator<'a, T> {
+        BarIterator::new(self.0, 0x10, MAX_BARS as u8, BarDecode::Command, access)
     }
 
     /// Write to a BAR, setting the address for a device to use. The supplied value must be a valid
     /// BAR value (refer to the PCIe specification for requirements) and must be of the correct
     /// size (i.e. no larger than `u32::MAX` for 32-bit BARs). In the case of a 64-bit BAR, the
     /// supplied slot should be the first slot of the pair.
+    ///
+    /// # Safety
+    /// The caller must make sure that the new address does not overlap with the resources of any other device,
+    /// and that nothing is relying on the device decoding its old address.
     pub unsafe fn write_bar(
         &mut self,
         slot: u8,
         access: &impl ConfigRegionAccess,
         value: usize,
     ) -> Result<(), BarWriteError> {
-        match self.bar(slot, access) {
-            Some(Bar::Memory64 { .. }) => {
-                let offset = 0x10 + (slot as u16) * 4;
-                unsafe {
-                    access.write(self.0, offset, value.get_bits(0..32) as u32);
-                    access.write(self.0, offset + 4, value.get_bits(32..64) as u32);
-                }
-                Ok(())
-            }
-            Some(Bar::Memory32 { .. }) | Some(Bar::Io { .. }) => {
-                if value > u32::MAX as usize {
-                    return Err(BarWriteError::InvalidValue);
-                }
+        unsafe { write_bar(self.0, 0x10, MAX_BARS as u8, slot, value, access) }
+    }
 
-                let offset = 0x10 + (slot as u16) * 4;
-                unsafe {
-                    access.write(self.0, offset, value as u32);
-                }
-                Ok(())
-            }
-            None => return Err(BarWriteError::NoSuchBar),
-        }
+    /// Get the function's Expansion ROM BAR, or `None` if it doesn't implement one. The size of the ROM is found
+    /// by writing all ones to the BAR, during which the ROM is not decoded.
+    pub fn expansion_rom(&self, access: &impl ConfigRegionAccess) -> Option<ExpansionRom> {
+        read_expansion_rom(self.0, 0x30, access)
+    }
+
+    /// Map the function's Expansion ROM at `address` and enable decoding of it. `address` must be aligned to the
+    /// size of the ROM. The ROM is only accessible while the Command register's `MEMORY_ENABLE` bit is also
+    /// set.
+    ///
+    /// # Safety
+    /// The caller must make sure that the ROM does not overlap with the resources of any other device. Many
+    /// devices share an address decoder between the ROM and their other BARs, so the ROM should be disabled
+    /// again once it has been read.
+    pub unsafe fn enable_expansion_rom(
+        &self,
+        address: u32,
+        access: &impl ConfigRegionAccess,
+    ) -> Result<(), BarWriteError> {
+        unsafe { enable_expansion_rom(self.0, 0x30, address, access) }
+    }
+
+    /// Stop the function decoding accesses to its Expansion ROM. The ROM's address is left in place.
+    pub fn disable_expansion_rom(&self, access: &impl ConfigRegionAccess) {
+        disable_expansion_rom(self.0, 0x30, access);
     }
 
     pub fn interrupt(&self, access: &impl ConfigRegionAccess) -> (InterruptPin, InterruptLine) {
@@ -489,19 +501,1299 @@ impl PciPciBridgeHeader {
         let data = unsafe { access.read(self.0, 0x18).get_bits(16..24) };
         data as u8
     }
-}
 
-pub const MAX_BARS: usize = 6;
+    pub fn set_primary_bus_number(&self, bus: u8, access: &impl ConfigRegionAccess) {
+        self.update_bus_numbers(0..8, bus, access);
+    }
 
-#[derive(Clone, Copy, Debug)]
-pub enum Bar {
-    Memory32 { address: u32, size: u32, prefetchable: bool },
-    Memory64 { address: u64, size: u64, prefetchable: bool },
-    Io { port: u32 },
+    pub fn set_secondary_bus_number(&self, bus: u8, access: &impl ConfigRegionAccess) {
+        self.update_bus_numbers(8..16, bus, access);
+    }
+
+    pub fn set_subordinate_bus_number(&self, bus: u8, access: &impl ConfigRegionAccess) {
+        self.update_bus_numbers(16..24, bus, access);
+    }
+
+    /// Set all three bus numbers of the bridge at once. The bridge forwards configuration transactions for buses
+    /// `secondary..=subordinate` to its secondary bus.
+    pub fn set_bus_numbers(&self, primary: u8, secondary: u8, subordinate: u8, access: &impl ConfigRegionAccess) {
+        let mut data = unsafe { access.read(self.0, 0x18) };
+        data.set_bits(0..8, primary as u32);
+        data.set_bits(8..16, secondary as u32);
+        data.set_bits(16..24, subordinate as u32);
+        unsafe {
+            access.write(self.0, 0x18, data);
+        }
+    }
+
+    fn update_bus_numbers(&self, bits: core::ops::Range<usize>, bus: u8, access: &impl ConfigRegionAccess) {
+        let mut data = unsafe { access.read(self.0, 0x18) };
+        data.set_bits(bits, bus as u32);
+        unsafe {
+            access.write(self.0, 0x18, data);
+        }
+    }
+
+    pub fn capability_pointer(&self, access: &impl ConfigRegionAccess) -> u16 {
+        let status = self.status(access);
+        if status.has_capability_list() {
+            unsafe { access.read(self.0, 0x34).get_bits(0..8) as u16 }
+        } else {
+            0
+        }
+    }
+
+    pub fn capabilities<'a, T: ConfigRegionAccess>(&self, access: &'a T) -> CapabilityIterator<'a, T> {
+        let pointer = self.capability_pointer(access);
+        CapabilityIterator::new(self.0, pointer, access)
+    }
+
+    pub fn extended_capabilities<'a, T: ConfigRegionAccess>(
+        &self,
+        access: &'a T,
+    ) -> ExtendedCapabilityIterator<'a, T> {
+        ExtendedCapabilityIterator::new(self.0, access)
+    }
+
+    /// Get the contents of one of the bridge's two BARs. This behaves like [`EndpointHeader::bar`].
+    pub fn bar(&self, slot: u8, access: &impl ConfigRegionAccess) -> Result<Option<Bar>, BarError> {
+        read_bar(self.0, 0x10, MAX_BRIDGE_BARS as u8, slot, BarDecode::Command, access)
+    }
+
+    /// Iterate over the bridge's BARs. This behaves like [`EndpointHeader::bars`].
+    pub fn bars<'a, T: ConfigRegionAccess>(&self, access: &'a T) -> BarIterator<'a, T> {
+        BarIterator::new(self.0, 0x10, MAX_BRIDGE_BARS as u8, BarDecode::Command, access)
+    }
+
+    /// Write to one of the bridge's BARs. This behaves like [`EndpointHeader::write_bar`].
+    ///
+    /// # Safety
+    /// The caller must make sure that the new address does not overlap with the resources of any other device,
+    /// and that nothing is relying on the bridge decoding its old address.
+    pub unsafe fn write_bar(
+        &mut self,
+        slot: u8,
+        access: &impl ConfigRegionAccess,
+        value: usize,
+    ) -> Result<(), BarWriteError> {
+        unsafe { write_bar(self.0, 0x10, MAX_BRIDGE_BARS as u8, slot, value, access) }
+    }
+
+    /// Get the bridge's Expansion ROM BAR, or `None` if it doesn't implement one. The size of the ROM is found
+    /// by writing all ones to the BAR, during which the ROM is not decoded.
+    pub fn expansion_rom(&self, access: &impl ConfigRegionAccess) -> Option<ExpansionRom> {
+        read_expansion_rom(self.0, 0x38, access)
+    }
+
+    /// Map the bridge's Expansion ROM at `address` and enable decoding of it. `address` must be aligned to the
+    /// size of the ROM. The ROM is only accessible while the Command register's `MEMORY_ENABLE` bit is also
+    /// set.
+    ///
+    /// # Safety
+    /// The caller must make sure that the ROM does not overlap with the resources of any other device. Many
+    /// devices share an address decoder between the ROM and their other BARs, so the ROM should be disabled
+    /// again once it has been read.
+    pub unsafe fn enable_expansion_rom(
+        &self,
+        address: u32,
+        access: &impl ConfigRegionAccess,
+    ) -> Result<(), BarWriteError> {
+        unsafe { enable_expansion_rom(self.0, 0x38, address, access) }
+    }
+
+    /// Stop the bridge decoding accesses to its Expansion ROM. The ROM's address is left in place.
+    pub fn disable_expansion_rom(&self, access: &impl ConfigRegionAccess) {
+        disable_expansion_rom(self.0, 0x38, access);
+    }
+
+    /// Get the status of the secondary side of the bridge.
+    pub fn secondary_status(&self, access: &impl ConfigRegionAccess) -> StatusRegister {
+        let data = unsafe { access.read(self.0, 0x1c).get_bits(16..32) };
+        StatusRegister::new(data as u16)
+    }
+
+    pub fn bridge_control(&self, access: &impl ConfigRegionAccess) -> BridgeControl {
+        let data = unsafe { access.read(self.0, 0x3c).get_bits(16..32) };
+        BridgeControl::from_bits_truncate(data as u16)
+    }
+
+    /// Update the Bridge Control register. The Discard Timer Status bit is RW1C, so it is not passed to `f`, and
+    /// is only cleared if `f` sets it.
+    pub fn update_bridge_control<F>(&self, access: &impl ConfigRegionAccess, f: F)
+    where
+        F: Fn(BridgeControl) -> BridgeControl,
+    {
+        let mut data = unsafe { access.read(self.0, 0x3c) };
+        let old_control = BridgeControl::from_bits_truncate(data.get_bits(16..32) as u16);
+        let new_control = f(old_control - BridgeControl::DISCARD_TIMER_STATUS);
+        data.set_bits(16..32, new_control.bits() as u32);
+        unsafe {
+            access.write(self.0, 0x3c, data);
+        }
+    }
+
+    /// Does the bridge's I/O window decode 32-bit ports (rather than only 16-bit ones)?
+    pub fn io_window_is_32bit(&self, access: &impl ConfigRegionAccess) -> bool {
+        unsafe { access.read(self.0, 0x1c) }.get_bits(0..4) == 0x1
+    }
+
+    /// Get the range of I/O ports the bridge forwards to its secondary bus, or `None` if the window is disabled
+    /// (its limit is below its base). The window has a granularity of 4KiB.
+    ///
+    /// ### Note
+    /// Bridges that don't implement an I/O window hardwire its registers to zero, which reads as a window of
+    /// `0x0..=0xfff`.
+    pub fn io_window(&self, access: &impl ConfigRegionAccess) -> Option<RangeInclusive<u32>> {
+        let data = unsafe { access.read(self.0, 0x1c) };
+        let mut base = data.get_bits(4..8) << 12;
+        let mut limit = data.get_bits(12..16) << 12 | 0xfff;
+
+        if data.get_bits(0..4) == 0x1 {
+            let upper = unsafe { access.read(self.0, 0x30) };
+            base.set_bits(16..32, upper.get_bits(0..16));
+            limit.set_bits(16..32, upper.get_bits(16..32));
+        }
+
+        if limit < base {
+            None
+        } else {
+            Some(base..=limit)
+        }
+    }
+
+    /// Set the range of I/O ports the bridge forwards to its secondary bus, or disable the window with `None`.
+    /// The range must start and end on 4KiB boundaries, and must fit in 16 bits unless the window is
+    /// [32-bit](PciPciBridgeHeader::io_window_is_32bit).
+    pub fn set_io_window(
+        &self,
+        window: Option<RangeInclusive<u32>>,
+        access: &impl ConfigRegionAccess,
+    ) -> Result<(), BridgeWindowError> {
+        let (base, limit) = match window {
+            Some(window) => (*window.start(), *window.end()),
+            None => (0xf000, 0x0fff),
+        };
+        if base & 0xfff != 0 || limit & 0xfff != 0xfff {
+            return Err(BridgeWindowError::Misaligned);
+        }
+        if !self.io_window_is_32bit(access) && limit > 0xffff {
+            return Err(BridgeWindowError::OutOfRange);
+        }
+
+        /*
+         * Disable the window while it's being changed, by setting the upper half of the base above the upper
+         * half of the limit. The Secondary Status register shares a dword with the I/O base and limit, and is
+         * written as zero so none of its RW1C bits are cleared.
+         */
+        unsafe {
+            access.write(self.0, 0x30, 0x0000ffff);
+            let mut data = access.read(self.0, 0x1c);
+            data.set_bits(4..8, base.get_bits(12..16));
+            data.set_bits(12..16, limit.get_bits(12..16));
+            data.set_bits(16..32, 0);
+            access.write(self.0, 0x1c, data);
+            access.write(self.0, 0x30, limit.get_bits(16..32) << 16 | base.get_bits(16..32));
+        }
+        Ok(())
+    }
+
+    /// Get the range of memory addresses the bridge forwards to its secondary bus, or `None` if the window is
+    /// disabled (its limit is below its base). The window has a granularity of 1MiB.
+    pub fn memory_window(&self, access: &impl ConfigRegionAccess) -> Option<RangeInclusive<u32>> {
+        let data = unsafe { access.read(self.0, 0x20) };
+        let base = data.get_bits(4..16) << 20;
+        let limit = data.get_bits(20..32) << 20 | 0xfffff;
+
+        if limit < base {
+            None
+        } else {
+            Some(base..=limit)
+        }
+    }
+
+    /// Set the range of memory addresses the bridge forwards to its secondary bus, or disable the window with
+    /// `None`. The range must start and end on 1MiB boundaries.
+    pub fn set_memory_window(
+        &self,
+        window: Option<RangeInclusive<u32>>,
+        access: &impl ConfigRegionAccess,
+    ) -> Result<(), BridgeWindowError> {
+        let (base, limit) = match window {
+            Some(window) => (*window.start(), *window.end()),
+            None => (0xfff00000, 0x000fffff),
+        };
+        if base & 0xfffff != 0 || limit & 0xfffff != 0xfffff {
+            return Err(BridgeWindowError::Misaligned);
+        }
+
+        let mut data = 0;
+        data.set_bits(4..16, base.get_bits(20..32));
+        data.set_bits(20..32, limit.get_bits(20..32));
+        unsafe {
+            access.write(self.0, 0x20, data);
+        }
+        Ok(())
+    }
+
+    /// Does the bridge's prefetchable memory window decode 64-bit addresses (rather than only 32-bit ones)?
+    pub fn prefetchable_memory_window_is_64bit(&self, access: &impl ConfigRegionAccess) -> bool {
+        unsafe { access.read(self.0, 0x24) }.get_bits(0..4) == 0x1
+    }
+
+    /// Get the range of prefetchable memory addresses the bridge forwards to its secondary bus, or `None` if the
+    /// window is disabled (its limit is below its base). The window has a granularity of 1MiB.
+    ///
+    /// ### Note
+    /// Bridges that don't implement a prefetchable memory window hardwire its registers to zero, which reads as a
+    /// window of `0x0..=0xfffff`.
+    pub fn prefetchable_memory_window(&self, access: &impl ConfigRegionAccess) -> Option<RangeInclusive<u64>> {
+        let data = unsafe { access.read(self.0, 0x24) };
+        let mut base = (data.get_bits(4..16) as u64) << 20;
+        let mut limit = (data.get_bits(20..32) as u64) << 20 | 0xfffff;
+
+        if data.get_bits(0..4) == 0x1 {
+            base.set_bits(32..64, unsafe { access.read(self.0, 0x28) } as u64);
+            limit.set_bits(32..64, unsafe { access.read(self.0, 0x2c) } as u64);
+        }
+
+        if limit < base {
+            None
+        } else {
+            Some(base..=limit)
+        }
+    }
+
+    /// Set the range of prefetchable memory addresses the bridge forwards to its secondary bus, or disable the
+    /// window with `None`. The range must start and end on 1MiB boundaries, and must fit in 32 bits unless the
+    /// window is [64-bit](PciPciBridgeHeader::prefetchable_memory_window_is_64bit).
+    pub fn set_prefetchable_memory_window(
+        &self,
+        window: Option<RangeInclusive<u64>>,
+        access: &impl ConfigRegionAccess,
+    ) -> Result<(), BridgeWindowError> {
+        let (base, limit) = match window {
+            Some(window) => (*window.start(), *window.end()),
+            None => (0xfff00000, 0x000fffff),
+        };
+        if base & 0xfffff != 0 || limit & 0xfffff != 0xfffff {
+            return Err(BridgeWindowError::Misaligned);
+        }
+        let is_64bit = self.prefetchable_memory_window_is_64bit(access);
+        if !is_64bit && limit > u32::MAX as u64 {
+            return Err(BridgeWindowError::OutOfRange);
+        }
+
+        let mut data = 0;
+        data.set_bits(4..16, base.get_bits(20..32) as u32);
+        data.set_bits(20..32, limit.get_bits(20..32) as u32);
+
+        /*
+         * Clearing the upper half of the limit first disables the window while it's being changed (if the upper
+         * half of the base is non-zero).
+         */
+        unsafe {
+            if is_64bit {
+                access.write(self.0, 0x2c, 0);
+            }
+            access.write(self.0, 0x24, data);
+            if is_64bit {
+                access.write(self.0, 0x28, base.get_bits(32..64) as u32);
+                access.write(self.0, 0x2c, limit.get_bits(32..64) as u32);
+            }
+        }
+        Ok(())
+    }
 }
 
+/// CardBus Bridges have a Type-2 header, so the remainder of the header is of the form:
+/// ```ignore
+///     32                           16                              0
+///     +-----------------------------------------------------------+ 0x00
+///     |                                                           |
+///     |                Predefined region of header                |
+///     |                                                           |
+///     |                                                           |
+///     +-----------------------------------------------------------+
+///     |             CardBus Socket/ExCA Base Address              | 0x10
+///     |                                                           |
+///     +-----------------------------+--------------+--------------+
+///     |      Secondary Status       |   Reserved   | Capabilities | 0x14
+///     |                             |              |   Pointer    |
+///     +--------------+--------------+--------------+--------------+
+///     |   CardBus    | Subordinate  |   CardBus    |   PCI Bus    | 0x18
+///     |Latency Timer | Bus Number   |  Bus Number  |   Number     |
+///     +--------------+--------------+--------------+--------------+
+///     |                  Memory Base Address 0                    | 0x1C
+///     |                                                           |
+///     +-----------------------------------------------------------+
+///     |                     Memory Limit 0                        | 0x20
+///     |                                                           |
+///     +-----------------------------------------------------------+
+///     |                  Memory Base Address 1                    | 0x24
+///     |                                                           |
+///     +-----------------------------------------------------------+
+///     |                     Memory Limit 1                        | 0x28
+///     |                                                           |
+///     +-----------------------------------------------------------+
+///     |                   I/O Base Address 0                      | 0x2C
+///     |                                                           |
+///     +-----------------------------------------------------------+
+///     |                      I/O Limit 0                          | 0x30
+///     |                                                           |
+///     +-----------------------------------------------------------+
+///     |                   I/O Base Address 1                      | 0x34
+///     |                                                           |
+///     +-----------------------------------------------------------+
+///     |                      I/O Limit 1                          | 0x38
+///     |                                                           |
+///     +-----------------------------+--------------+--------------+
+///     |       Bridge Control        |  Interrupt   |  Interrupt   | 0x3C
+///     |                             |     PIN      |    Line      |
+///     +-----------------------------+--------------+--------------+
+///     |    Subsystem Device ID      |     Subsystem Vendor ID     | 0x40
+///     |                             |                             |
+///     +-----------------------------+-----------------------------+
+///     |          16-bit PC Card Legacy Mode Base Address          | 0x44
+///     |                                                           |
+///     +-----------------------------------------------------------+
+/// ```
+pub struct CardBusBridgeHeader(PciAddress);
+
+/// One of the two memory windows, or one of the two I/O windows, of a [`CardBusBridgeHeader`].
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
-pub enum BarWriteError {
-    NoSuchBar,
-    InvalidValue,
+pub enum CardBusWindow {
+    Window0,
+    Window1,
+}
+
+impl CardBusBridgeHeader {
+    pub fn from_header(header: PciHeader, access: &impl ConfigRegionAccess) -> Option<CardBusBridgeHeader> {
+        match header.header_type(access) {
+            HeaderType::CardBusBridge => Some(CardBusBridgeHeader(header.0)),
+            _ => None,
+        }
+    }
+
+    pub fn header(&self) -> PciHeader {
+        PciHeader(self.0)
+    }
+
+    pub fn status(&self, access: &impl ConfigRegionAccess) -> StatusRegister {
+        self.header().status(access)
+    }
+
+    pub fn command(&self, access: &impl ConfigRegionAccess) -> CommandRegister {
+        self.header().command(access)
+    }
+
+    pub fn update_command<F>(&self, access: &impl ConfigRegionAccess, f: F)
+    where
+        F: Fn(CommandRegister) -> CommandRegister,
+    {
+        self.header().update_command(access, f);
+    }
+
+    /// Get the address of the memory-mapped CardBus socket registers and ExCA registers.
+    pub fn socket_base_address(&self, access: &impl ConfigRegionAccess) -> u32 {
+        unsafe { access.read(self.0, 0x10) & !0xfff }
+    }
+
+    /// Get the status of the CardBus side of the bridge.
+    pub fn secondary_status(&self, access: &impl ConfigRegionAccess) -> StatusRegister {
+        let data = unsafe { access.read(self.0, 0x14).get_bits(16..32) };
+        StatusRegister::new(data as u16)
+    }
+
+    pub fn capability_pointer(&self, access: &impl ConfigRegionAccess) -> u16 {
+        let status = self.status(access);
+        if status.has_capability_list() {
+            unsafe { access.read(self.0, 0x14).get_bits(0..8) as u16 }
+        } else {
+            0
+        }
+    }
+
+    pub fn capabilities<'a, T: ConfigRegionAccess>(&self, access: &'a T) -> CapabilityIterator<'a, T> {
+        let pointer = self.capability_pointer(access);
+        CapabilityIterator::new(self.0, pointer, access)
+    }
+
+    /// Get the number of the PCI bus the bridge is on.
+    pub fn pci_bus_number(&self, access: &impl ConfigRegionAccess) -> u8 {
+        let data = unsafe { access.read(self.0, 0x18).get_bits(0..8) };
+        data as u8
+    }
+
+    /// Get the number of the CardBus bus behind the bridge.
+    pub fn cardbus_bus_number(&self, access: &impl ConfigRegionAccess) -> u8 {
+        let data = unsafe { access.read(self.0, 0x18).get_bits(8..16) };
+        data as u8
+    }
+
+    pub fn subordinate_bus_number(&self, access: &impl ConfigRegionAccess) -> u8 {
+        let data = unsafe { access.read(self.0, 0x18).get_bits(16..24) };
+        data as u8
+    }
+
+    pub fn cardbus_latency_timer(&self, access: &impl ConfigRegionAccess) -> u8 {
+        let data = unsafe { access.read(self.0, 0x18).get_bits(24..32) };
+        data as u8
+    }
+
+    /// Get the range of addresses forwarded by one of the bridge's two memory windows, or `None` if the window is
+    /// disabled (its limit is below its base). The windows have a granularity of 4KiB.
+    pub fn memory_window(
+        &self,
+        window: CardBusWindow,
+        access: &impl ConfigRegionAccess,
+    ) -> Option<RangeInclusive<u32>> {
+        let offset = match window {
+            CardBusWindow::Window0 => 0x1c,
+            CardBusWindow::Window1 => 0x24,
+        };
+        let base = unsafe { access.read(self.0, offset) } & !0xfff;
+        let limit = unsafe { access.read(self.0, offset + 4) } | 0xfff;
+        if limit < base {
+            None
+        } else {
+            Some(base..=limit)
+        }
+    }
+
+    /// Get the range of ports forwarded by one of the bridge's two I/O windows, or `None` if the window is
+    /// disabled (its limit is below its base). Windows that only decode 16-bit ports are returned with the upper
+    /// 16 bits cleared.
+    pub fn io_window(
+        &self,
+        window: CardBusWindow,
+        access: &impl ConfigRegionAccess,
+    ) -> Option<RangeInclusive<u32>> {
+        let offset = match window {
+            CardBusWindow::Window0 => 0x2c,
+            CardBusWindow::Window1 => 0x34,
+        };
+        let base_register = unsafe { access.read(self.0, offset) };
+        let mut base = base_register & !0x3;
+        let mut limit = unsafe { access.read(self.0, offset + 4) } | 0x3;
+        if !base_register.get_bit(0) {
+            base &= 0xffff;
+            limit &= 0xffff;
+        }
+
+        if limit < base {
+            None
+        } else {
+            Some(base..=limit)
+        }
+    }
+
+    pub fn bridge_control(&self, access: &impl ConfigRegionAccess) -> CardBusBridgeControl {
+        let data = unsafe { access.read(self.0, 0x3c).get_bits(16..32) };
+        CardBusBridgeControl::from_bits_truncate(data as u16)
+    }
+
+    pub fn update_bridge_control<F>(&self, access: &impl ConfigRegionAccess, f: F)
+    where
+        F: Fn(CardBusBridgeControl) -> CardBusBridgeControl,
+    {
+        let mut data = unsafe { access.read(self.0, 0x3c) };
+        let new_control = f(CardBusBridgeControl::from_bits_truncate(data.get_bits(16..32) as u16));
+        data.set_bits(16..32, new_control.bits() as u32);
+        unsafe {
+            access.write(self.0, 0x3c, data);
+        }
+    }
+
+    pub fn interrupt(&self, access: &impl ConfigRegionAccess) -> (InterruptPin, InterruptLine) {
+        let data = unsafe { access.read(self.0, 0x3c) };
+        (data.get_bits(8..16) as u8, data.get_bits(0..8) as u8)
+    }
+
+    pub fn subsystem(&self, access: &impl ConfigRegionAccess) -> (SubsystemId, SubsystemVendorId) {
+        let data = unsafe { access.read(self.0, 0x40) };
+        (data.get_bits(16..32) as u16, data.get_bits(0..16) as u16)
+    }
+
+    /// Get the base address of the 16-bit PC Card legacy mode registers.
+    pub fn legacy_mode_base_address(&self, access: &impl ConfigRegionAccess) -> u32 {
+        unsafe { access.read(self.0, 0x44) }
+    }
+}
+
+/// A function of any header type. This dispatches to the header-specific types for operations that are common
+/// to all of them but live at different offsets, so callers that don't care about the header type don't have to
+/// match on [`PciHeader::header_type`] themselves.
+pub enum PciFunction {
+    Endpoint(EndpointHeader),
+    PciPciBridge(PciPciBridgeHeader),
+    CardBusBridge(CardBusBridgeHeader),
+    Unknown(PciHeader),
+}
+
+impl PciFunction {
+    pub fn new(address: PciAddress, access: &impl ConfigRegionAccess) -> PciFunction {
+        PciFunction::from_header(PciHeader::new(address), access)
+    }
+
+    pub fn from_header(header: PciHeader, access: &impl ConfigRegionAccess) -> PciFunction {
+        match header.header_type(access) {
+            HeaderType::Endpoint => PciFunction::Endpoint(EndpointHeader(header.0)),
+            HeaderType::PciPciBridge => PciFunction::PciPciBridge(PciPciBridgeHeader(header.0)),
+            HeaderType::CardBusBridge => PciFunction::CardBusBridge(CardBusBridgeHeader(header.0)),
+            HeaderType::Unknown(_) => PciFunction::Unknown(header),
+        }
+    }
+
+    pub fn address(&self) -> PciAddress {
+        self.header().0
+    }
+
+    pub fn header(&self) -> PciHeader {
+        match self {
+            PciFunction::Endpoint(endpoint) => endpoint.header(),
+            PciFunction::PciPciBridge(bridge) => bridge.header(),
+            PciFunction::CardBusBridge(bridge) => bridge.header(),
+            PciFunction::Unknown(header) => PciHeader(header.0),
+        }
+    }
+
+    pub fn id(&self, access: &impl ConfigRegionAccess) -> (VendorId, DeviceId) {
+        self.header().id(access)
+    }
+
+    pub fn revision_and_class(
+        &self,
+        access: &impl ConfigRegionAccess,
+    ) -> (DeviceRevision, BaseClass, SubClass, Interface) {
+        self.header().revision_and_class(access)
+    }
+
+    pub fn has_multiple_functions(&self, access: &impl ConfigRegionAccess) -> bool {
+        self.header().has_multiple_functions(access)
+    }
+
+    pub fn status(&self, access: &impl ConfigRegionAccess) -> StatusRegister {
+        self.header().status(access)
+    }
+
+    pub fn command(&self, access: &impl ConfigRegionAccess) -> CommandRegister {
+        self.header().command(access)
+    }
+
+    pub fn update_command<F>(&self, access: &impl ConfigRegionAccess, f: F)
+    where
+        F: Fn(CommandRegister) -> CommandRegister,
+    {
+        self.header().update_command(access, f);
+    }
+
+    /// Get the offset of the first capability, or `0` if the function doesn't have any. Functions with an unknown
+    /// header type are treated as not having any.
+    pub fn capability_pointer(&self, access: &impl ConfigRegionAccess) -> u16 {
+        match self {
+            PciFunction::Endpoint(endpoint) => endpoint.capability_pointer(access),
+            PciFunction::PciPciBridge(bridge) => bridge.capability_pointer(access),
+            PciFunction::CardBusBridge(bridge) => bridge.capability_pointer(access),
+            PciFunction::Unknown(_) => 0,
+        }
+    }
+
+    pub fn capabilities<'a, T: ConfigRegionAccess>(&self, access: &'a T) -> CapabilityIterator<'a, T> {
+        let pointer = self.capability_pointer(access);
+        CapabilityIterator::new(self.address(), pointer, access)
+    }
+
+    pub fn extended_capabilities<'a, T: ConfigRegionAccess>(
+        &self,
+        access: &'a T,
+    ) -> ExtendedCapabilityIterator<'a, T> {
+        ExtendedCapabilityIterator::new(self.address(), access)
+    }
+
+    /// Get the interrupt pin and line of the function. The Interrupt Pin and Interrupt Line registers are at the
+    /// same offset in all of the known header types; functions with an unknown header type return `(0, 0)`.
+    pub fn interrupt(&self, access: &impl ConfigRegionAccess) -> (InterruptPin, InterruptLine) {
+        if let PciFunction::Unknown(_) = self {
+            return (0, 0);
+        }
+
+        let data = unsafe { access.read(self.address(), 0x3c) };
+        (data.get_bits(8..16) as u8, data.get_bits(0..8) as u8)
+    }
+}
+
+/// How decoding of a block of BARs is turned on and off.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum BarDecode {
+    /// The BARs of a header, which are decoded while the Command register's `IO_ENABLE` or `MEMORY_ENABLE` bit is
+    /// set.
+    Command,
+    /// The VF BARs of an SR-IOV capability, which are decoded while the VF Memory Space Enable bit is set in the
+    /// SR-IOV Control register at `offset`.
+    SriovControl { offset: u16 },
+}
+
+impl BarDecode {
+    /// Get the offset of the register, and the bit in it, that enables decoding of I/O or memory BARs. Both
+    /// registers share a dword with an RW1C status register, in the upper half.
+    fn enable_bit(self, io: bool) -> Option<(u16, usize)> {
+        match self {
+            BarDecode::Command => Some((0x04, if io { 0 } else { 1 })),
+            /*
+             * VFs don't support I/O BARs.
+             */
+            BarDecode::SriovControl { .. } if io => None,
+            BarDecode::SriovControl { offset } => Some((offset, 3)),
+        }
+    }
+
+    /// Call `f` with decoding of I/O or memory BARs disabled, and restore it afterwards.
+    fn disabled_while<R>(
+        self,
+        function: PciAddress,
+        io: bool,
+        access: &impl ConfigRegionAccess,
+        f: impl FnOnce() -> R,
+    ) -> R {
+        let enabled =
+            self.enable_bit(io).filter(|&(offset, bit)| unsafe { access.read(function, offset) }.get_bit(bit));
+
+        /*
+         * The upper half of the register is written as zero, so none of its RW1C bits are cleared.
+         */
+        if let Some((offset, bit)) = enabled {
+            let mut data = unsafe { access.read(function, offset) };
+            data.set_bit(bit, false);
+            data.set_bits(16..32, 0);
+            unsafe { access.write(function, offset, data) };
+        }
+        let result = f();
+        if let Some((offset, bit)) = enabled {
+            let mut data = unsafe { access.read(function, offset) };
+            data.set_bit(bit, true);
+            data.set_bits(16..32, 0);
+            unsafe { access.write(function, offset, data) };
+        }
+
+        result
+    }
+}
+
+/// Decode the BAR in `slot` of a block of `num_bars` BARs, starting at offset `base` of `function`'s
+/// configuration space. This is shared between the header types, which only differ in how many BARs they have,
+/// and the SR-IOV capability's VF BARs. Decoding of the BAR is disabled, as described by `decode`, while it is
+/// sized.
+pub(crate) fn read_bar(
+    function: PciAddress,
+    base: u16,
+    num_bars: u8,
+    slot: u8,
+    decode: BarDecode,
+    access: &impl ConfigRegionAccess,
+) -> Result<Option<Bar>, BarError> {
+    if slot >= num_bars {
+        return Err(BarError::NoSuchSlot);
+    }
+
+    let offset = base + (slot as u16) * 4;
+    let bar = unsafe { access.read(function, offset) };
+
+    /*
+     * If bit 0 is `0`, the BAR is in memory. If it's `1`, it's in I/O.
+     */
+    if !bar.get_bit(0) {
+        let prefetchable = bar.get_bit(3);
+        let address = bar.get_bits(4..32) << 4;
+
+        match bar.get_bits(1..3) {
+            0b00 => {
+                let mut readback = decode.disabled_while(function, false, access, || unsafe {
+                    access.write(function, offset, 0xfffffff0);
+                    let readback = access.read(function, offset);
+                    access.write(function, offset, address);
+                    readback
+                });
+
+                /*
+                 * If the entire readback value is zero, the BAR is empty, so we return `None`.
+                 */
+                if readback == 0x0 {
+                    return Ok(None);
+                }
+
+                /*
+                 * The size is the lowest address bit that can be set. If none can, the BAR is unimplemented.
+                 */
+                readback.set_bits(0..4, 0);
+                let size = 1u32.checked_shl(readback.trailing_zeros()).ok_or(BarError::Unimplemented)?;
+                Ok(Some(Bar::Memory32 { address, size, prefetchable }))
+            }
+
+            0b10 => {
+                /*
+                 * If the BAR is 64 bit-wide and this slot is the last, there is no second slot to read.
+                 */
+                if slot + 1 >= num_bars {
+                    return Err(BarError::Truncated64Bit);
+                }
+
+                let address_upper = unsafe { access.read(function, offset + 4) };
+
+                let (mut readback_low, readback_high) =
+                    decode.disabled_while(function, false, access, || unsafe {
+                        access.write(function, offset, 0xfffffff0);
+                        access.write(function, offset + 4, 0xffffffff);
+                        let readback_low = access.read(function, offset);
+                        let readback_high = access.read(function, offset + 4);
+                        access.write(function, offset, address);
+                        access.write(function, offset + 4, address_upper);
+                        (readback_low, readback_high)
+                    });
+
+                readback_low.set_bits(0..4, 0);
+                let readback = (readback_high as u64) << 32 | readback_low as u64;
+                let size = 1u64.checked_shl(readback.trailing_zeros()).ok_or(BarError::Unimplemented)?;
+
+                let address = {
+                    let mut address = address as u64;
+                    // TODO: do we need to mask off the lower bits on this?
+                    address.set_bits(32..64, address_upper as u64);
+                    address
+                };
+
+                Ok(Some(Bar::Memory64 { address, size, prefetchable }))
+            }
+            _ => Err(BarError::ReservedType),
+        }
+    } else {
+        let port = bar.get_bits(2..32) << 2;
+        let mut readback = decode.disabled_while(function, true, access, || unsafe {
+            access.write(function, offset, 0xfffffffc);
+            let readback = access.read(function, offset);
+            access.write(function, offset, bar);
+            readback
+        });
+
+        /*
+         * Bits 0 and 1 are not part of the address. If no other bits can be set, the BAR is unimplemented.
+         */
+        readback.set_bits(0..2, 0);
+        let size = 1u32.checked_shl(readback.trailing_zeros()).ok_or(BarError::Unimplemented)?;
+        Ok(Some(Bar::Io { port, size }))
+    }
+}
+
+/// Write `value` to the BAR in `slot` of a block of `num_bars` BARs, starting at offset `base` of `function`'s
+/// configuration space. The BAR is read first, to check that it exists and to find its width.
+unsafe fn write_bar(
+    function: PciAddress,
+    base: u16,
+    num_bars: u8,
+    slot: u8,
+    value: usize,
+    access: &impl ConfigRegionAccess,
+) -> Result<(), BarWriteError> {
+    let offset = base + (slot as u16) * 4;
+    match read_bar(function, base, num_bars, slot, BarDecode::Command, access) {
+        Ok(Some(Bar::Memory64 { .. })) => {
+            unsafe {
+                access.write(function, offset, value.get_bits(0..32) as u32);
+                access.write(function, offset + 4, value.get_bits(32..64) as u32);
+            }
+            Ok(())
+        }
+        Ok(Some(Bar::Memory32 { .. })) | Ok(Some(Bar::Io { .. })) => {
+            if value > u32::MAX as usize {
+                return Err(BarWriteError::InvalidValue);
+            }
+
+            unsafe {
+                access.write(function, offset, value as u32);
+            }
+            Ok(())
+        }
+        Ok(None) | Err(_) => Err(BarWriteError::NoSuchBar),
+    }
+}
+
+/// Read and size the Expansion ROM BAR at `offset` in `function`'s configuration space.
+fn read_expansion_rom(
+    function: PciAddress,
+    offset: u16,
+    access: &impl ConfigRegionAccess,
+) -> Option<ExpansionRom> {
+    let rom = unsafe { access.read(function, offset) };
+
+    let size = unsafe {
+        /*
+         * Bit 0 is the enable bit, so writing the mask with it clear also stops the ROM being decoded while it's
+         * sized. Bits 1..11 are reserved.
+         */
+        access.write(function, offset, 0xfffff800);
+        let mut readback = access.read(function, offset);
+        access.write(function, offset, rom);
+
+        readback.set_bits(0..11, 0);
+        if readback == 0x0 {
+            return None;
+        }
+        1 << readback.trailing_zeros()
+    };
+
+    Some(ExpansionRom { address: rom.get_bits(11..32) << 11, size, enabled: rom.get_bit(0) })
+}
+
+unsafe fn enable_expansion_rom(
+    function: PciAddress,
+    offset: u16,
+    address: u32,
+    access: &impl ConfigRegionAccess,
+) -> Result<(), BarWriteError> {
+    let rom = read_expansion_rom(function, offset, access).ok_or(BarWriteError::NoSuchBar)?;
+    if address & (rom.size - 1) != 0 {
+        return Err(BarWriteError::InvalidValue);
+    }
+
+    unsafe {
+        access.write(function, offset, address | 0x1);
+    }
+    Ok(())
+}
+
+fn disable_expansion_rom(function: PciAddress, offset: u16, access: &impl ConfigRegionAccess) {
+    let mut rom = unsafe { access.read(function, offset) };
+    rom.set_bit(0, false);
+    unsafe {
+        access.write(function, offset, rom);
+    }
+}
+
+pub const MAX_BARS: usize = 6;
+/// The number of BARs in a PCI-to-PCI bridge's (Type 1) header.
+pub const MAX_BRIDGE_BARS: usize = 2;
+
+#[derive(Clone, Copy, Debug)]
+pub enum Bar {
+    Memory32 { address: u32, size: u32, prefetchable: bool },
+    Memory64 { address: u64, size: u64, prefetchable: bool },
+    Io { port: u32, size: u32 },
+}
+
+impl Bar {
+    /// Get the address the BAR is mapped at. For I/O BARs, this is the port number.
+    pub fn address(&self) -> u64 {
+        match *self {
+            Bar::Memory32 { address, .. } => address as u64,
+            Bar::Memory64 { address, .. } => address,
+            Bar::Io { port, .. } => port as u64,
+        }
+    }
+
+    /// Get the size of the region decoded by the BAR, in bytes (or ports).
+    pub fn size(&self) -> u64 {
+        match *self {
+            Bar::Memory32 { size, .. } => size as u64,
+            Bar::Memory64 { size, .. } => size,
+            Bar::Io { size, .. } => size as u64,
+        }
+    }
+
+    /// Get the range of addresses (or ports) decoded by the BAR. This is inclusive, so that a BAR that ends at
+    /// the very top of the 64-bit address space can be represented.
+    pub fn range(&self) -> RangeInclusive<u64> {
+        self.address()..=(self.address() + (self.size() - 1))
+    }
+
+    /// Is the BAR's memory prefetchable? I/O BARs are never prefetchable.
+    pub fn is_prefetchable(&self) -> bool {
+        match *self {
+            Bar::Memory32 { prefetchable, .. } | Bar::Memory64 { prefetchable, .. } => prefetchable,
+            Bar::Io { .. } => false,
+        }
+    }
+}
+
+/// Iterates over the BARs of a function, yielding each implemented BAR (or the error it couldn't be decoded
+/// with) along with its slot. Created by [`EndpointHeader::bars`] or [`PciPciBridgeHeader::bars`].
+pub struct BarIterator<'a, T: ConfigRegionAccess> {
+    address: PciAddress,
+    base: u16,
+    num_bars: u8,
+    decode: BarDecode,
+    slot: u8,
+    access: &'a T,
+}
+
+impl<'a, T: ConfigRegionAccess> BarIterator<'a, T> {
+    pub(crate) fn new(
+        address: PciAddress,
+        base: u16,
+        num_bars: u8,
+        decode: BarDecode,
+        access: &'a T,
+    ) -> BarIterator<'a, T> {
+        BarIterator { address, base, num_bars, decode, slot: 0, access }
+    }
+}
+
+impl<'a, T: ConfigRegionAccess> Iterator for BarIterator<'a, T> {
+    type Item = (u8, Result<Bar, BarError>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.slot < self.num_bars {
+            let slot = self.slot;
+            let bar = read_bar(self.address, self.base, self.num_bars, slot, self.decode, self.access);
+
+            /*
+             * A 64-bit BAR takes up the next slot too, so skip over its upper half.
+             */
+            self.slot += if let Ok(Some(Bar::Memory64 { .. })) = bar { 2 } else { 1 };
+            match bar {
+                Ok(Some(bar)) => return Some((slot, Ok(bar))),
+                Ok(None) => (),
+                Err(err) => return Some((slot, Err(err))),
+            }
+        }
+        None
+    }
+}
+
+/// A function's Expansion ROM BAR, which maps its option ROM into memory space. The contents of the ROM can be
+/// parsed with the [`rom`](crate::rom) module.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ExpansionRom {
+    pub address: u32,
+    pub size: u32,
+    /// Whether decoding of the ROM is enabled. The Command register's `MEMORY_ENABLE` bit must also be set for
+    /// the function to respond to accesses to it.
+    pub enabled: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BarError {
+    /// The header type doesn't have a BAR in this slot.
+    NoSuchSlot,
+    /// The BAR reports a type, but none of its address bits can be written, so it has no size and can't be used.
+    /// This is different to an empty BAR, which reads as zero and is returned as `Ok(None)`.
+    Unimplemented,
+    /// The BAR is a memory BAR with the reserved memory type (`0b01` or `0b11`).
+    ReservedType,
+    /// The BAR is a 64-bit memory BAR in the last slot, so there is no slot for its upper half.
+    Truncated64Bit,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BarWriteError {
+    NoSuchBar,
+    InvalidValue,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BridgeWindowError {
+    /// The window doesn't start and end on the window's granularity.
+    Misaligned,
+    /// The window doesn't fit in the addresses the bridge can decode.
+    OutOfRange,
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod tests {
+    use super::*;
+    use crate::access::{MockConfigSpace, MockFunction};
+    use alloc::vec::Vec;
+    use core::cell::RefCell;
+
+    const ADDRESS: PciAddress = PciAddress(0x0000_0100);
+
+    /// Wraps a [`MockConfigSpace`], recording the Command register's value whenever all ones are written to a BAR.
+    struct SizingRecorder {
+        config: MockConfigSpace,
+        commands: RefCell<Vec<(u16, u32)>>,
+    }
+
+    impl ConfigRegionAccess for SizingRecorder {
+        fn function_exists(&self, address: PciAddress) -> bool {
+            self.config.function_exists(address)
+        }
+
+        unsafe fn read(&self, address: PciAddress, offset: u16) -> u32 {
+            unsafe { self.config.read(address, offset) }
+        }
+
+        unsafe fn write(&self, address: PciAddress, offset: u16, value: u32) {
+            if (0x10..0x28).contains(&offset) && value | 0xf == 0xffffffff {
+                let command = unsafe { self.config.read(address, 0x04) } & 0xffff;
+                self.commands.borrow_mut().push((offset, command));
+            }
+            unsafe { self.config.write(address, offset, value) }
+        }
+    }
+
+    fn mock_endpoint(f: impl FnOnce(&mut MockFunction)) -> MockConfigSpace {
+        let mut function = MockFunction::new(0x8086, 0x1234, HeaderType::Endpoint);
+        f(&mut function);
+        let mut config = MockConfigSpace::new();
+        config.add_function(ADDRESS, function);
+        config
+    }
+
+    #[test]
+    fn bar_sizing_disables_decode() {
+        let config = mock_endpoint(|function| {
+            // I/O, memory and bus master enabled, with errors pending in the Status register
+            function.set(0x04, 0xf900_0007);
+            function.set_memory_bar(0, 0xfebf_0000, 0x1000, false, false);
+            function.set_io_bar(1, 0xe000, 0x40);
+        });
+        let access = SizingRecorder { config, commands: RefCell::new(Vec::new()) };
+        let endpoint = EndpointHeader(ADDRESS);
+
+        let memory = endpoint.bar(0, &access).unwrap().unwrap();
+        assert_eq!((memory.address(), memory.size()), (0xfebf_0000, 0x1000));
+        let io = endpoint.bar(1, &access).unwrap().unwrap();
+        assert_eq!((io.address(), io.size()), (0xe000, 0x40));
+
+        assert_eq!(*access.commands.borrow(), [(0x10, 0x0005), (0x14, 0x0006)]);
+        assert_eq!(unsafe { access.read(ADDRESS, 0x04) }, 0xf900_0007);
+        assert_eq!(unsafe { access.read(ADDRESS, 0x10) }, 0xfebf_0000);
+        assert_eq!(unsafe { access.read(ADDRESS, 0x14) }, 0x0000_e001);
+    }
+
+    #[test]
+    fn bar_sizing_leaves_decode_disabled() {
+        let config = mock_endpoint(|function| function.set_memory_bar(0, 0xfebf_0000, 0x1000, false, false));
+        let access = SizingRecorder { config, commands: RefCell::new(Vec::new()) };
+
+        EndpointHeader(ADDRESS).bar(0, &access).unwrap();
+        assert_eq!(*access.commands.borrow(), [(0x10, 0x0000)]);
+        assert_eq!(unsafe { access.read(ADDRESS, 0x04) }, 0x0000_0000);
+    }
+
+    #[test]
+    fn bar_decoding() {
+        let config = mock_endpoint(|function| {
+            function.set_memory_bar(0, 0x8000_0000, 0x10, true, false);
+            function.set_memory_bar(1, 0x40_0000_0000, 0x20_0000_0000, true, true);
+            function.set_io_bar(3, 0x1000, 0x4);
+        });
+        let endpoint = EndpointHeader(ADDRESS);
+
+        assert!(matches!(
+            endpoint.bar(0, &config),
+            Ok(Some(Bar::Memory32 { address: 0x8000_0000, size: 0x10, prefetchable: true }))
+        ));
+        assert!(matches!(
+            endpoint.bar(1, &config),
+            Ok(Some(Bar::Memory64 { address: 0x40_0000_0000, size: 0x20_0000_0000, prefetchable: true }))
+        ));
+        assert!(matches!(endpoint.bar(3, &config), Ok(Some(Bar::Io { port: 0x1000, size: 0x4 }))));
+        assert!(matches!(endpoint.bar(4, &config), Ok(None)));
+        assert!(matches!(endpoint.bar(6, &config), Err(BarError::NoSuchSlot)));
+    }
+
+    #[test]
+    fn bar_range() {
+        let bar = Bar::Memory32 { address: 0x8000_0000, size: 0x1000, prefetchable: false };
+        assert_eq!(bar.range(), 0x8000_0000..=0x8000_0fff);
+        let bar = Bar::Io { port: 0xe000, size: 0x40 };
+        assert_eq!(bar.range(), 0xe000..=0xe03f);
+
+        let bar = Bar::Memory64 { address: 0xffff_ffff_0000_0000, size: 0x1_0000_0000, prefetchable: true };
+        assert_eq!(bar.range(), 0xffff_ffff_0000_0000..=u64::MAX);
+    }
+
+    #[test]
+    fn bar_errors() {
+        let config = mock_endpoint(|function| {
+            // Memory BARs with the reserved types
+            function.set(0x10, 0x0000_0002);
+            function.set(0x14, 0x0000_0006);
+            // BARs with a type, but no writable address bits
+            function.set(0x18, 0x0000_0008);
+            function.set(0x1c, 0x0000_0004);
+            function.set(0x24, 0x0000_0001);
+        });
+        let endpoint = EndpointHeader(ADDRESS);
+
+        assert_eq!(endpoint.bar(0, &config).unwrap_err(), BarError::ReservedType);
+        assert_eq!(endpoint.bar(1, &config).unwrap_err(), BarError::ReservedType);
+        assert_eq!(endpoint.bar(2, &config).unwrap_err(), BarError::Unimplemented);
+        assert_eq!(endpoint.bar(3, &config).unwrap_err(), BarError::Unimplemented);
+        assert_eq!(endpoint.bar(5, &config).unwrap_err(), BarError::Unimplemented);
+
+        let config = mock_endpoint(|function| function.set_memory_bar(5, 0x8000_0000, 0x1000, false, true));
+        assert_eq!(EndpointHeader(ADDRESS).bar(5, &config).unwrap_err(), BarError::Truncated64Bit);
+    }
+
+    #[test]
+    fn bar_iterator() {
+        let config = mock_endpoint(|function| {
+            function.set_memory_bar(0, 0x8000_0000, 0x1000, false, true);
+            function.set_io_bar(2, 0x1000, 0x20);
+            function.set(0x20, 0x0000_0002);
+            function.set_memory_bar(5, 0x9000_0000, 0x1000, false, true);
+        });
+        let bars: Vec<(u8, Result<Bar, BarError>)> = EndpointHeader(ADDRESS).bars(&config).collect();
+
+        assert_eq!(bars.len(), 4);
+        assert!(matches!(bars[0], (0, Ok(Bar::Memory64 { address: 0x8000_0000, size: 0x1000, .. }))));
+        assert!(matches!(bars[1], (2, Ok(Bar::Io { port: 0x1000, size: 0x20 }))));
+        assert!(matches!(bars[2], (4, Err(BarError::ReservedType))));
+        assert!(matches!(bars[3], (5, Err(BarError::Truncated64Bit))));
+    }
+
+    #[test]
+    fn bridge_bar_iterator() {
+        let mut function = MockFunction::new(0x8086, 0x1234, HeaderType::PciPciBridge);
+        function.set_memory_bar(1, 0x8000_0000, 0x4000, false, false);
+        let mut config = MockConfigSpace::new();
+        config.add_function(ADDRESS, function);
+        let bridge = PciPciBridgeHeader(ADDRESS);
+
+        let bars: Vec<(u8, Result<Bar, BarError>)> = bridge.bars(&config).collect();
+        assert_eq!(bars.len(), 1);
+        assert!(matches!(bars[0], (1, Ok(Bar::Memory32 { address: 0x8000_0000, size: 0x4000, .. }))));
+        assert_eq!(bridge.bar(2, &config).unwrap_err(), BarError::NoSuchSlot);
+    }
+
+    #[test]
+    fn expansion_rom() {
+        let config = mock_endpoint(|function| function.set_expansion_rom(0xfe00_0000, 0x10000));
+        let endpoint = EndpointHeader(ADDRESS);
+
+        assert_eq!(
+            endpoint.expansion_rom(&config),
+            Some(ExpansionRom { address: 0xfe00_0000, size: 0x10000, enabled: false })
+        );
+        assert_eq!(
+            unsafe { endpoint.enable_expansion_rom(0xfd00_8000, &config) },
+            Err(BarWriteError::InvalidValue)
+        );
+        assert_eq!(unsafe { config.read(ADDRESS, 0x30) }, 0xfe00_0000);
+
+        unsafe { endpoint.enable_expansion_rom(0xfd01_0000, &config) }.unwrap();
+        assert_eq!(
+            endpoint.expansion_rom(&config),
+            Some(ExpansionRom { address: 0xfd01_0000, size: 0x10000, enabled: true })
+        );
+
+        endpoint.disable_expansion_rom(&config);
+        assert_eq!(
+            endpoint.expansion_rom(&config),
+            Some(ExpansionRom { address: 0xfd01_0000, size: 0x10000, enabled: false })
+        );
+
+        let config = mock_endpoint(|_| ());
+        assert_eq!(endpoint.expansion_rom(&config), None);
+        assert_eq!(
+            unsafe { endpoint.enable_expansion_rom(0xfd00_0000, &config) },
+            Err(BarWriteError::NoSuchBar)
+        );
+    }
+
+    #[test]
+    fn bridge_expansion_rom() {
+        let config = mock_bridge(|function| function.set_expansion_rom(0xfe00_0000, 0x800));
+        let bridge = PciPciBridgeHeader(ADDRESS);
+
+        unsafe { bridge.enable_expansion_rom(0xfd00_0800, &config) }.unwrap();
+        assert_eq!(unsafe { config.read(ADDRESS, 0x38) }, 0xfd00_0801);
+        assert_eq!(unsafe { config.read(ADDRESS, 0x30) }, 0x0000_0000);
+        bridge.disable_expansion_rom(&config);
+        assert_eq!(
+            bridge.expansion_rom(&config),
+            Some(ExpansionRom { address: 0xfd00_0800, size: 0x800, enabled: false })
+        );
+    }
+
+    fn mock_bridge(f: impl FnOnce(&mut MockFunction)) -> MockConfigSpace {
+        let mut function = MockFunction::bridge(0, 1, 1);
+        f(&mut function);
+        let mut config = MockConfigSpace::new();
+        config.add_function(ADDRESS, function);
+        config
+    }
+
+    #[test]
+    fn io_window() {
+        let config = mock_bridge(|_| ());
+        let bridge = PciPciBridgeHeader(ADDRESS);
+
+        bridge.set_io_window(Some(0x1_2000..=0x1_3fff), &config).unwrap();
+        assert_eq!(bridge.io_window(&config), Some(0x1_2000..=0x1_3fff));
+        assert_eq!(bridge.set_io_window(Some(0x1800..=0x1fff), &config), Err(BridgeWindowError::Misaligned));
+        assert_eq!(bridge.set_io_window(Some(0x1000..=0x17ff), &config), Err(BridgeWindowError::Misaligned));
+        assert_eq!(bridge.io_window(&config), Some(0x1_2000..=0x1_3fff));
+
+        bridge.set_io_window(None, &config).unwrap();
+        assert_eq!(bridge.io_window(&config), None);
+
+        /*
+         * A bridge that only decodes 16-bit ports.
+         */
+        let config = mock_bridge(|function| function.set(0x1c, 0x0000_0000));
+        assert!(!bridge.io_window_is_32bit(&config));
+        assert_eq!(bridge.set_io_window(Some(0x1_0000..=0x1_0fff), &config), Err(BridgeWindowError::OutOfRange));
+        bridge.set_io_window(Some(0xf000..=0xffff), &config).unwrap();
+        assert_eq!(bridge.io_window(&config), Some(0xf000..=0xffff));
+    }
+
+    #[test]
+    fn memory_window() {
+        let config = mock_bridge(|_| ());
+        let bridge = PciPciBridgeHeader(ADDRESS);
+
+        bridge.set_memory_window(Some(0x8000_0000..=0x802f_ffff), &config).unwrap();
+        assert_eq!(bridge.memory_window(&config), Some(0x8000_0000..=0x802f_ffff));
+        assert_eq!(
+            bridge.set_memory_window(Some(0x8008_0000..=0x802f_ffff), &config),
+            Err(BridgeWindowError::Misaligned)
+        );
+        assert_eq!(
+            bridge.set_memory_window(Some(0x8000_0000..=0x8007_ffff), &config),
+            Err(BridgeWindowError::Misaligned)
+        );
+
+        bridge.set_memory_window(None, &config).unwrap();
+        assert_eq!(bridge.memory_window(&config), None);
+
+        /*
+         * A base above the limit, as firmware might leave it.
+         */
+        let config = mock_bridge(|function| function.set(0x20, 0x8000_8010));
+        assert_eq!(bridge.memory_window(&config), None);
+    }
+
+    #[test]
+    fn prefetchable_memory_window() {
+        let config = mock_bridge(|_| ());
+        let bridge = PciPciBridgeHeader(ADDRESS);
+
+        assert!(bridge.prefetchable_memory_window_is_64bit(&config));
+        bridge.set_prefetchable_memory_window(Some(0x40_0000_0000..=0x40_3fff_ffff), &config).unwrap();
+        assert_eq!(bridge.prefetchable_memory_window(&config), Some(0x40_0000_0000..=0x40_3fff_ffff));
+        assert_eq!(
+            bridge.set_prefetchable_memory_window(Some(0x40_0000_0000..=0x40_0000_ffff), &config),
+            Err(BridgeWindowError::Misaligned)
+        );
+
+        bridge.set_prefetchable_memory_window(None, &config).unwrap();
+        assert_eq!(bridge.prefetchable_memory_window(&config), None);
+
+        /*
+         * A bridge that only decodes 32-bit prefetchable addresses.
+         */
+        let config = mock_bridge(|function| function.set(0x24, 0x0000_0000));
+        assert!(!bridge.prefetchable_memory_window_is_64bit(&config));
+        assert_eq!(
+            bridge.set_prefetchable_memory_window(Some(0x1_0000_0000..=0x1_000f_ffff), &config),
+            Err(BridgeWindowError::OutOfRange)
+        );
+        assert_eq!(
+            bridge.set_prefetchable_memory_window(Some(0xfff0_0000..=0x1_000f_ffff), &config),
+            Err(BridgeWindowError::OutOfRange)
+        );
+        bridge.set_prefetchable_memory_window(Some(0xc000_0000..=0xcfff_ffff), &config).unwrap();
+        assert_eq!(bridge.prefetchable_memory_window(&config), Some(0xc000_0000..=0xcfff_ffff));
+    }
 }
@@ -64,6 +64,7 @@ impl StatusRegister {
     /// Configuration Space read and writes.
     ///
     /// For PCIe always set to `Fast`
+    #[allow(clippy::result_unit_err)]
     pub fn devsel_timing(&self) -> Result<DevselTiming, ()> {
         let bits = self.0.get_bits(9..11);
         DevselTiming::try_from(bits as u8)
@@ -141,3 +142,39 @@ bitflags::bitflags! {
         const INTERRUPT_DISABLE = 1 << 10;
     }
 }
+
+bitflags::bitflags! {
+    /// The Bridge Control register of a PCI-PCI bridge.
+    #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+    pub struct BridgeControl: u16 {
+        const PARITY_ERROR_RESPONSE_ENABLE = 1 << 0;
+        const SERR_ENABLE = 1 << 1;
+        const ISA_ENABLE = 1 << 2;
+        const VGA_ENABLE = 1 << 3;
+        const VGA_16BIT_DECODE = 1 << 4;
+        const MASTER_ABORT_MODE = 1 << 5;
+        const SECONDARY_BUS_RESET = 1 << 6;
+        const FAST_BACK_TO_BACK_ENABLE = 1 << 7;
+        const PRIMARY_DISCARD_TIMEOUT = 1 << 8;
+        const SECONDARY_DISCARD_TIMEOUT = 1 << 9;
+        const DISCARD_TIMER_STATUS = 1 << 10;
+        const DISCARD_TIMER_SERR_ENABLE = 1 << 11;
+    }
+}
+
+bitflags::bitflags! {
+    /// The Bridge Control register of a CardBus bridge.
+    #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+    pub struct CardBusBridgeControl: u16 {
+        const PARITY_ERROR_RESPONSE_ENABLE = 1 << 0;
+        const SERR_ENABLE = 1 << 1;
+        const ISA_ENABLE = 1 << 2;
+        const VGA_ENABLE = 1 << 3;
+        const MASTER_ABORT_MODE = 1 << 5;
+        const CARDBUS_RESET = 1 << 6;
+        const IREQ_INT_ENABLE = 1 << 7;
+        const MEMORY_0_PREFETCH_ENABLE = 1 << 8;
+        const MEMORY_1_PREFETCH_ENABLE = 1 << 9;
+        const WRITE_POSTING_ENABLE = 1 << 10;
+    }
+}
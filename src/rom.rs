@@ -0,0 +1,382 @@
+//! Parsing of the contents of a function's Expansion ROM. The ROM must first be mapped (e.g. with
+//! [`EndpointHeader::enable_expansion_rom`](crate::EndpointHeader::enable_expansion_rom)) and copied out of
+//! memory space - this module only deals with the bytes.
+//!
+//! A ROM contains one or more images, one after another. Each image starts with a ROM header, which points to a
+//! PCI Data Structure describing the image:
+//! ```ignore
+//!     ROM Header                                  PCI Data Structure
+//!     +--------+----------------------------+     +--------+----------------------------+
+//!     | 0x00   | Signature (0x55, 0xaa)     |     | 0x00   | Signature ("PCIR")         |
+//!     +--------+----------------------------+     +--------+----------------------------+
+//!     | 0x02   | Code-type specific         |     | 0x04   | Vendor ID                  |
+//!     |  ...   |                            |     | 0x06   | Device ID                  |
+//!     +--------+----------------------------+     | 0x0a   | Length of structure        |
+//!     | 0x18   | Pointer to PCI Data Struct |     | 0x0c   | Structure revision         |
+//!     +--------+----------------------------+     | 0x0d   | Class code                 |
+//!                                                 | 0x10   | Image length (512B units)  |
+//!                                                 | 0x12   | Code revision              |
+//!                                                 | 0x14   | Code type                  |
+//!                                                 | 0x15   | Indicator (bit 7: last)    |
+//!                                                 +--------+----------------------------+
+//! ```
+
+use crate::{BaseClass, DeviceId, Interface, SubClass, VendorId};
+
+const ROM_SIGNATURE: [u8; 2] = [0x55, 0xaa];
+const PCIR_SIGNATURE: [u8; 4] = *b"PCIR";
+const EFI_SIGNATURE: u32 = 0x0ef1;
+
+/// The lengths of images are given in units of 512 bytes.
+const IMAGE_UNIT: usize = 512;
+
+/// Parse the images contained in the bytes of an Expansion ROM.
+pub fn images(rom: &[u8]) -> RomImageIterator<'_> {
+    RomImageIterator { rom, offset: 0, done: false }
+}
+
+/// Find the first image in a ROM with the given code type, e.g. the EFI driver. Images that can't be parsed end
+/// the search.
+pub fn find_image(rom: &[u8], code_type: CodeType) -> Option<RomImage<'_>> {
+    images(rom).map_while(Result::ok).find(|image| image.code_type() == code_type)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RomError {
+    /// The image doesn't start with `0x55, 0xaa`.
+    InvalidRomSignature { offset: usize },
+    /// The image's PCI Data Structure doesn't start with `PCIR`.
+    InvalidPcirSignature { offset: usize },
+    /// The image, or a structure within it, extends past the end of the ROM.
+    Truncated { offset: usize },
+}
+
+/// The type of code contained in an image.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CodeType {
+    /// Legacy x86 (PC-AT compatible) option ROM code.
+    X86,
+    OpenFirmware,
+    HpPaRisc,
+    /// A UEFI driver. See [`RomImage::efi`].
+    Efi,
+    Unknown(u8),
+}
+
+impl From<u8> for CodeType {
+    fn from(value: u8) -> Self {
+        match value {
+            0x00 => CodeType::X86,
+            0x01 => CodeType::OpenFirmware,
+            0x02 => CodeType::HpPaRisc,
+            0x03 => CodeType::Efi,
+            other => CodeType::Unknown(other),
+        }
+    }
+}
+
+/// Iterates over the images of an Expansion ROM. Created by [`images`]. If an image can't be parsed, the error is
+/// yielded and iteration stops, as the position of the next image isn't known.
+pub struct RomImageIterator<'a> {
+    rom: &'a [u8],
+    offset: usize,
+    done: bool,
+}
+
+impl<'a> Iterator for RomImageIterator<'a> {
+    type Item = Result<RomImage<'a>, RomError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done || self.offset >= self.rom.len() {
+            return None;
+        }
+
+        match RomImage::parse(self.rom, self.offset) {
+            Ok(image) => {
+                if image.is_last() {
+                    self.done = true;
+                }
+                self.offset += image.data.len();
+                Some(Ok(image))
+            }
+            Err(error) => {
+                self.done = true;
+                Some(Err(error))
+            }
+        }
+    }
+}
+
+/// One image of an Expansion ROM.
+#[derive(Clone, Copy, Debug)]
+pub struct RomImage<'a> {
+    /// The offset of the image from the start of the ROM.
+    offset: usize,
+    /// The bytes of the whole image, starting with the ROM header.
+    data: &'a [u8],
+    /// The offset of the PCI Data Structure within `data`.
+    pcir: usize,
+}
+
+impl<'a> RomImage<'a> {
+    fn parse(rom: &'a [u8], offset: usize) -> Result<RomImage<'a>, RomError> {
+        let header = &rom[offset..];
+        if header.len() < 0x1a {
+            return Err(RomError::Truncated { offset });
+        }
+        if header[0..2] != ROM_SIGNATURE {
+            return Err(RomError::InvalidRomSignature { offset });
+        }
+
+        let pcir = read_u16(header, 0x18) as usize;
+        if header.len() < pcir + 0x18 {
+            return Err(RomError::Truncated { offset: offset + pcir });
+        }
+        if header[pcir..(pcir + 4)] != PCIR_SIGNATURE {
+            return Err(RomError::InvalidPcirSignature { offset: offset + pcir });
+        }
+
+        /*
+         * The image must at least contain its own headers, which also means it can't be empty.
+         */
+        let length = read_u16(header, pcir + 0x10) as usize * IMAGE_UNIT;
+        if header.len() < length || length < pcir + 0x18 {
+            return Err(RomError::Truncated { offset });
+        }
+
+        Ok(RomImage { offset, data: &header[..length], pcir })
+    }
+
+    /// Get the offset of the image from the start of the ROM.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Get the bytes of the whole image, starting with its ROM header.
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    pub fn vendor_id(&self) -> VendorId {
+        read_u16(self.data, self.pcir + 0x04)
+    }
+
+    pub fn device_id(&self) -> DeviceId {
+        read_u16(self.data, self.pcir + 0x06)
+    }
+
+    /// Get the class code of the device the image is for, as `(base_class, sub_class, interface)`.
+    pub fn class(&self) -> (BaseClass, SubClass, Interface) {
+        (self.data[self.pcir + 0x0f], self.data[self.pcir + 0x0e], self.data[self.pcir + 0x0d])
+    }
+
+    /// Get the vendor-specific revision of the code in the image.
+    pub fn code_revision(&self) -> u16 {
+        read_u16(self.data, self.pcir + 0x12)
+    }
+
+    pub fn code_type(&self) -> CodeType {
+        CodeType::from(self.data[self.pcir + 0x14])
+    }
+
+    /// Is this the last image in the ROM?
+    pub fn is_last(&self) -> bool {
+        self.data[self.pcir + 0x15] & 0x80 != 0
+    }
+
+    /// Get the EFI-specific fields of the image, if it is a UEFI driver.
+    pub fn efi(&self) -> Option<EfiRomImage<'a>> {
+        if self.code_type() != CodeType::Efi || read_u32(self.data, 0x04) != EFI_SIGNATURE {
+            return None;
+        }
+        Some(EfiRomImage { data: self.data })
+    }
+}
+
+/// The EFI ROM header of an image containing a UEFI driver:
+/// ```ignore
+///     +--------+----------------------------+
+///     | 0x00   | Signature (0x55, 0xaa)     |
+///     | 0x02   | Initialization size        |
+///     | 0x04   | EFI signature (0x0ef1)     |
+///     | 0x08   | EFI subsystem              |
+///     | 0x0a   | EFI machine type           |
+///     | 0x0c   | Compression type           |
+///     | 0x0e   | Reserved                   |
+///     | 0x16   | Offset to EFI image        |
+///     | 0x18   | Pointer to PCI Data Struct |
+///     +--------+----------------------------+
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct EfiRomImage<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> EfiRomImage<'a> {
+    /// Get the PE/COFF subsystem of the driver (e.g. `0xb` for a boot service driver).
+    pub fn subsystem(&self) -> u16 {
+        read_u16(self.data, 0x08)
+    }
+
+    /// Get the PE/COFF machine type of the driver (e.g. `0x8664` for x64).
+    pub fn machine_type(&self) -> u16 {
+        read_u16(self.data, 0x0a)
+    }
+
+    /// Is the driver compressed (with the EFI compression algorithm)?
+    pub fn is_compressed(&self) -> bool {
+        read_u16(self.data, 0x0c) == 0x1
+    }
+
+    /// Get the bytes of the driver itself, which run to the end of the image (although the image may be padded
+    /// past the end of the driver). Returns `None` if the driver's offset lies outside the image.
+    pub fn driver(&self) -> Option<&'a [u8]> {
+        let offset = read_u16(self.data, 0x16) as usize;
+        self.data.get(offset..)
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]])
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::{vec, vec::Vec};
+
+    const PCIR: usize = 0x1c;
+
+    /// Build an image of `units` 512-byte units, with its PCI Data Structure right after the ROM header.
+    fn image(code_type: u8, last: bool, units: u16) -> Vec<u8> {
+        let mut data = vec![0; units as usize * IMAGE_UNIT];
+        data[0..2].copy_from_slice(&ROM_SIGNATURE);
+        data[0x18..0x1a].copy_from_slice(&(PCIR as u16).to_le_bytes());
+
+        data[PCIR..(PCIR + 4)].copy_from_slice(&PCIR_SIGNATURE);
+        data[(PCIR + 0x04)..(PCIR + 0x06)].copy_from_slice(&0x8086u16.to_le_bytes());
+        data[(PCIR + 0x06)..(PCIR + 0x08)].copy_from_slice(&0x1234u16.to_le_bytes());
+        data[(PCIR + 0x0a)..(PCIR + 0x0c)].copy_from_slice(&0x18u16.to_le_bytes());
+        data[(PCIR + 0x0d)..(PCIR + 0x10)].copy_from_slice(&[0x00, 0x00, 0x02]);
+        data[(PCIR + 0x10)..(PCIR + 0x12)].copy_from_slice(&units.to_le_bytes());
+        data[(PCIR + 0x12)..(PCIR + 0x14)].copy_from_slice(&0x0102u16.to_le_bytes());
+        data[PCIR + 0x14] = code_type;
+        data[PCIR + 0x15] = if last { 0x80 } else { 0x00 };
+        data
+    }
+
+    /// Build a UEFI driver image, with the driver starting at `0x40`.
+    fn efi_image(last: bool, units: u16) -> Vec<u8> {
+        let mut data = image(0x03, last, units);
+        data[0x04..0x08].copy_from_slice(&EFI_SIGNATURE.to_le_bytes());
+        data[0x08..0x0a].copy_from_slice(&0x000bu16.to_le_bytes());
+        data[0x0a..0x0c].copy_from_slice(&0x8664u16.to_le_bytes());
+        data[0x16..0x18].copy_from_slice(&0x0040u16.to_le_bytes());
+        data[0x40] = 0x4d;
+        data
+    }
+
+    fn parse(rom: &[u8]) -> Vec<Result<(usize, CodeType), RomError>> {
+        images(rom).map(|image| image.map(|image| (image.offset(), image.code_type()))).collect()
+    }
+
+    #[test]
+    fn multiple_images() {
+        let rom = [image(0x00, false, 1), efi_image(true, 2)].concat();
+        assert_eq!(parse(&rom), [Ok((0, CodeType::X86)), Ok((512, CodeType::Efi))]);
+
+        let x86 = images(&rom).next().unwrap().unwrap();
+        assert_eq!((x86.vendor_id(), x86.device_id()), (0x8086, 0x1234));
+        assert_eq!(x86.class(), (0x02, 0x00, 0x00));
+        assert_eq!(x86.code_revision(), 0x0102);
+        assert_eq!(x86.data().len(), 512);
+        assert!(!x86.is_last());
+        assert!(x86.efi().is_none());
+
+        let efi = find_image(&rom, CodeType::Efi).unwrap();
+        assert_eq!(efi.offset(), 512);
+        assert!(efi.is_last());
+        let driver = efi.efi().unwrap();
+        assert_eq!((driver.subsystem(), driver.machine_type()), (0x000b, 0x8664));
+        assert!(!driver.is_compressed());
+        assert_eq!(driver.driver().unwrap().len(), 1024 - 0x40);
+        assert_eq!(driver.driver().unwrap()[0], 0x4d);
+
+        assert!(find_image(&rom, CodeType::OpenFirmware).is_none());
+    }
+
+    #[test]
+    fn stops_at_last_image() {
+        /*
+         * Anything after the image marked as the last one is ignored, even if it isn't a valid image.
+         */
+        let rom = [image(0x00, true, 1), vec![0xff; 512]].concat();
+        assert_eq!(parse(&rom), [Ok((0, CodeType::X86))]);
+    }
+
+    #[test]
+    fn missing_last_image() {
+        let rom = image(0x00, false, 1);
+        assert_eq!(parse(&rom), [Ok((0, CodeType::X86))]);
+
+        let rom = [image(0x00, false, 1), vec![0x55, 0xaa, 0x00, 0x00]].concat();
+        assert_eq!(parse(&rom), [Ok((0, CodeType::X86)), Err(RomError::Truncated { offset: 512 })]);
+    }
+
+    #[test]
+    fn invalid_signatures() {
+        let mut rom = image(0x00, true, 1);
+        rom[1] = 0x00;
+        assert_eq!(parse(&rom), [Err(RomError::InvalidRomSignature { offset: 0 })]);
+
+        let mut rom = [image(0x00, false, 1), image(0x00, true, 1)].concat();
+        rom[512 + PCIR] = b'X';
+        assert_eq!(
+            parse(&rom),
+            [Ok((0, CodeType::X86)), Err(RomError::InvalidPcirSignature { offset: 512 + PCIR })]
+        );
+        assert!(find_image(&rom, CodeType::X86).is_some());
+    }
+
+    #[test]
+    fn pcir_past_end() {
+        let mut rom = image(0x00, true, 1);
+        rom[0x18..0x1a].copy_from_slice(&0x01f0u16.to_le_bytes());
+        assert_eq!(parse(&rom), [Err(RomError::Truncated { offset: 0x1f0 })]);
+
+        rom[0x18..0x1a].copy_from_slice(&0xffffu16.to_le_bytes());
+        assert_eq!(parse(&rom), [Err(RomError::Truncated { offset: 0xffff })]);
+    }
+
+    #[test]
+    fn bad_image_lengths() {
+        let mut rom = image(0x00, true, 1);
+        rom[(PCIR + 0x10)..(PCIR + 0x12)].copy_from_slice(&0u16.to_le_bytes());
+        assert_eq!(parse(&rom), [Err(RomError::Truncated { offset: 0 })]);
+
+        rom[(PCIR + 0x10)..(PCIR + 0x12)].copy_from_slice(&2u16.to_le_bytes());
+        assert_eq!(parse(&rom), [Err(RomError::Truncated { offset: 0 })]);
+
+        assert_eq!(parse(&rom[..0x10]), [Err(RomError::Truncated { offset: 0 })]);
+        assert_eq!(parse(&[]), []);
+    }
+
+    #[test]
+    fn efi_driver_outside_image() {
+        let mut rom = efi_image(true, 1);
+        rom[0x16..0x18].copy_from_slice(&0x0400u16.to_le_bytes());
+        assert!(find_image(&rom, CodeType::Efi).unwrap().efi().unwrap().driver().is_none());
+
+        /*
+         * An image with the EFI code type but without the EFI signature isn't treated as a driver.
+         */
+        rom[0x04] = 0x00;
+        assert!(find_image(&rom, CodeType::Efi).unwrap().efi().is_none());
+    }
+}
@@ -0,0 +1,515 @@
+use crate::{
+    capability::{AcsFlags, DevicePortType, PciCapability, PciExtendedCapability},
+    ConfigRegionAccess,
+    EnumeratedFunction,
+    HeaderType,
+    PciAddress,
+    PciEnumerator,
+    PciFunction,
+    PciPciBridgeHeader,
+};
+use alloc::{collections::BTreeMap, vec::Vec};
+
+/// The Access Control Services a function must have enabled for peer-to-peer transactions to be routed through
+/// (and so checked by) the IOMMU. These are the services Linux requires of a port before it will put the
+/// functions on either side of it in different IOMMU groups.
+const REQUIRED_ACS: AcsFlags = AcsFlags::SOURCE_VALIDATION
+    .union(AcsFlags::P2P_REQUEST_REDIRECT)
+    .union(AcsFlags::P2P_COMPLETION_REDIRECT)
+    .union(AcsFlags::UPSTREAM_FORWARDING);
+
+/// A function in a [`PciTopology`], along with the parts of its configuration that decide how isolated it is
+/// from other functions.
+#[derive(Clone, Copy, Debug)]
+pub struct TopologyFunction {
+    pub function: EnumeratedFunction,
+    /// The bridge whose secondary bus the function is on, or `None` if it is on a root bus.
+    pub upstream_bridge: Option<PciAddress>,
+    /// For PCI-PCI bridges, the bus number of the secondary bus.
+    pub secondary_bus: Option<u8>,
+    /// Whether the function is part of a multi-function device.
+    pub multiple_functions: bool,
+    /// The type of PCI Express function, or `None` if it is a conventional PCI function.
+    pub port_type: Option<DevicePortType>,
+    /// The function's Access Control Services, if it has the capability.
+    pub acs: Option<AcsState>,
+}
+
+/// The Access Control Services a function implements, and those that are enabled.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct AcsState {
+    pub capabilities: AcsFlags,
+    pub control: AcsFlags,
+}
+
+/// A snapshot of the functions below a root bus, and of how they are connected. This is read from the hardware
+/// once, so later changes to the configuration (e.g. to ACS controls) need a new snapshot.
+#[derive(Clone, Debug)]
+pub struct PciTopology {
+    functions: BTreeMap<PciAddress, TopologyFunction>,
+}
+
+impl PciTopology {
+    /// Find every function below `root_bus`, following the buses behind PCI-PCI bridges (which must already have
+    /// their bus numbers set up).
+    pub fn new(segment: u16, root_bus: u8, access: &impl ConfigRegionAccess) -> PciTopology {
+        let enumerated: Vec<EnumeratedFunction> = PciEnumerator::recursive(segment, root_bus, access).collect();
+
+        /*
+         * Buses are scanned in order, so a bridge might be found after the functions behind it. Map each
+         * secondary bus to its bridge first.
+         */
+        let mut bridges = BTreeMap::new();
+        for function in enumerated.iter().filter(|function| function.header_type == HeaderType::PciPciBridge) {
+            let bridge = PciPciBridgeHeader::from_header(function.header(), access).unwrap();
+            bridges.entry(bridge.secondary_bus_number(access)).or_insert(function.address);
+        }
+
+        let mut functions = BTreeMap::new();
+        for function in enumerated {
+            let header = PciFunction::from_header(function.header(), access);
+
+            let secondary_bus = match header {
+                PciFunction::PciPciBridge(ref bridge) => Some(bridge.secondary_bus_number(access)),
+                _ => None,
+            };
+            let port_type = header.capabilities(access).find_map(|capability| match capability {
+                PciCapability::PciExpress(pcie) => Some(pcie.device_port_type()),
+                _ => None,
+            });
+
+            /*
+             * Only PCI Express functions have an extended configuration space.
+             */
+            let acs = if port_type.is_some() {
+                header.extended_capabilities(access).find_map(|capability| match capability {
+                    PciExtendedCapability::AccessControlServices(acs) => {
+                        Some(AcsState { capabilities: acs.capabilities(access), control: acs.control(access) })
+                    }
+                    _ => None,
+                })
+            } else {
+                None
+            };
+
+            let upstream_bridge = if function.address.bus() == root_bus {
+                None
+            } else {
+                bridges.get(&function.address.bus()).copied()
+            };
+
+            functions.insert(
+                function.address,
+                TopologyFunction {
+                    function,
+                    upstream_bridge,
+                    secondary_bus,
+                    multiple_functions: header.has_multiple_functions(access),
+                    port_type,
+                    acs,
+                },
+            );
+        }
+
+        PciTopology { functions }
+    }
+
+    /// Iterate over the functions in the topology, in order of address.
+    pub fn functions(&self) -> impl Iterator<Item = &TopologyFunction> {
+        self.functions.values()
+    }
+
+    pub fn function(&self, address: PciAddress) -> Option<&TopologyFunction> {
+        self.functions.get(&address)
+    }
+
+    /// Split the functions into groups that are isolated from each other: a function can't reach a function in
+    /// another group with peer-to-peer DMA that bypasses the IOMMU, or issue DMA that the IOMMU can't tell apart
+    /// from theirs. This follows how Linux builds IOMMU groups:
+    ///    - functions that issue DMA with the Requester ID of a bridge above them (see
+    ///      [`PciTopology::dma_aliases`]) are grouped with that bridge
+    ///    - functions are grouped with every bridge above them up to the first one which, along with every bridge
+    ///      above it, has the required Access Control Services enabled (Source Validation, P2P Request Redirect,
+    ///      P2P Completion Redirect and Upstream Forwarding)
+    ///    - functions of a multi-function device are grouped together unless they have ACS enabled
+    ///
+    /// Conventional PCI functions and bridges never provide isolation. Each group is sorted by address, and the
+    /// groups are sorted by their first address.
+    pub fn isolation_groups(&self) -> Vec<Vec<PciAddress>> {
+        let addresses: Vec<PciAddress> = self.functions.keys().copied().collect();
+        let index = |address: PciAddress| addresses.binary_search(&address).unwrap();
+        let mut groups = UnionFind::new(addresses.len());
+
+        for function in self.functions.values() {
+            let this = index(function.function.address);
+
+            /*
+             * Group the function with the bridges its DMA is aliased to. Its DMA then looks like it comes from the
+             * topmost of these.
+             */
+            let mut alias = function;
+            self.walk_dma_aliases(function.function.address, |bridge, _| {
+                groups.union(this, index(bridge.function.address));
+                alias = bridge;
+            });
+
+            /*
+             * Continue upstream until the path to the root bus is isolated by ACS.
+             */
+            let mut top = alias;
+            while let Some(bridge) = top.upstream_bridge.and_then(|address| self.functions.get(&address)) {
+                if self.acs_path_enabled(bridge) {
+                    break;
+                }
+                groups.union(this, index(bridge.function.address));
+                top = bridge;
+            }
+
+            /*
+             * Functions of a multi-function device can reach each other unless they have ACS enabled.
+             */
+            if top.multiple_functions && !self.acs_enabled(top) {
+                let slot = top.function.address;
+                for other in self.functions.values() {
+                    let address = other.function.address;
+                    if address.segment() == slot.segment()
+                        && address.bus() == slot.bus()
+                        && address.device() == slot.device()
+                        && !self.acs_enabled(other)
+                    {
+                        groups.union(this, index(address));
+                    }
+                }
+            }
+        }
+
+        let mut sets: BTreeMap<usize, Vec<PciAddress>> = BTreeMap::new();
+        for (i, &address) in addresses.iter().enumerate() {
+            sets.entry(groups.find(i)).or_default().push(address);
+        }
+        let mut sets: Vec<Vec<PciAddress>> = sets.into_values().collect();
+        sets.sort_by_key(|group| group[0]);
+        sets
+    }
+
+    /// Get every Requester ID that DMA from the function at `address` may carry by the time it reaches the root
+    /// complex, starting with the function's own. Bridges between the function and the root complex may take
+    /// ownership of its transactions:
+    ///    - PCI Express Root Ports and Switch Ports forward the Requester ID unchanged
+    ///    - PCIe-to-PCI(-X) bridges use the Requester ID of device 0, function 0 on their secondary bus
+    ///    - PCI(-X)-to-PCIe bridges and conventional PCI bridges use their own Requester ID
+    ///
+    /// The IOMMU must treat all of these IDs as belonging to the function. Returns `None` if the function isn't
+    /// in the topology.
+    pub fn dma_aliases(&self, address: PciAddress) -> Option<Vec<PciAddress>> {
+        self.functions.get(&address)?;
+
+        let mut aliases = Vec::from([address]);
+        self.walk_dma_aliases(address, |_, requester_id| {
+            let alias = PciAddress::from_routing_id(address.segment(), requester_id);
+            if !aliases.contains(&alias) {
+                aliases.push(alias);
+            }
+        });
+        Some(aliases)
+    }
+
+    /// Call `f` with each bridge above the function at `address` that its DMA may be aliased to, along with the
+    /// Requester ID it is aliased to, starting from the closest bridge.
+    fn walk_dma_aliases<'a>(&'a self, address: PciAddress, mut f: impl FnMut(&'a TopologyFunction, u16)) {
+        let mut current = self.functions.get(&address);
+        while let Some(bridge) =
+            current.and_then(|function| function.upstream_bridge).and_then(|address| self.functions.get(&address))
+        {
+            match bridge.port_type {
+                /*
+                 * PCI Express ports forward the Requester IDs of the functions below them.
+                 */
+                Some(DevicePortType::RootPort)
+                | Some(DevicePortType::UpstreamPort)
+                | Some(DevicePortType::DownstreamPort) => (),
+
+                /*
+                 * PCIe-to-PCI bridges take ownership of transactions from their conventional secondary bus, and
+                 * use the Requester ID of device 0, function 0 on it.
+                 */
+                Some(DevicePortType::PcieToPciBridge) => {
+                    let secondary_bus = bridge.secondary_bus.unwrap_or(0);
+                    f(bridge, (secondary_bus as u16) << 8);
+                }
+
+                /*
+                 * Other bridges (including conventional PCI and PCI-X ones) use their own Requester ID.
+                 */
+                _ => f(bridge, bridge.function.address.routing_id()),
+            }
+            current = Some(bridge);
+        }
+    }
+
+    /// Is ACS enabled on `function` and every bridge above it?
+    fn acs_path_enabled(&self, function: &TopologyFunction) -> bool {
+        let mut current = Some(function);
+        while let Some(function) = current {
+            if !self.acs_enabled(function) {
+                return false;
+            }
+            current = function.upstream_bridge.and_then(|address| self.functions.get(&address));
+        }
+        true
+    }
+
+    /// Does `function` isolate the functions below it (for a port) or the other functions of its device (for a
+    /// function of a multi-function device) from peer-to-peer DMA?
+    fn acs_enabled(&self, function: &TopologyFunction) -> bool {
+        match function.port_type {
+            /*
+             * Conventional PCI functions share a bus, so any of them can snoop or receive another's DMA.
+             */
+            None => false,
+
+            /*
+             * PCIe-to-PCI and PCI-to-PCIe bridges have a conventional bus on one side, so can't redirect
+             * peer-to-peer DMA. Root Complex Event Collectors must never implement ACS.
+             */
+            Some(DevicePortType::PcieToPciBridge)
+            | Some(DevicePortType::PciToPcieBridge)
+            | Some(DevicePortType::RootComplexEventCollector) => false,
+
+            Some(DevicePortType::RootPort) | Some(DevicePortType::DownstreamPort) => {
+                Self::acs_flags_enabled(function)
+            }
+
+            /*
+             * Any other function of a multi-function device (including an Upstream Port) needs ACS to be isolated
+             * from the other functions.
+             */
+            _ if function.multiple_functions => Self::acs_flags_enabled(function),
+
+            /*
+             * ACS doesn't apply to single-function devices, or to single-function Upstream Ports.
+             */
+            _ => true,
+        }
+    }
+
+    /// Are all of the required Access Control Services enabled? Services that aren't implemented are taken to be
+    /// always enabled (except Egress Control, which isn't required).
+    fn acs_flags_enabled(function: &TopologyFunction) -> bool {
+        match function.acs {
+            Some(acs) => {
+                let required = REQUIRED_ACS & (acs.capabilities | AcsFlags::P2P_EGRESS_CONTROL);
+                acs.control.contains(required)
+            }
+            None => false,
+        }
+    }
+}
+
+/// A disjoint-set forest, used to merge functions into isolation groups.
+struct UnionFind {
+    parents: Vec<usize>,
+}
+
+impl UnionFind {
+    fn new(len: usize) -> UnionFind {
+        UnionFind { parents: (0..len).collect() }
+    }
+
+    fn find(&mut self, mut i: usize) -> usize {
+        while self.parents[i] != i {
+            self.parents[i] = self.parents[self.parents[i]];
+            i = self.parents[i];
+        }
+        i
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        if a != b {
+            self.parents[a.max(b)] = a.min(b);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::access::{MockConfigSpace, MockFunction};
+
+    const ENDPOINT: u32 = 0b0000;
+    const ROOT_PORT: u32 = 0b0100;
+    const UPSTREAM_PORT: u32 = 0b0101;
+    const ROOT_COMPLEX_EVENT_COLLECTOR: u32 = 0b1010;
+    const PCIE_TO_PCI_BRIDGE: u32 = 0b0111;
+
+    /// Give `function` a PCI Express capability with the given Device/Port Type.
+    fn pcie(mut function: MockFunction, port_type: u32) -> MockFunction {
+        function.add_capability(0x40, 0x10);
+        function.set(0x40, function.get(0x40) | port_type << 20);
+        function
+    }
+
+    /// Give `function` an ACS capability which implements and enables all of the required services.
+    fn acs(mut function: MockFunction) -> MockFunction {
+        function.add_extended_capability(0x100, 0x000D, 1);
+        function.set(0x104, (REQUIRED_ACS.bits() as u32) << 16 | REQUIRED_ACS.bits() as u32);
+        function
+    }
+
+    fn groups(config: &MockConfigSpace) -> Vec<Vec<PciAddress>> {
+        PciTopology::new(0, 0, config).isolation_groups()
+    }
+
+    #[test]
+    fn root_ports() {
+        let root_port = PciAddress::new(0, 0, 0x1c, 0);
+        let other_root_port = PciAddress::new(0, 0, 0x1d, 0);
+        let below = PciAddress::new(0, 1, 0, 0);
+        let other_below = PciAddress::new(0, 2, 0, 0);
+
+        let mut config = MockConfigSpace::new();
+        config.add_function(root_port, acs(pcie(MockFunction::bridge(0, 1, 1), ROOT_PORT)));
+        config.add_function(other_root_port, pcie(MockFunction::bridge(0, 2, 2), ROOT_PORT));
+        config.add_function(below, pcie(MockFunction::endpoint(), ENDPOINT));
+        config.add_function(other_below, pcie(MockFunction::endpoint(), ENDPOINT));
+
+        /*
+         * Only the root port with ACS enabled isolates the function below it.
+         */
+        assert_eq!(
+            groups(&config),
+            [Vec::from([root_port]), Vec::from([other_root_port, other_below]), Vec::from([below])]
+        );
+    }
+
+    #[test]
+    fn multi_function_endpoint() {
+        let functions = [PciAddress::new(0, 0, 3, 0), PciAddress::new(0, 0, 3, 1)];
+
+        let mut config = MockConfigSpace::new();
+        for &address in functions.iter() {
+            let mut function = pcie(MockFunction::endpoint(), ENDPOINT);
+            function.set_multiple_functions(true);
+            config.add_function(address, function);
+        }
+        assert_eq!(groups(&config), [Vec::from(functions)]);
+
+        for &address in functions.iter() {
+            let function = config.remove_function(address).unwrap();
+            config.add_function(address, acs(function));
+        }
+        assert_eq!(groups(&config), [Vec::from([functions[0]]), Vec::from([functions[1]])]);
+    }
+
+    #[test]
+    fn root_complex_event_collector() {
+        let functions = [PciAddress::new(0, 0, 3, 0), PciAddress::new(0, 0, 3, 1)];
+
+        let mut config = MockConfigSpace::new();
+        let mut function = pcie(MockFunction::endpoint(), ENDPOINT);
+        function.set_multiple_functions(true);
+        config.add_function(functions[0], function);
+        let mut function = acs(pcie(MockFunction::endpoint(), ROOT_COMPLEX_EVENT_COLLECTOR));
+        function.set_multiple_functions(true);
+        config.add_function(functions[1], function);
+
+        /*
+         * The collector never provides isolation, even with ACS enabled.
+         */
+        assert_eq!(groups(&config), [Vec::from(functions)]);
+    }
+
+    #[test]
+    fn multi_function_upstream_port() {
+        let functions = [PciAddress::new(0, 0, 1, 0), PciAddress::new(0, 0, 1, 1)];
+
+        let mut config = MockConfigSpace::new();
+        let mut function = pcie(MockFunction::bridge(0, 1, 1), UPSTREAM_PORT);
+        function.set_multiple_functions(true);
+        config.add_function(functions[0], function);
+        let mut function = pcie(MockFunction::endpoint(), ENDPOINT);
+        function.set_multiple_functions(true);
+        config.add_function(functions[1], function);
+        assert_eq!(groups(&config), [Vec::from(functions)]);
+
+        let function = config.remove_function(functions[0]).unwrap();
+        config.add_function(functions[0], acs(function));
+        assert_eq!(groups(&config), [Vec::from([functions[0]]), Vec::from([functions[1]])]);
+    }
+
+    #[test]
+    fn pcie_to_pci_bridge() {
+        let root_port = PciAddress::new(0, 0, 0x1c, 0);
+        let bridge_address = PciAddress::new(0, 1, 0, 0);
+        let conventional = [PciAddress::new(0, 2, 0, 0), PciAddress::new(0, 2, 1, 0)];
+
+        let mut config = MockConfigSpace::new();
+        config.add_function(root_port, acs(pcie(MockFunction::bridge(0, 1, 2), ROOT_PORT)));
+        config.add_function(bridge_address, pcie(MockFunction::bridge(1, 2, 2), PCIE_TO_PCI_BRIDGE));
+        config.add_function(conventional[0], MockFunction::endpoint());
+        config.add_function(conventional[1], MockFunction::endpoint());
+
+        /*
+         * Everything behind the bridge shares its Requester ID, so is grouped with it.
+         */
+        assert_eq!(
+            groups(&config),
+            [Vec::from([root_port]), Vec::from([bridge_address, conventional[0], conventional[1]])]
+        );
+    }
+
+    #[test]
+    fn aliases_behind_pcie_to_pci_bridge() {
+        let root_port = PciAddress::new(0, 0, 0x1c, 0);
+        let bridge_address = PciAddress::new(0, 1, 0, 0);
+
+        let mut config = MockConfigSpace::new();
+        config.add_function(root_port, pcie(MockFunction::bridge(0, 1, 2), ROOT_PORT));
+        config.add_function(bridge_address, pcie(MockFunction::bridge(1, 2, 2), PCIE_TO_PCI_BRIDGE));
+        config.add_function(PciAddress::new(0, 2, 0, 0), MockFunction::endpoint());
+        config.add_function(PciAddress::new(0, 2, 3, 0), MockFunction::endpoint());
+        let topology = PciTopology::new(0, 0, &config);
+
+        /*
+         * The root port forwards the bridge's Requester ID unchanged, and the bridge uses 00.0 on its secondary
+         * bus for everything behind it.
+         */
+        assert_eq!(topology.dma_aliases(bridge_address), Some(Vec::from([bridge_address])));
+        assert_eq!(
+            topology.dma_aliases(PciAddress::new(0, 2, 3, 0)),
+            Some(Vec::from([PciAddress::new(0, 2, 3, 0), PciAddress::new(0, 2, 0, 0)]))
+        );
+        assert_eq!(
+            topology.dma_aliases(PciAddress::new(0, 2, 0, 0)),
+            Some(Vec::from([PciAddress::new(0, 2, 0, 0)]))
+        );
+        assert_eq!(topology.dma_aliases(PciAddress::new(0, 3, 0, 0)), None);
+    }
+
+    #[test]
+    fn aliases_behind_pci_bridge() {
+        let bridge_address = PciAddress::new(0, 0, 0x1e, 0);
+        let nested_bridge = PciAddress::new(0, 1, 1, 0);
+
+        let mut config = MockConfigSpace::new();
+        config.add_function(bridge_address, MockFunction::bridge(0, 1, 2));
+        config.add_function(PciAddress::new(0, 1, 0, 0), MockFunction::endpoint());
+        config.add_function(nested_bridge, MockFunction::bridge(1, 2, 2));
+        config.add_function(PciAddress::new(0, 2, 0, 0), MockFunction::endpoint());
+        let topology = PciTopology::new(0, 0, &config);
+
+        /*
+         * Conventional PCI bridges use their own Requester ID, so a function behind two of them is aliased to
+         * both.
+         */
+        assert_eq!(
+            topology.dma_aliases(PciAddress::new(0, 1, 0, 0)),
+            Some(Vec::from([PciAddress::new(0, 1, 0, 0), bridge_address]))
+        );
+        assert_eq!(
+            topology.dma_aliases(PciAddress::new(0, 2, 0, 0)),
+            Some(Vec::from([PciAddress::new(0, 2, 0, 0), nested_bridge, bridge_address]))
+        );
+    }
+}